@@ -5,14 +5,19 @@ use spark_jit::arch::x86::Operand;
 use spark_jit::arch::x86::Operand::{Imm64, MemDisp, Reg};
 use spark_jit::arch::x86::Reg64;
 use spark_jit::arch::x86::Reg64::*;
+use spark_jit::arch::EncodeError;
 use spark_jit::executable::Executable;
-use spark_jit::X86Asm;
+use spark_jit::module::LinkError;
+use spark_jit::{x86, Module, X86Asm};
 
 use crate::rpn_converter::RPNExpr;
 
 pub enum CompilerError {
     UnsupportedOp(crate::tokenizer::Op),
     UnknownOp(crate::tokenizer::Op),
+    Encode(EncodeError),
+    Link(LinkError),
+    TooManyArguments(usize),
 }
 
 impl std::fmt::Display for CompilerError {
@@ -20,10 +25,27 @@ impl std::fmt::Display for CompilerError {
         match self {
             CompilerError::UnsupportedOp(op) => write!(f, "Unsupported operation: {:?}", op),
             CompilerError::UnknownOp(op) => write!(f, "Unknown operation: {:?}", op),
+            CompilerError::Encode(err) => write!(f, "Failed to encode instruction: {}", err),
+            CompilerError::Link(err) => write!(f, "Failed to link the code: {}", err),
+            CompilerError::TooManyArguments(count) => {
+                write!(f, "Too many arguments for a native call: {}", count)
+            }
         }
     }
 }
 
+impl From<EncodeError> for CompilerError {
+    fn from(err: EncodeError) -> Self {
+        CompilerError::Encode(err)
+    }
+}
+
+impl From<LinkError> for CompilerError {
+    fn from(err: LinkError) -> Self {
+        CompilerError::Link(err)
+    }
+}
+
 const ARG1: Reg64 = R8;
 const ARG2: Reg64 = R9;
 const VARS_BASE: Reg64 = R13;
@@ -33,6 +55,9 @@ const SCRATCH_REG: Reg64 = R15;
 /// X86-64 calling convention: RDI, RSI, RDX, RCX, R8, R9, ... <stack>
 const SYSTEMV_CALLING_CONV: [Reg64; 6] = [Rdi, Rsi, Rdx, Rcx, R8, R9];
 
+/// Name of the function that evaluates the expression.
+const EXPR_SYMBOL: &str = "expr";
+
 /// A JIT compiler for RPN expressions.
 ///
 /// Given an RPN expression, this compiler generates machine code that evaluates the expression.
@@ -77,21 +102,22 @@ impl Compiler {
     ///
     /// * `codegen` - The code generator.
     /// * `op` - The operand to push onto the stack.
-    fn push_eval_stack(&mut self, codegen: &mut X86Asm, op: Operand) {
+    fn push_eval_stack(&mut self, codegen: &mut X86Asm, op: Operand) -> Result<(), CompilerError> {
         with_integrity!(self, codegen, {
             match op {
                 // Support 64-bit immediate values
                 Imm64(_) => {
-                    codegen.mov(Reg(SCRATCH_REG), op);
-                    codegen.mov(MemDisp(EVAL_STACK, 0), Reg(SCRATCH_REG));
+                    codegen.mov(Reg(SCRATCH_REG), op)?;
+                    x86!(codegen; mov [EVAL_STACK], SCRATCH_REG)?;
                 }
                 _ => {
-                    codegen.mov(MemDisp(EVAL_STACK, 0), op);
+                    codegen.mov(MemDisp(EVAL_STACK, 0), op)?;
                 }
             }
 
-            codegen.add(Reg(EVAL_STACK), Imm64(8));
+            x86!(codegen; add EVAL_STACK, 8)?;
         });
+        Ok(())
     }
 
     /// Pop the top of the evaluation stack into the specified register.
@@ -100,41 +126,38 @@ impl Compiler {
     ///
     /// * `codegen` - The code generator.
     /// * `reg` - The register to pop the value into.
-    fn pop_eval_stack(&mut self, codegen: &mut X86Asm, reg: Reg64) {
+    fn pop_eval_stack(&mut self, codegen: &mut X86Asm, reg: Reg64) -> Result<(), CompilerError> {
         with_integrity!(self, codegen, {
-            codegen.sub(Reg(EVAL_STACK), Imm64(8));
-            codegen.mov(Reg(reg), MemDisp(EVAL_STACK, 0));
+            x86!(codegen;
+                sub EVAL_STACK, 8;
+                mov reg, [EVAL_STACK]
+            )?;
         });
+        Ok(())
     }
 
     /// Compile the prologue of the generated code (save preserved registers).
-    fn compile_prologue(&mut self, codegen: &mut X86Asm) {
+    fn compile_prologue(&mut self, codegen: &mut X86Asm) -> Result<(), CompilerError> {
         // Save registers
         with_integrity!(self, codegen, {
-            codegen.push(Reg(R12));
-            codegen.push(Reg(R13));
-            codegen.push(Reg(R14));
-            codegen.push(Reg(R15));
-            codegen.push(Reg(Rbx));
-            codegen.push(Reg(Rbp));
-            codegen.push(Reg(Rdi));
-            codegen.push(Reg(Rsi));
+            x86!(codegen;
+                push r12; push r13; push r14; push r15;
+                push rbx; push rbp; push rdi; push rsi
+            )?;
         });
+        Ok(())
     }
 
     /// Compile the epilogue of the generated code (restore preserved registers).
-    fn compile_epilogue(&mut self, codegen: &mut X86Asm) {
+    fn compile_epilogue(&mut self, codegen: &mut X86Asm) -> Result<(), CompilerError> {
         // Restore registers
         with_integrity!(self, codegen, {
-            codegen.pop(Reg(Rsi));
-            codegen.pop(Reg(Rdi));
-            codegen.pop(Reg(Rbp));
-            codegen.pop(Reg(Rbx));
-            codegen.pop(Reg(R15));
-            codegen.pop(Reg(R14));
-            codegen.pop(Reg(R13));
-            codegen.pop(Reg(R12));
+            x86!(codegen;
+                pop rsi; pop rdi; pop rbp; pop rbx;
+                pop r15; pop r14; pop r13; pop r12
+            )?;
         });
+        Ok(())
     }
 
     /// Compile a call to a native function. The function must be ABI-compatible
@@ -143,29 +166,31 @@ impl Compiler {
     /// # Arguments
     ///
     /// * `codegen` - The code generator.
-    /// * `func` - Raw pointer to an ABI-compatible function.
+    /// * `func` - Name of an ABI-compatible function linked by [`Compiler::compile`].
     /// * `args` - The arguments to pass to the function.
     ///
-    fn compile_native_call(&mut self, codegen: &mut X86Asm, func: usize, args: &[Operand]) {
+    fn compile_native_call(
+        &mut self,
+        codegen: &mut X86Asm,
+        func: &str,
+        args: &[Operand],
+    ) -> Result<(), CompilerError> {
         if args.len() > SYSTEMV_CALLING_CONV.len() {
-            unimplemented!("Too many arguments for a native call!");
+            return Err(CompilerError::TooManyArguments(args.len()));
         }
 
         with_integrity!(self, codegen, {
             // Move the arguments into the correct registers
             for (i, arg) in args.iter().enumerate() {
-                codegen.mov(Reg(SYSTEMV_CALLING_CONV[i]), *arg);
+                codegen.mov(Reg(SYSTEMV_CALLING_CONV[i]), *arg)?;
             }
-        });
-
-        // Make sure not to hash the function pointer!
-        codegen.mov(Reg(Rax), Imm64(func as i64));
 
-        with_integrity!(self, codegen, {
-            codegen.call(Reg(Rax));
+            // The call is only linked to the function by the module, so the hash
+            // doesn't depend on where the function is.
+            codegen.call_symbol(func)?;
         });
 
-        self.push_eval_stack(codegen, Reg(Rax));
+        self.push_eval_stack(codegen, Reg(Rax))
     }
 
     /// Compile an RPN expression into machine code.
@@ -185,12 +210,14 @@ impl Compiler {
         // We have the base address of our eval stack in RDI
         let mut codegen = X86Asm::new();
 
-        self.compile_prologue(&mut codegen);
+        self.compile_prologue(&mut codegen)?;
 
         // Load arguments into registers
         with_integrity!(self, codegen, {
-            codegen.mov(Reg(EVAL_STACK), Reg(Rdi));
-            codegen.mov(Reg(VARS_BASE), Reg(Rsi));
+            x86!(codegen;
+                mov EVAL_STACK, rdi;
+                mov VARS_BASE, rsi
+            )?;
         });
 
         for token in rpn.iter() {
@@ -203,73 +230,68 @@ impl Compiler {
                         .or_insert_with(|| len);
 
                     with_integrity!(self, codegen, {
-                        codegen.mov(Reg(SCRATCH_REG), Reg(VARS_BASE));
-                        codegen.add(Reg(SCRATCH_REG), Imm64(*offset as i64 * 8));
-                        codegen.mov(Reg(SCRATCH_REG), MemDisp(SCRATCH_REG, 0));
+                        x86!(codegen;
+                            mov SCRATCH_REG, VARS_BASE;
+                            add SCRATCH_REG, (*offset as i32 * 8);
+                            mov SCRATCH_REG, [SCRATCH_REG]
+                        )?;
                     });
-                    self.push_eval_stack(&mut codegen, Reg(SCRATCH_REG));
+                    self.push_eval_stack(&mut codegen, Reg(SCRATCH_REG))?;
                 }
                 Number(n) => {
-                    self.push_eval_stack(&mut codegen, Imm64(*n));
+                    self.push_eval_stack(&mut codegen, Imm64(*n))?;
                 }
                 BinaryOp(op) => {
-                    self.pop_eval_stack(&mut codegen, ARG1);
-                    self.pop_eval_stack(&mut codegen, ARG2);
+                    self.pop_eval_stack(&mut codegen, ARG1)?;
+                    self.pop_eval_stack(&mut codegen, ARG2)?;
 
                     match op {
                         Plus => {
                             with_integrity!(self, codegen, {
-                                codegen.add(Reg(ARG1), Reg(ARG2));
+                                x86!(codegen; add ARG1, ARG2)?;
                             });
-                            self.push_eval_stack(&mut codegen, Reg(ARG1));
+                            self.push_eval_stack(&mut codegen, Reg(ARG1))?;
                         }
                         Minus => {
                             with_integrity!(self, codegen, {
-                                codegen.sub(Reg(ARG2), Reg(ARG1));
+                                x86!(codegen; sub ARG2, ARG1)?;
                             });
-                            self.push_eval_stack(&mut codegen, Reg(ARG2));
+                            self.push_eval_stack(&mut codegen, Reg(ARG2))?;
                         }
                         Mult => {
                             with_integrity!(self, codegen, {
-                                codegen.mov(Reg(Rax), Reg(ARG1));
-                                codegen.imul(Reg(ARG2));
+                                x86!(codegen; mov rax, ARG1; imul ARG2)?;
                             });
-                            self.push_eval_stack(&mut codegen, Reg(Rax));
+                            self.push_eval_stack(&mut codegen, Reg(Rax))?;
                         }
                         Div => {
                             with_integrity!(self, codegen, {
-                                codegen.mov(Reg(Rax), Reg(ARG2));
-                                codegen.cqo();
-                                codegen.idiv(Reg(ARG1));
+                                x86!(codegen; mov rax, ARG2; cqo; idiv ARG1)?;
                             });
-                            self.push_eval_stack(&mut codegen, Reg(Rax));
+                            self.push_eval_stack(&mut codegen, Reg(Rax))?;
+                        }
+                        Pow => {
+                            self.compile_native_call(&mut codegen, "pow", &[Reg(ARG2), Reg(ARG1)])?
                         }
-                        Pow => self.compile_native_call(
-                            &mut codegen,
-                            super::builtins::pow as usize,
-                            &[Reg(ARG2), Reg(ARG1)],
-                        ),
                         _ => return Err(CompilerError::UnknownOp(op.clone())),
                     }
                 }
                 UnaryOp(op) => {
-                    self.pop_eval_stack(&mut codegen, ARG1);
+                    self.pop_eval_stack(&mut codegen, ARG1)?;
 
                     match op {
                         Plus => {
-                            self.push_eval_stack(&mut codegen, Reg(ARG1));
+                            self.push_eval_stack(&mut codegen, Reg(ARG1))?;
                         }
                         Minus => {
                             with_integrity!(self, codegen, {
-                                codegen.neg(Reg(ARG1));
+                                x86!(codegen; neg ARG1)?;
                             });
-                            self.push_eval_stack(&mut codegen, Reg(ARG1));
+                            self.push_eval_stack(&mut codegen, Reg(ARG1))?;
+                        }
+                        Fact => {
+                            self.compile_native_call(&mut codegen, "factorial", &[Reg(ARG1)])?
                         }
-                        Fact => self.compile_native_call(
-                            &mut codegen,
-                            super::builtins::factorial as usize,
-                            &[Reg(ARG1)],
-                        ),
                         _ => return Err(CompilerError::UnknownOp(op.clone())),
                     }
                 }
@@ -278,22 +300,32 @@ impl Compiler {
         }
 
         // The result is on top of the stack.
-        self.pop_eval_stack(&mut codegen, Rax);
+        self.pop_eval_stack(&mut codegen, Rax)?;
 
-        self.compile_epilogue(&mut codegen);
+        self.compile_epilogue(&mut codegen)?;
         with_integrity!(self, codegen, {
-            codegen.ret();
+            x86!(codegen; ret)?;
         });
 
-        // Allocate memory for the code and copy the generated code.
-        let exec = Executable::new(
-            codegen.code(),
+        // println!("Generated expression code:");
+        // print!("{}", codegen.disassemble(0));
+
+        // Link the expression with the builtins it calls.
+        let mut module = Module::new();
+        module.add_function(EXPR_SYMBOL, codegen)?;
+        module.add_extern("pow", super::builtins::pow as *const () as usize)?;
+        module.add_extern(
+            "factorial",
+            super::builtins::factorial as *const () as usize,
+        )?;
+
+        // Allocate memory for the code and copy the linked code.
+        let exec = Executable::from_module(
+            &module,
             self.integrity_hasher.clone().finalize().as_slice(),
             self.variables_map.clone(),
-        );
+        )?;
 
-        // println!("Generated expression code:");
-        // codegen.dump_generated_code(exec.code.as_ref().unwrap().ptr() as u64);
         // println!("Integrity hash: {}", hex::encode(&exec.integrity));
 
         println!("Code loaded at: {:p}", exec.code.as_ref().unwrap().ptr());
@@ -1,5 +1,6 @@
 /// Implements a signal handler for SIGFPE that catches the Zero-Division error and sets the result of the division to 0.
 /// The handler is implemented in such a way that it "skips" the faulting instruction and zeroes the result of the division.
+/// The faulting instruction is looked up in the instruction tables of the live executables.
 ///
 /// # Arguments
 ///
@@ -15,11 +16,18 @@ fn sigfpe_handler(_sig: i32, info: *mut libc::siginfo_t, _ucontext: *mut libc::c
         addr, code
     );
 
-    // Update the context to "skip" the faulting instruction
-    // This is actually, where the bug is introduced. Usually, the `div` instruction
-    // is 2 bytes long, but if its operand is the register of 64-bit size, the instruction is 3 bytes long (+REX prefix).
+    // Update the context to "skip" the faulting instruction, whose length depends on its
+    // operand (e.g. `idiv r64` has a REX prefix). If the fault isn't in generated code,
+    // restore the default action so that the instruction faults again and aborts.
     let ucontext = unsafe { &mut *(_ucontext as *mut libc::ucontext_t) };
-    ucontext.uc_mcontext.gregs[libc::REG_RIP as usize] += 3;
+    let rip = ucontext.uc_mcontext.gregs[libc::REG_RIP as usize];
+    match spark_jit::executable::instruction_at(rip as usize) {
+        Some(insn) => ucontext.uc_mcontext.gregs[libc::REG_RIP as usize] = insn.end as i64,
+        None => {
+            unsafe { libc::signal(libc::SIGFPE, libc::SIG_DFL) };
+            return;
+        }
+    }
 
     // Set the result of the division to 0
     ucontext.uc_mcontext.gregs[libc::REG_RAX as usize] = 0;
@@ -1,3 +1,269 @@
 pub mod aarch64;
 pub mod arm;
 pub mod x86;
+
+use crate::label::Label;
+
+/// Error returned by the assemblers when an instruction can't be encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The instruction has no form that takes the given operands.
+    InvalidOperands {
+        mnemonic: &'static str,
+        operands: String,
+    },
+    /// The immediate doesn't fit into the immediate field of the instruction.
+    ImmediateOutOfRange(i64),
+    /// The register can't be used in this position.
+    UnsupportedRegister(&'static str),
+    /// A reference to the label can't reach it.
+    LabelOutOfRange(Label),
+    /// The label is already bound.
+    LabelAlreadyBound(Label),
+    /// Literals of the constant pool must be 8, 16 or 32 bytes long.
+    InvalidConstant(usize),
+    /// A reference to a constant can't reach the constant pool.
+    ConstantOutOfRange,
+    /// Alignments must be powers of two.
+    InvalidAlignment(usize),
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EncodeError::InvalidOperands { mnemonic, operands } => {
+                write!(f, "Invalid operands for {}: {}", mnemonic, operands)
+            }
+            EncodeError::ImmediateOutOfRange(imm) => {
+                write!(f, "Immediate out of range: {:#x}", imm)
+            }
+            EncodeError::UnsupportedRegister(reason) => {
+                write!(f, "Unsupported register: {}", reason)
+            }
+            EncodeError::LabelOutOfRange(label) => {
+                write!(f, "Label {} is out of range", label.id())
+            }
+            EncodeError::LabelAlreadyBound(label) => {
+                write!(f, "Label {} is already bound", label.id())
+            }
+            EncodeError::InvalidConstant(len) => {
+                write!(f, "Constants must be 8, 16 or 32 bytes long, got {}", len)
+            }
+            EncodeError::ConstantOutOfRange => write!(f, "Constant pool is out of range"),
+            EncodeError::InvalidAlignment(align) => {
+                write!(f, "Alignment must be a power of two, got {}", align)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// Operations shared by every backend, so that a code generator can be written once and
+/// instantiated for several instruction sets.
+///
+/// Registers are the general-purpose registers of the native word size: 64 bits on x86-64
+/// and AArch64, 32 bits on A32 and Thumb-2. Operations take three registers even where the
+/// instruction set only has two-operand forms; such backends emit extra moves as needed.
+pub trait Assembler {
+    /// General-purpose register of the native word size.
+    type Reg: Copy + PartialEq + std::fmt::Debug + 'static;
+
+    /// Registers that hold the first integer arguments of a call, in order, in the
+    /// standard calling convention of the target.
+    const ARG_REGS: &'static [Self::Reg];
+    /// Register that holds the integer result of a call.
+    const RET_REG: Self::Reg;
+
+    /// Create a new label, to be bound with [`Assembler::bind_label`].
+    fn new_label(&mut self) -> Label;
+
+    /// Bind the label to the current offset and patch every pending reference to it.
+    fn bind_label(&mut self, label: Label) -> Result<(), EncodeError>;
+
+    /// Current offset in the code buffer.
+    fn offset(&self) -> usize;
+
+    fn code(&self) -> &[u8];
+
+    /// `dst = imm`
+    fn load_imm(&mut self, dst: Self::Reg, imm: i64) -> Result<(), EncodeError>;
+
+    /// `dst = src`
+    fn mov_reg(&mut self, dst: Self::Reg, src: Self::Reg) -> Result<(), EncodeError>;
+
+    /// `dst = lhs + rhs`
+    fn add_reg(
+        &mut self,
+        dst: Self::Reg,
+        lhs: Self::Reg,
+        rhs: Self::Reg,
+    ) -> Result<(), EncodeError>;
+
+    /// `dst = lhs - rhs`
+    fn sub_reg(
+        &mut self,
+        dst: Self::Reg,
+        lhs: Self::Reg,
+        rhs: Self::Reg,
+    ) -> Result<(), EncodeError>;
+
+    /// `dst = lhs * rhs`, truncated to the word size.
+    fn mul_reg(
+        &mut self,
+        dst: Self::Reg,
+        lhs: Self::Reg,
+        rhs: Self::Reg,
+    ) -> Result<(), EncodeError>;
+
+    /// Load a word: `dst = [base + offset]`
+    fn load(&mut self, dst: Self::Reg, base: Self::Reg, offset: i32) -> Result<(), EncodeError>;
+
+    /// Store a word: `[base + offset] = src`
+    fn store(&mut self, src: Self::Reg, base: Self::Reg, offset: i32) -> Result<(), EncodeError>;
+
+    /// Unconditional jump to the label.
+    fn jump(&mut self, label: Label) -> Result<(), EncodeError>;
+
+    /// Call the function at the label.
+    fn call_label(&mut self, label: Label) -> Result<(), EncodeError>;
+
+    /// Call the function whose address is in the register.
+    fn call_reg(&mut self, target: Self::Reg) -> Result<(), EncodeError>;
+
+    /// Return from the function.
+    fn ret(&mut self) -> Result<(), EncodeError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::aarch64::AArch64Asm;
+    use super::arm::{ArmAsm, Mode};
+    use super::x86::X86Asm;
+    use super::*;
+
+    /// Emit the same code with any backend:
+    ///
+    /// ```text
+    /// f(p, x): y = p[8] + x; x = y - x; p[16] = x; return x * y
+    /// g(p):    f(); p()
+    /// ```
+    fn emit_functions<A: Assembler>(asm: &mut A) -> Result<(), EncodeError> {
+        let (p, x, y) = (A::ARG_REGS[0], A::ARG_REGS[1], A::ARG_REGS[2]);
+        let f = asm.new_label();
+        let end = asm.new_label();
+
+        asm.bind_label(f)?;
+        asm.load(y, p, 8)?;
+        asm.add_reg(y, y, x)?;
+        asm.sub_reg(x, y, x)?;
+        asm.store(x, p, 16)?;
+        asm.mul_reg(A::RET_REG, x, y)?;
+        asm.jump(end)?;
+        asm.load_imm(A::RET_REG, 0x1234_5678)?;
+        asm.bind_label(end)?;
+        asm.ret()?;
+
+        asm.call_label(f)?;
+        asm.call_reg(p)?;
+        asm.ret()
+    }
+
+    #[test]
+    fn test_assembler_x86_64() {
+        let mut codegen = X86Asm::new();
+        emit_functions(&mut codegen).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x48, 0x8b, 0x57, 0x08, // mov rdx, qword ptr [rdi + 8]
+                0x48, 0x01, 0xf2, // add rdx, rsi
+                0x48, 0xf7, 0xde, // neg rsi
+                0x48, 0x01, 0xd6, // add rsi, rdx
+                0x48, 0x89, 0x77, 0x10, // mov qword ptr [rdi + 16], rsi
+                0x48, 0x89, 0xf0, // mov rax, rsi
+                0x48, 0x0f, 0xaf, 0xc2, // imul rax, rdx
+                0xe9, 0x05, 0x00, 0x00, 0x00, // jmp end
+                0xb8, 0x78, 0x56, 0x34, 0x12, // mov eax, 0x12345678
+                0xc3, // end: ret
+                0xe8, 0xd8, 0xff, 0xff, 0xff, // call f
+                0x48, 0xff, 0xd7, // call rdi
+                0xc3, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assembler_aarch64() {
+        let mut codegen = AArch64Asm::new();
+        emit_functions(&mut codegen).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x02, 0x04, 0x40, 0xf9, // ldr x2, [x0, #8]
+                0x42, 0x00, 0x01, 0x8b, // add x2, x2, x1
+                0x41, 0x00, 0x01, 0xcb, // sub x1, x2, x1
+                0x01, 0x08, 0x00, 0xf9, // str x1, [x0, #16]
+                0x20, 0x7c, 0x02, 0x9b, // mul x0, x1, x2
+                0x03, 0x00, 0x00, 0x14, // b end
+                0x00, 0xcf, 0x8a, 0xd2, // movz x0, #0x5678
+                0x80, 0x46, 0xa2, 0xf2, // movk x0, #0x1234, lsl #16
+                0xc0, 0x03, 0x5f, 0xd6, // end: ret
+                0xf7, 0xff, 0xff, 0x97, // bl f
+                0x00, 0x00, 0x3f, 0xd6, // blr x0
+                0xc0, 0x03, 0x5f, 0xd6, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assembler_arm() {
+        let mut codegen = ArmAsm::new();
+        emit_functions(&mut codegen).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x08, 0x20, 0x90, 0xe5, // ldr r2, [r0, #8]
+                0x01, 0x20, 0x82, 0xe0, // add r2, r2, r1
+                0x01, 0x10, 0x42, 0xe0, // sub r1, r2, r1
+                0x10, 0x10, 0x80, 0xe5, // str r1, [r0, #16]
+                0x91, 0x02, 0x00, 0xe0, // mul r0, r1, r2
+                0x01, 0x00, 0x00, 0xea, // b end
+                0x78, 0x06, 0x05, 0xe3, // movw r0, #0x5678
+                0x34, 0x02, 0x41, 0xe3, // movt r0, #0x1234
+                0x1e, 0xff, 0x2f, 0xe1, // end: bx lr
+                0xf5, 0xff, 0xff, 0xeb, // bl f
+                0x30, 0xff, 0x2f, 0xe1, // blx r0
+                0x1e, 0xff, 0x2f, 0xe1, // bx lr
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assembler_thumb() {
+        let mut codegen = ArmAsm::new();
+        codegen.set_mode(Mode::Thumb);
+        emit_functions(&mut codegen).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0xd0, 0xf8, 0x08, 0x20, // ldr.w r2, [r0, #8]
+                0x02, 0xeb, 0x01, 0x02, // add.w r2, r2, r1
+                0xa2, 0xeb, 0x01, 0x01, // sub.w r1, r2, r1
+                0xc0, 0xf8, 0x10, 0x10, // str.w r1, [r0, #16]
+                0x01, 0xfb, 0x02, 0xf0, // mul r0, r1, r2
+                0x00, 0xf0, 0x04, 0xb8, // b.w end
+                0x45, 0xf2, 0x78, 0x60, // movw r0, #0x5678
+                0xc1, 0xf2, 0x34, 0x20, // movt r0, #0x1234
+                0x70, 0x47, // end: bx lr
+                0xff, 0xf7, 0xed, 0xff, // bl f
+                0x80, 0x47, // blx r0
+                0x70, 0x47, // bx lr
+            ]
+        );
+    }
+}
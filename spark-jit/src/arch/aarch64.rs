@@ -0,0 +1,6 @@
+mod aarch64_asm;
+mod instructions;
+mod regs;
+
+pub use aarch64_asm::{AArch64Asm, Cond, Mem, Operand};
+pub use regs::{Reg32, Reg64};
@@ -1,3 +1,303 @@
+use super::regs::{Reg32, Reg64};
+use crate::arch::EncodeError;
+use crate::label::{Fixup, Label, LabelTable};
+use crate::writer::Writer;
+
+/// Assembler for the A64 instruction set. Every instruction is a little-endian 32-bit word.
+#[derive(Default)]
 pub struct AArch64Asm {
-    asm: Asm,
+    pub(super) writer: Writer,
+    pub(super) labels: LabelTable<FixupKind>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operand {
+    Reg(Reg64),
+    Reg32(Reg32),
+    Imm(i64),
+}
+
+/// Addressing modes of loads and stores with an immediate offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Index {
+    /// `[base, #offset]`
+    Offset,
+    /// `[base, #offset]!`
+    Pre,
+    /// `[base], #offset`
+    Post,
+}
+
+/// A memory operand: a 64-bit base register (or SP) and an immediate offset.
+#[derive(Debug, Clone, Copy)]
+pub struct Mem {
+    pub(super) base: Reg64,
+    pub(super) offset: i32,
+    pub(super) index: Index,
+}
+
+impl Mem {
+    /// `[base, #offset]`
+    pub fn new(base: Reg64, offset: i32) -> Self {
+        Self {
+            base,
+            offset,
+            index: Index::Offset,
+        }
+    }
+
+    /// `[base, #offset]!`: the base register is updated before the access.
+    pub fn pre(base: Reg64, offset: i32) -> Self {
+        Self {
+            base,
+            offset,
+            index: Index::Pre,
+        }
+    }
+
+    /// `[base], #offset`: the base register is updated after the access.
+    pub fn post(base: Reg64, offset: i32) -> Self {
+        Self {
+            base,
+            offset,
+            index: Index::Post,
+        }
+    }
+}
+
+/// A general-purpose register of any size, as seen by the encoder.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Gpr {
+    /// Register number (0-31).
+    pub num: u32,
+    /// 64-bit register, encoded in the `sf` bit.
+    pub sf: bool,
+    /// SP or WSP rather than the zero register.
+    pub sp: bool,
+}
+
+impl Operand {
+    pub(super) fn gpr(&self) -> Option<Gpr> {
+        let (num, sf) = match *self {
+            Operand::Reg(reg) => (reg as u32, true),
+            Operand::Reg32(reg) => (reg as u32, false),
+            Operand::Imm(_) => return None,
+        };
+
+        Some(Gpr {
+            num: num & 0x1f,
+            sf,
+            sp: num & 0x20 != 0,
+        })
+    }
+}
+
+/// How an instruction interprets register number 31 in one of its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum R31 {
+    Zr,
+    Sp,
+}
+
+/// Condition codes of `b.cond`, as encoded in its low nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Eq = 0x0,
+    Ne = 0x1,
+    Hs = 0x2,
+    Lo = 0x3,
+    Mi = 0x4,
+    Pl = 0x5,
+    Vs = 0x6,
+    Vc = 0x7,
+    Hi = 0x8,
+    Ls = 0x9,
+    Ge = 0xa,
+    Lt = 0xb,
+    Gt = 0xc,
+    Le = 0xd,
+    Al = 0xe,
+}
+
+#[allow(non_upper_case_globals)]
+impl Cond {
+    pub const Cs: Cond = Cond::Hs;
+    pub const Cc: Cond = Cond::Lo;
+
+    /// Get the opposite condition (e.g. `Ne` for `Eq`). `Al` has no opposite and is
+    /// returned unchanged.
+    pub fn negate(self) -> Cond {
+        use Cond::*;
+
+        match self {
+            Eq => Ne,
+            Ne => Eq,
+            Hs => Lo,
+            Lo => Hs,
+            Mi => Pl,
+            Pl => Mi,
+            Vs => Vc,
+            Vc => Vs,
+            Hi => Ls,
+            Ls => Hi,
+            Ge => Lt,
+            Lt => Ge,
+            Gt => Le,
+            Le => Gt,
+            Al => Al,
+        }
+    }
+}
+
+/// Width of the word offset of a label-relative branch.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum FixupKind {
+    /// `b` and `bl`, bits 0-25.
+    Imm26,
+    /// `b.cond`, `cbz` and `cbnz`, bits 5-23.
+    Imm19,
+}
+
+impl AArch64Asm {
+    pub fn new() -> Self {
+        Self {
+            writer: Writer::new(),
+            labels: LabelTable::default(),
+        }
+    }
+
+    /// Create a new label. The label must be bound with [`AArch64Asm::bind_label`] before
+    /// the code is executed, but it can be referenced by branches at any time.
+    pub fn new_label(&mut self) -> Label {
+        self.labels.create()
+    }
+
+    /// Bind the label to the current offset and patch every pending reference to it.
+    ///
+    /// Fails if the label is already bound or if a branch can't reach the label.
+    pub fn bind_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        let offset = self.offset();
+        for fixup in self.labels.bind(label, offset)? {
+            self.patch_fixup(fixup, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Get the offset the label is bound to, if any.
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels.offset(label)
+    }
+
+    /// Check whether some emitted instructions still reference unbound labels.
+    pub fn has_unbound_references(&self) -> bool {
+        self.labels.has_pending()
+    }
+
+    /// Current offset in the code buffer.
+    pub fn offset(&self) -> usize {
+        self.code().len()
+    }
+
+    pub fn code(&self) -> &[u8] {
+        self.writer.bytes()
+    }
+
+    /// Emit a single instruction word.
+    pub(super) fn emit(&mut self, insn: u32) {
+        self.writer.emit32(insn);
+    }
+
+    /// Emit a branch whose offset field refers to the label. The field is patched right
+    /// away if the label is bound, or once it is.
+    pub(super) fn emit_branch(
+        &mut self,
+        insn: u32,
+        label: Label,
+        kind: FixupKind,
+    ) -> Result<(), EncodeError> {
+        let fixup = Fixup {
+            label,
+            offset: self.offset(),
+            kind,
+        };
+
+        match self.labels.offset(label) {
+            Some(target) => {
+                // Check the range before emitting, so that a failed branch leaves nothing behind.
+                Self::encode_rel(fixup, target)?;
+                self.emit(insn);
+                self.patch_fixup(fixup, target)
+            }
+            None => {
+                self.emit(insn);
+                self.labels.add_pending(fixup);
+                Ok(())
+            }
+        }
+    }
+
+    /// Encode the word offset from the branch to the target into the bits of its field.
+    fn encode_rel(fixup: Fixup<FixupKind>, target: usize) -> Result<u32, EncodeError> {
+        let rel = (target as i64 - fixup.offset as i64) >> 2;
+        let (bits, shift) = match fixup.kind {
+            FixupKind::Imm26 => (26, 0),
+            FixupKind::Imm19 => (19, 5),
+        };
+
+        if !(-(1 << (bits - 1))..(1 << (bits - 1))).contains(&rel) {
+            return Err(EncodeError::LabelOutOfRange(fixup.label));
+        }
+        Ok(((rel as u32) & ((1 << bits) - 1)) << shift)
+    }
+
+    fn patch_fixup(&mut self, fixup: Fixup<FixupKind>, target: usize) -> Result<(), EncodeError> {
+        let field = Self::encode_rel(fixup, target)?;
+        let bytes = &self.code()[fixup.offset..fixup.offset + 4];
+        let insn = u32::from_le_bytes(bytes.try_into().unwrap());
+        self.writer.emit32_at(fixup.offset, insn | field);
+        Ok(())
+    }
+
+    /// Build the error for an operand combination the instruction doesn't support.
+    pub(super) fn invalid(mnemonic: &'static str, operands: &[Operand]) -> EncodeError {
+        EncodeError::InvalidOperands {
+            mnemonic,
+            operands: format!("{:?}", operands),
+        }
+    }
+
+    /// Get the number of a register, given how the field interprets register 31.
+    pub(super) fn reg_num(gpr: Gpr, r31: R31) -> Result<u32, EncodeError> {
+        match (gpr.num, gpr.sp, r31) {
+            (31, true, R31::Zr) => Err(EncodeError::UnsupportedRegister(
+                "the stack pointer can't be used here",
+            )),
+            (31, false, R31::Sp) => Err(EncodeError::UnsupportedRegister(
+                "the zero register can't be used here",
+            )),
+            (num, _, _) => Ok(num),
+        }
+    }
+
+    /// Resolve register operands that must all have the same size and read register 31
+    /// as the zero register. Returns their numbers and the `sf` bit.
+    pub(super) fn zr_regs<const N: usize>(
+        mnemonic: &'static str,
+        operands: [Operand; N],
+    ) -> Result<([u32; N], u32), EncodeError> {
+        let mut nums = [0; N];
+        let mut sf = None;
+
+        for (num, op) in nums.iter_mut().zip(operands) {
+            let Some(gpr) = op.gpr() else {
+                return Err(Self::invalid(mnemonic, &operands));
+            };
+            if *sf.get_or_insert(gpr.sf) != gpr.sf {
+                return Err(Self::invalid(mnemonic, &operands));
+            }
+            *num = Self::reg_num(gpr, R31::Zr)?;
+        }
+
+        Ok((nums, sf.unwrap_or(true) as u32))
+    }
 }
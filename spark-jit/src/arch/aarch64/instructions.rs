@@ -0,0 +1,763 @@
+use super::aarch64_asm::{FixupKind, Index, R31};
+use super::{AArch64Asm, Cond, Mem, Operand, Reg64};
+use crate::arch::{Assembler, EncodeError};
+use crate::label::Label;
+
+impl AArch64Asm {
+    /// Emit `add`, `sub` or their flag-setting forms, given the `op` (bit 30) and `S`
+    /// (bit 29) bits. Immediates are 12-bit, optionally shifted left by 12. A negative
+    /// immediate is encoded with the opposite operation, e.g. `add x0, x1, #-1` is
+    /// `sub x0, x1, #1`.
+    ///
+    /// Register forms that involve SP use the extended register encoding.
+    fn emit_add_sub(
+        &mut self,
+        mnemonic: &'static str,
+        sub: bool,
+        set_flags: bool,
+        operands: [Operand; 3],
+    ) -> Result<(), EncodeError> {
+        let [dst, src1, src2] = operands;
+        let (Some(rd), Some(rn)) = (dst.gpr(), src1.gpr()) else {
+            return Err(Self::invalid(mnemonic, &operands));
+        };
+        if rd.sf != rn.sf {
+            return Err(Self::invalid(mnemonic, &operands));
+        }
+
+        // The flag-setting forms discard the result into the zero register instead of SP.
+        let rd_r31 = if set_flags { R31::Zr } else { R31::Sp };
+        let sf = rd.sf as u32;
+        let s = set_flags as u32;
+
+        match src2 {
+            Operand::Imm(imm) => {
+                let (sub, abs) = match imm < 0 {
+                    true => (!sub, imm.unsigned_abs()),
+                    false => (sub, imm as u64),
+                };
+                let (sh, imm12) = match abs {
+                    0..=0xfff => (0, abs as u32),
+                    _ if abs & 0xfff == 0 && abs >> 12 <= 0xfff => (1, (abs >> 12) as u32),
+                    _ => return Err(EncodeError::ImmediateOutOfRange(imm)),
+                };
+                let rd = Self::reg_num(rd, rd_r31)?;
+                let rn = Self::reg_num(rn, R31::Sp)?;
+
+                self.emit(
+                    (sf << 31)
+                        | ((sub as u32) << 30)
+                        | (s << 29)
+                        | 0x1100_0000
+                        | (sh << 22)
+                        | (imm12 << 10)
+                        | (rn << 5)
+                        | rd,
+                );
+            }
+            _ if rd.sp || rn.sp => {
+                // UXTX (or UXTW for 32-bit registers) with no shift is a plain register.
+                let option = if rd.sf { 0b011 } else { 0b010 };
+                let rd = Self::reg_num(rd, rd_r31)?;
+                let rn = Self::reg_num(rn, R31::Sp)?;
+                let ([rm], rm_sf) = Self::zr_regs(mnemonic, [src2])?;
+                if rm_sf != sf {
+                    return Err(Self::invalid(mnemonic, &operands));
+                }
+
+                self.emit(
+                    (sf << 31)
+                        | ((sub as u32) << 30)
+                        | (s << 29)
+                        | 0x0b20_0000
+                        | (rm << 16)
+                        | (option << 13)
+                        | (rn << 5)
+                        | rd,
+                );
+            }
+            _ => {
+                let ([rd, rn, rm], sf) = Self::zr_regs(mnemonic, operands)?;
+                self.emit(
+                    (sf << 31)
+                        | ((sub as u32) << 30)
+                        | (s << 29)
+                        | 0x0b00_0000
+                        | (rm << 16)
+                        | (rn << 5)
+                        | rd,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_add_sub("add", false, false, [dst, src1, src2])
+    }
+
+    pub fn sub(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_add_sub("sub", true, false, [dst, src1, src2])
+    }
+
+    /// Compare a register with a register or an immediate, an alias of `subs` that
+    /// discards the result.
+    pub fn cmp(&mut self, lhs: Operand, rhs: Operand) -> Result<(), EncodeError> {
+        let zr = match lhs {
+            Operand::Reg32(_) => Operand::Reg32(super::Reg32::Wzr),
+            _ => Operand::Reg(super::Reg64::Xzr),
+        };
+        self.emit_add_sub("cmp", true, true, [zr, lhs, rhs])
+    }
+
+    /// Negate a register, an alias of `sub dst, zr, src`.
+    pub fn neg(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let ([rd, rm], sf) = Self::zr_regs("neg", [dst, src])?;
+        self.emit((sf << 31) | 0x4b00_03e0 | (rm << 16) | rd);
+        Ok(())
+    }
+
+    /// Multiply two registers, an alias of `madd dst, src1, src2, zr`.
+    pub fn mul(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        let ([rd, rn, rm], sf) = Self::zr_regs("mul", [dst, src1, src2])?;
+        self.emit((sf << 31) | 0x1b00_7c00 | (rm << 16) | (rn << 5) | rd);
+        Ok(())
+    }
+
+    /// Signed division, rounding towards zero. Dividing by zero yields zero instead
+    /// of trapping.
+    pub fn sdiv(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        let ([rd, rn, rm], sf) = Self::zr_regs("sdiv", [dst, src1, src2])?;
+        self.emit((sf << 31) | 0x1ac0_0c00 | (rm << 16) | (rn << 5) | rd);
+        Ok(())
+    }
+
+    /// Unsigned division. Dividing by zero yields zero instead of trapping.
+    pub fn udiv(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        let ([rd, rn, rm], sf) = Self::zr_regs("udiv", [dst, src1, src2])?;
+        self.emit((sf << 31) | 0x1ac0_0800 | (rm << 16) | (rn << 5) | rd);
+        Ok(())
+    }
+
+    /// Move a register or an immediate into a register.
+    ///
+    /// Immediates are materialized with a `movz` or `movn` followed by a `movk` for each
+    /// remaining halfword, whichever takes fewer instructions. 32-bit registers take
+    /// immediates that fit into 32 bits, either signed or unsigned.
+    pub fn mov(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        match (dst.gpr(), src) {
+            (Some(rd), Operand::Imm(imm)) => {
+                let rd_num = Self::reg_num(rd, R31::Zr)?;
+                let value = match rd.sf {
+                    true => imm as u64,
+                    false if i32::try_from(imm).is_ok() || u32::try_from(imm).is_ok() => {
+                        imm as u32 as u64
+                    }
+                    false => return Err(EncodeError::ImmediateOutOfRange(imm)),
+                };
+
+                let halfwords = if rd.sf { 4 } else { 2 };
+                let halfword = |hw: u32| (value >> (hw * 16)) as u16;
+                let count = |pattern| (0..halfwords).filter(|&hw| halfword(hw) == pattern).count();
+
+                // Halfwords that already match the initial value don't need a `movk`.
+                let (initial, opc) = match count(0xffff) > count(0) {
+                    true => (0xffff, 0b00),
+                    false => (0, 0b10),
+                };
+                let first = (0..halfwords)
+                    .find(|&hw| halfword(hw) != initial)
+                    .unwrap_or(0);
+
+                // `movn` writes the inverse of its immediate.
+                let imm16 = halfword(first) ^ initial;
+                self.emit_mov_wide(opc, rd.sf, rd_num, imm16, first);
+                for hw in first + 1..halfwords {
+                    if halfword(hw) != initial {
+                        self.emit_mov_wide(0b11, rd.sf, rd_num, halfword(hw), hw);
+                    }
+                }
+                Ok(())
+            }
+            // mov to or from SP is an alias of `add dst, src, #0`.
+            (Some(rd), _) if rd.sp || src.gpr().is_some_and(|rn| rn.sp) => {
+                self.emit_add_sub("mov", false, false, [dst, src, Operand::Imm(0)])
+            }
+            // mov between registers is an alias of `orr dst, zr, src`.
+            _ => {
+                let ([rd, rm], sf) = Self::zr_regs("mov", [dst, src])?;
+                self.emit((sf << 31) | 0x2a00_03e0 | (rm << 16) | rd);
+                Ok(())
+            }
+        }
+    }
+
+    /// Emit `movn`, `movz` or `movk` given their `opc` field, the already validated
+    /// destination register and the halfword index.
+    fn emit_mov_wide(&mut self, opc: u32, sf: bool, rd: u32, imm16: u16, hw: u32) {
+        self.emit(
+            ((sf as u32) << 31)
+                | (opc << 29)
+                | 0x1280_0000
+                | (hw << 21)
+                | ((imm16 as u32) << 5)
+                | rd,
+        );
+    }
+
+    /// Validate the destination and the shift of `movn`, `movz` and `movk`, and emit it.
+    /// The shift must be a multiple of 16 within the register.
+    fn emit_mov_wide_checked(
+        &mut self,
+        mnemonic: &'static str,
+        opc: u32,
+        dst: Operand,
+        imm16: u16,
+        shift: u32,
+    ) -> Result<(), EncodeError> {
+        let Some(rd) = dst.gpr() else {
+            return Err(Self::invalid(mnemonic, &[dst]));
+        };
+        let rd_num = Self::reg_num(rd, R31::Zr)?;
+
+        let max_shift = if rd.sf { 48 } else { 16 };
+        if !shift.is_multiple_of(16) || shift > max_shift {
+            return Err(EncodeError::ImmediateOutOfRange(shift as i64));
+        }
+
+        self.emit_mov_wide(opc, rd.sf, rd_num, imm16, shift / 16);
+        Ok(())
+    }
+
+    /// Move `imm16 << shift` into a register, zeroing the other bits.
+    pub fn movz(&mut self, dst: Operand, imm16: u16, shift: u32) -> Result<(), EncodeError> {
+        self.emit_mov_wide_checked("movz", 0b10, dst, imm16, shift)
+    }
+
+    /// Move the inverse of `imm16 << shift` into a register.
+    pub fn movn(&mut self, dst: Operand, imm16: u16, shift: u32) -> Result<(), EncodeError> {
+        self.emit_mov_wide_checked("movn", 0b00, dst, imm16, shift)
+    }
+
+    /// Replace the halfword at `shift` of a register, keeping the other bits.
+    pub fn movk(&mut self, dst: Operand, imm16: u16, shift: u32) -> Result<(), EncodeError> {
+        self.emit_mov_wide_checked("movk", 0b11, dst, imm16, shift)
+    }
+
+    /// Resolve the base register of a memory operand, which reads register 31 as SP.
+    fn mem_base(mem: Mem) -> Result<u32, EncodeError> {
+        match Operand::Reg(mem.base).gpr() {
+            Some(gpr) => Self::reg_num(gpr, R31::Sp),
+            None => unreachable!(),
+        }
+    }
+
+    /// Emit `ldr` or `str` of a 32 or 64-bit register. Offsets that are a multiple of the
+    /// access size use the scaled 12-bit form, other offsets the unscaled 9-bit one.
+    fn emit_load_store(
+        &mut self,
+        mnemonic: &'static str,
+        load: bool,
+        rt: Operand,
+        mem: Mem,
+    ) -> Result<(), EncodeError> {
+        let ([rt], sf) = Self::zr_regs(mnemonic, [rt])?;
+        let rn = Self::mem_base(mem)?;
+
+        let size = 4 << sf;
+        let offset = mem.offset as i64;
+        let simm9 = |offset: i64| match (-256..256).contains(&offset) {
+            true => Ok(((offset as u32) & 0x1ff) << 12),
+            false => Err(EncodeError::ImmediateOutOfRange(offset)),
+        };
+
+        let base = (sf << 30) | 0xb800_0000 | ((load as u32) << 22) | (rn << 5) | rt;
+        let insn = match mem.index {
+            Index::Offset if offset >= 0 && offset % size == 0 && offset / size <= 0xfff => {
+                base | 0x0100_0000 | (((offset / size) as u32) << 10)
+            }
+            Index::Offset => base | simm9(offset)?,
+            Index::Pre => base | simm9(offset)? | (0b11 << 10),
+            Index::Post => base | simm9(offset)? | (0b01 << 10),
+        };
+
+        self.emit(insn);
+        Ok(())
+    }
+
+    /// Load a 32 or 64-bit register from memory.
+    pub fn ldr(&mut self, dst: Operand, src: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store("ldr", true, dst, src)
+    }
+
+    /// Store a 32 or 64-bit register to memory.
+    pub fn str(&mut self, src: Operand, dst: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store("str", false, src, dst)
+    }
+
+    /// Emit `ldp` or `stp`. The offset is a signed 7-bit multiple of the register size.
+    fn emit_load_store_pair(
+        &mut self,
+        mnemonic: &'static str,
+        load: bool,
+        rt1: Operand,
+        rt2: Operand,
+        mem: Mem,
+    ) -> Result<(), EncodeError> {
+        let ([rt1, rt2], sf) = Self::zr_regs(mnemonic, [rt1, rt2])?;
+        let rn = Self::mem_base(mem)?;
+
+        let size = 4 << sf;
+        let offset = mem.offset as i64;
+        if offset % size != 0 || !(-64..64).contains(&(offset / size)) {
+            return Err(EncodeError::ImmediateOutOfRange(offset));
+        }
+        let imm7 = ((offset / size) as u32) & 0x7f;
+
+        let index = match mem.index {
+            Index::Post => 0b01,
+            Index::Offset => 0b10,
+            Index::Pre => 0b11,
+        };
+
+        self.emit(
+            (sf << 31)
+                | 0x2800_0000
+                | (index << 23)
+                | ((load as u32) << 22)
+                | (imm7 << 15)
+                | (rt2 << 10)
+                | (rn << 5)
+                | rt1,
+        );
+        Ok(())
+    }
+
+    /// Load a pair of 32 or 64-bit registers from consecutive memory locations.
+    pub fn ldp(&mut self, dst1: Operand, dst2: Operand, src: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store_pair("ldp", true, dst1, dst2, src)
+    }
+
+    /// Store a pair of 32 or 64-bit registers to consecutive memory locations.
+    pub fn stp(&mut self, src1: Operand, src2: Operand, dst: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store_pair("stp", false, src1, src2, dst)
+    }
+
+    /// Branch to a label within +-128MiB.
+    pub fn b(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.emit_branch(0x1400_0000, label, FixupKind::Imm26)
+    }
+
+    /// Call a label within +-128MiB, saving the return address in the link register.
+    pub fn bl(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.emit_branch(0x9400_0000, label, FixupKind::Imm26)
+    }
+
+    /// Branch to a label within +-1MiB if the condition holds.
+    pub fn b_cond(&mut self, cond: Cond, label: Label) -> Result<(), EncodeError> {
+        self.emit_branch(0x5400_0000 | cond as u32, label, FixupKind::Imm19)
+    }
+
+    /// Branch to a label within +-1MiB if the register is zero.
+    pub fn cbz(&mut self, src: Operand, label: Label) -> Result<(), EncodeError> {
+        let ([rt], sf) = Self::zr_regs("cbz", [src])?;
+        self.emit_branch((sf << 31) | 0x3400_0000 | rt, label, FixupKind::Imm19)
+    }
+
+    /// Branch to a label within +-1MiB if the register is not zero.
+    pub fn cbnz(&mut self, src: Operand, label: Label) -> Result<(), EncodeError> {
+        let ([rt], sf) = Self::zr_regs("cbnz", [src])?;
+        self.emit_branch((sf << 31) | 0x3500_0000 | rt, label, FixupKind::Imm19)
+    }
+
+    /// Emit a branch to the address held by a 64-bit register.
+    fn emit_branch_reg(
+        &mut self,
+        mnemonic: &'static str,
+        opcode: u32,
+        target: Operand,
+    ) -> Result<(), EncodeError> {
+        match Self::zr_regs(mnemonic, [target])? {
+            ([rn], 1) => {
+                self.emit(opcode | (rn << 5));
+                Ok(())
+            }
+            _ => Err(Self::invalid(mnemonic, &[target])),
+        }
+    }
+
+    /// Branch to the address held by a register.
+    pub fn br(&mut self, target: Operand) -> Result<(), EncodeError> {
+        self.emit_branch_reg("br", 0xd61f_0000, target)
+    }
+
+    /// Call the address held by a register, saving the return address in the link register.
+    pub fn blr(&mut self, target: Operand) -> Result<(), EncodeError> {
+        self.emit_branch_reg("blr", 0xd63f_0000, target)
+    }
+
+    /// Return to the address held by the link register.
+    pub fn ret(&mut self) -> Result<(), EncodeError> {
+        self.emit_branch_reg("ret", 0xd65f_0000, Operand::Reg(super::Reg64::Lr))
+    }
+}
+
+impl Assembler for AArch64Asm {
+    type Reg = Reg64;
+
+    /// The AAPCS64 calling convention.
+    const ARG_REGS: &'static [Reg64] = &[
+        Reg64::X0,
+        Reg64::X1,
+        Reg64::X2,
+        Reg64::X3,
+        Reg64::X4,
+        Reg64::X5,
+        Reg64::X6,
+        Reg64::X7,
+    ];
+    const RET_REG: Reg64 = Reg64::X0;
+
+    fn new_label(&mut self) -> Label {
+        AArch64Asm::new_label(self)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        AArch64Asm::bind_label(self, label)
+    }
+
+    fn offset(&self) -> usize {
+        AArch64Asm::offset(self)
+    }
+
+    fn code(&self) -> &[u8] {
+        AArch64Asm::code(self)
+    }
+
+    fn load_imm(&mut self, dst: Reg64, imm: i64) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::Imm(imm))
+    }
+
+    fn mov_reg(&mut self, dst: Reg64, src: Reg64) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::Reg(src))
+    }
+
+    fn add_reg(&mut self, dst: Reg64, lhs: Reg64, rhs: Reg64) -> Result<(), EncodeError> {
+        self.add(Operand::Reg(dst), Operand::Reg(lhs), Operand::Reg(rhs))
+    }
+
+    fn sub_reg(&mut self, dst: Reg64, lhs: Reg64, rhs: Reg64) -> Result<(), EncodeError> {
+        self.sub(Operand::Reg(dst), Operand::Reg(lhs), Operand::Reg(rhs))
+    }
+
+    fn mul_reg(&mut self, dst: Reg64, lhs: Reg64, rhs: Reg64) -> Result<(), EncodeError> {
+        self.mul(Operand::Reg(dst), Operand::Reg(lhs), Operand::Reg(rhs))
+    }
+
+    fn load(&mut self, dst: Reg64, base: Reg64, offset: i32) -> Result<(), EncodeError> {
+        self.ldr(Operand::Reg(dst), Mem::new(base, offset))
+    }
+
+    fn store(&mut self, src: Reg64, base: Reg64, offset: i32) -> Result<(), EncodeError> {
+        self.str(Operand::Reg(src), Mem::new(base, offset))
+    }
+
+    fn jump(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.b(label)
+    }
+
+    fn call_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.bl(label)
+    }
+
+    fn call_reg(&mut self, target: Reg64) -> Result<(), EncodeError> {
+        self.blr(Operand::Reg(target))
+    }
+
+    fn ret(&mut self) -> Result<(), EncodeError> {
+        AArch64Asm::ret(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Reg32::*, Reg64, Reg64::*};
+    use super::*;
+    use Operand::*;
+
+    #[test]
+    fn test_aarch64_codegen_add_sub() {
+        let mut codegen = AArch64Asm::new();
+        codegen.add(Reg(X0), Reg(X1), Reg(X2)).unwrap();
+        codegen.add(Reg32(W3), Reg32(W4), Reg32(W5)).unwrap();
+        codegen.add(Reg(X0), Reg(X1), Imm(0xfff)).unwrap();
+        codegen.add(Reg(X0), Reg(X1), Imm(0x1000)).unwrap();
+        codegen.add(Reg(Sp), Reg(Sp), Imm(16)).unwrap();
+        codegen.add(Reg(X0), Reg(Sp), Reg(X1)).unwrap();
+        codegen.add(Reg(X0), Reg(X1), Imm(-5)).unwrap();
+        codegen.sub(Reg(X29), Reg(X30), Reg(Xzr)).unwrap();
+        codegen.sub(Reg(Sp), Reg(Sp), Imm(0x20)).unwrap();
+        codegen.sub(Reg32(W0), Reg32(W1), Imm(0x123000)).unwrap();
+        codegen.cmp(Reg(X0), Imm(42)).unwrap();
+        codegen.cmp(Reg(X0), Imm(-1)).unwrap();
+        codegen.cmp(Reg32(W1), Reg32(W2)).unwrap();
+        codegen.neg(Reg(X0), Reg(X1)).unwrap();
+        codegen.neg(Reg32(W2), Reg32(W3)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x20, 0x00, 0x02, 0x8b, // add x0, x1, x2
+                0x83, 0x00, 0x05, 0x0b, // add w3, w4, w5
+                0x20, 0xfc, 0x3f, 0x91, // add x0, x1, #0xfff
+                0x20, 0x04, 0x40, 0x91, // add x0, x1, #1, lsl #12
+                0xff, 0x43, 0x00, 0x91, // add sp, sp, #16
+                0xe0, 0x63, 0x21, 0x8b, // add x0, sp, x1
+                0x20, 0x14, 0x00, 0xd1, // sub x0, x1, #5
+                0xdd, 0x03, 0x1f, 0xcb, // sub x29, x30, xzr
+                0xff, 0x83, 0x00, 0xd1, // sub sp, sp, #0x20
+                0x20, 0x8c, 0x44, 0x51, // sub w0, w1, #0x123, lsl #12
+                0x1f, 0xa8, 0x00, 0xf1, // cmp x0, #42
+                0x1f, 0x04, 0x00, 0xb1, // cmn x0, #1
+                0x3f, 0x00, 0x02, 0x6b, // cmp w1, w2
+                0xe0, 0x03, 0x01, 0xcb, // neg x0, x1
+                0xe2, 0x03, 0x03, 0x4b, // neg w2, w3
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_codegen_mul_div() {
+        let mut codegen = AArch64Asm::new();
+        codegen.mul(Reg(X0), Reg(X1), Reg(X2)).unwrap();
+        codegen.mul(Reg32(W3), Reg32(W4), Reg32(W5)).unwrap();
+        codegen.sdiv(Reg(X6), Reg(X7), Reg(X8)).unwrap();
+        codegen.sdiv(Reg32(W9), Reg32(W10), Reg32(W11)).unwrap();
+        codegen.udiv(Reg(X12), Reg(X13), Reg(X14)).unwrap();
+        codegen.udiv(Reg32(W15), Reg32(W16), Reg32(W17)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x20, 0x7c, 0x02, 0x9b, // mul x0, x1, x2
+                0x83, 0x7c, 0x05, 0x1b, // mul w3, w4, w5
+                0xe6, 0x0c, 0xc8, 0x9a, // sdiv x6, x7, x8
+                0x49, 0x0d, 0xcb, 0x1a, // sdiv w9, w10, w11
+                0xac, 0x09, 0xce, 0x9a, // udiv x12, x13, x14
+                0x0f, 0x0a, 0xd1, 0x1a, // udiv w15, w16, w17
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_codegen_mov() {
+        let mut codegen = AArch64Asm::new();
+        codegen.mov(Reg(X0), Reg(X1)).unwrap();
+        codegen.mov(Reg32(W2), Reg32(W3)).unwrap();
+        codegen.mov(Reg(X29), Reg(Sp)).unwrap();
+        codegen.mov(Reg(Sp), Reg(X29)).unwrap();
+        codegen.mov(Reg(X0), Imm(0)).unwrap();
+        codegen.mov(Reg(X0), Imm(0x1234)).unwrap();
+        codegen.mov(Reg(X1), Imm(0x1234_0000)).unwrap();
+        codegen.mov(Reg(X2), Imm(-1)).unwrap();
+        codegen.mov(Reg(X3), Imm(-0x1235)).unwrap();
+        codegen.mov(Reg32(W4), Imm(-1)).unwrap();
+        codegen.mov(Reg32(W5), Imm(0xffff_0000)).unwrap();
+        codegen.mov(Reg(X6), Imm(0x1234_5678_9abc_def0)).unwrap();
+        codegen
+            .mov(Reg(X7), Imm(0xffff_ffff_0000_1234_u64 as i64))
+            .unwrap();
+        codegen.mov(Reg(X8), Imm(0x1_0000_0001)).unwrap();
+        codegen.movz(Reg(X9), 0xbeef, 32).unwrap();
+        codegen.movk(Reg(X9), 0xdead, 48).unwrap();
+        codegen.movn(Reg32(W10), 0x1, 16).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0xe0, 0x03, 0x01, 0xaa, // mov x0, x1
+                0xe2, 0x03, 0x03, 0x2a, // mov w2, w3
+                0xfd, 0x03, 0x00, 0x91, // mov x29, sp
+                0xbf, 0x03, 0x00, 0x91, // mov sp, x29
+                0x00, 0x00, 0x80, 0xd2, // movz x0, #0
+                0x80, 0x46, 0x82, 0xd2, // movz x0, #0x1234
+                0x81, 0x46, 0xa2, 0xd2, // movz x1, #0x1234, lsl #16
+                0x02, 0x00, 0x80, 0x92, // movn x2, #0
+                0x83, 0x46, 0x82, 0x92, // movn x3, #0x1234
+                0x04, 0x00, 0x80, 0x12, // movn w4, #0
+                0xe5, 0xff, 0xbf, 0x52, // movz w5, #0xffff, lsl #16
+                0x06, 0xde, 0x9b, 0xd2, // movz x6, #0xdef0
+                0x86, 0x57, 0xb3, 0xf2, // movk x6, #0x9abc, lsl #16
+                0x06, 0xcf, 0xca, 0xf2, // movk x6, #0x5678, lsl #32
+                0x86, 0x46, 0xe2, 0xf2, // movk x6, #0x1234, lsl #48
+                0x67, 0xb9, 0x9d, 0x92, // movn x7, #0xedcb
+                0x07, 0x00, 0xa0, 0xf2, // movk x7, #0, lsl #16
+                0x28, 0x00, 0x80, 0xd2, // movz x8, #1
+                0x28, 0x00, 0xc0, 0xf2, // movk x8, #1, lsl #32
+                0xe9, 0xdd, 0xd7, 0xd2, // movz x9, #0xbeef, lsl #32
+                0xa9, 0xd5, 0xfb, 0xf2, // movk x9, #0xdead, lsl #48
+                0x2a, 0x00, 0xa0, 0x12, // movn w10, #1, lsl #16
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_codegen_ldr_str() {
+        let mut codegen = AArch64Asm::new();
+        codegen.ldr(Reg(X0), Mem::new(X1, 0)).unwrap();
+        codegen.ldr(Reg(X2), Mem::new(Sp, 0x7ff8)).unwrap();
+        codegen.ldr(Reg32(W3), Mem::new(X4, 4)).unwrap();
+        codegen.ldr(Reg(X5), Mem::new(X6, -8)).unwrap();
+        codegen.ldr(Reg(X7), Mem::new(X8, 3)).unwrap();
+        codegen.ldr(Reg(X9), Mem::pre(X10, 16)).unwrap();
+        codegen.ldr(Reg32(W11), Mem::post(X12, -4)).unwrap();
+        codegen.str(Reg(X0), Mem::new(X1, 8)).unwrap();
+        codegen.str(Reg(Xzr), Mem::new(Sp, 0)).unwrap();
+        codegen.str(Reg32(W2), Mem::new(X3, 0x3ffc)).unwrap();
+        codegen.str(Reg(X4), Mem::pre(Sp, -16)).unwrap();
+        codegen.str(Reg(X5), Mem::post(X6, 255)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x20, 0x00, 0x40, 0xf9, // ldr x0, [x1]
+                0xe2, 0xff, 0x7f, 0xf9, // ldr x2, [sp, #0x7ff8]
+                0x83, 0x04, 0x40, 0xb9, // ldr w3, [x4, #4]
+                0xc5, 0x80, 0x5f, 0xf8, // ldur x5, [x6, #-8]
+                0x07, 0x31, 0x40, 0xf8, // ldur x7, [x8, #3]
+                0x49, 0x0d, 0x41, 0xf8, // ldr x9, [x10, #16]!
+                0x8b, 0xc5, 0x5f, 0xb8, // ldr w11, [x12], #-4
+                0x20, 0x04, 0x00, 0xf9, // str x0, [x1, #8]
+                0xff, 0x03, 0x00, 0xf9, // str xzr, [sp]
+                0x62, 0xfc, 0x3f, 0xb9, // str w2, [x3, #0x3ffc]
+                0xe4, 0x0f, 0x1f, 0xf8, // str x4, [sp, #-16]!
+                0xc5, 0xf4, 0x0f, 0xf8, // str x5, [x6], #255
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_codegen_ldp_stp() {
+        let mut codegen = AArch64Asm::new();
+        codegen
+            .stp(Reg(Reg64::Fp), Reg(Reg64::Lr), Mem::pre(Sp, -16))
+            .unwrap();
+        codegen.stp(Reg(X19), Reg(X20), Mem::new(Sp, 16)).unwrap();
+        codegen
+            .stp(Reg32(W0), Reg32(W1), Mem::new(X2, -256))
+            .unwrap();
+        codegen.ldp(Reg(X19), Reg(X20), Mem::new(Sp, 16)).unwrap();
+        codegen
+            .ldp(Reg(Reg64::Fp), Reg(Reg64::Lr), Mem::post(Sp, 16))
+            .unwrap();
+        codegen
+            .ldp(Reg32(W3), Reg32(W4), Mem::pre(X5, 252))
+            .unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0xfd, 0x7b, 0xbf, 0xa9, // stp x29, x30, [sp, #-16]!
+                0xf3, 0x53, 0x01, 0xa9, // stp x19, x20, [sp, #16]
+                0x40, 0x04, 0x20, 0x29, // stp w0, w1, [x2, #-256]
+                0xf3, 0x53, 0x41, 0xa9, // ldp x19, x20, [sp, #16]
+                0xfd, 0x7b, 0xc1, 0xa8, // ldp x29, x30, [sp], #16
+                0xa3, 0x90, 0xdf, 0x29, // ldp w3, w4, [x5, #252]!
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_codegen_branches() {
+        let mut codegen = AArch64Asm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+
+        codegen.bind_label(start).unwrap();
+        codegen.cbz(Reg(X0), end).unwrap();
+        codegen.cbnz(Reg32(W1), start).unwrap();
+        codegen.b_cond(Cond::Eq, end).unwrap();
+        codegen.b_cond(Cond::Lt.negate(), start).unwrap();
+        codegen.bl(end).unwrap();
+        codegen.b(start).unwrap();
+        codegen.blr(Reg(X16)).unwrap();
+        codegen.br(Reg(X17)).unwrap();
+        codegen.bind_label(end).unwrap();
+        codegen.ret().unwrap();
+
+        assert!(!codegen.has_unbound_references());
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x00, 0x01, 0x00, 0xb4, // cbz x0, end
+                0xe1, 0xff, 0xff, 0x35, // cbnz w1, start
+                0xc0, 0x00, 0x00, 0x54, // b.eq end
+                0xaa, 0xff, 0xff, 0x54, // b.ge start
+                0x04, 0x00, 0x00, 0x94, // bl end
+                0xfb, 0xff, 0xff, 0x17, // b start
+                0x00, 0x02, 0x3f, 0xd6, // blr x16
+                0x20, 0x02, 0x1f, 0xd6, // br x17
+                0xc0, 0x03, 0x5f, 0xd6, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_codegen_invalid_operands() {
+        let mut codegen = AArch64Asm::new();
+
+        // Mixed register sizes.
+        assert!(matches!(
+            codegen.add(Reg(X0), Reg32(W1), Reg(X2)),
+            Err(EncodeError::InvalidOperands { .. })
+        ));
+        assert!(codegen.mul(Reg(X0), Reg(X1), Reg32(W2)).is_err());
+        assert!(codegen.stp(Reg(X0), Reg32(W1), Mem::new(Sp, 0)).is_err());
+        // SP and the zero register where the other one is expected.
+        assert!(matches!(
+            codegen.mul(Reg(Sp), Reg(X1), Reg(X2)),
+            Err(EncodeError::UnsupportedRegister(_))
+        ));
+        assert!(codegen.ldr(Reg(X0), Mem::new(Xzr, 0)).is_err());
+        assert!(codegen.cmp(Reg(Sp), Reg(Sp)).is_err());
+        assert!(codegen.blr(Reg32(W0)).is_err());
+        // Immediates that don't fit.
+        assert_eq!(
+            codegen.add(Reg(X0), Reg(X1), Imm(0x1001)),
+            Err(EncodeError::ImmediateOutOfRange(0x1001))
+        );
+        assert!(codegen.mov(Reg32(W0), Imm(0x1_0000_0000)).is_err());
+        assert!(codegen.movk(Reg32(W0), 1, 32).is_err());
+        assert!(codegen.movz(Reg(X0), 1, 8).is_err());
+        assert!(codegen.ldr(Reg(X0), Mem::new(X1, 0x8000)).is_err());
+        assert!(codegen.ldr(Reg(X0), Mem::pre(X1, 256)).is_err());
+        assert!(codegen.ldp(Reg(X0), Reg(X1), Mem::new(X2, 4)).is_err());
+        assert!(codegen.stp(Reg(X0), Reg(X1), Mem::new(X2, 512)).is_err());
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_aarch64_codegen_branch_out_of_range() {
+        let mut codegen = AArch64Asm::new();
+        let label = codegen.new_label();
+        codegen.bind_label(label).unwrap();
+        for _ in 0..=(1 << 18) {
+            codegen.mov(Reg(X0), Reg(X0)).unwrap();
+        }
+
+        let len = codegen.code().len();
+        assert_eq!(
+            codegen.cbz(Reg(X0), label),
+            Err(EncodeError::LabelOutOfRange(label))
+        );
+        assert_eq!(codegen.code().len(), len);
+        codegen.b(label).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+/// 64-bit general-purpose registers.
+///
+/// The low 5 bits hold the encoding. Register 31 is either the zero register or the
+/// stack pointer depending on the instruction, bit 5 tells `Sp` apart from `Xzr`.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg64 {
+    X0 = 0,
+    X1 = 1,
+    X2 = 2,
+    X3 = 3,
+    X4 = 4,
+    X5 = 5,
+    X6 = 6,
+    X7 = 7,
+    X8 = 8,
+    X9 = 9,
+    X10 = 10,
+    X11 = 11,
+    X12 = 12,
+    X13 = 13,
+    X14 = 14,
+    X15 = 15,
+    X16 = 16,
+    X17 = 17,
+    X18 = 18,
+    X19 = 19,
+    X20 = 20,
+    X21 = 21,
+    X22 = 22,
+    X23 = 23,
+    X24 = 24,
+    X25 = 25,
+    X26 = 26,
+    X27 = 27,
+    X28 = 28,
+    X29 = 29,
+    X30 = 30,
+    Xzr = 31,
+    Sp = 0x3f,
+}
+
+#[allow(non_upper_case_globals)]
+impl Reg64 {
+    /// Frame pointer.
+    pub const Fp: Reg64 = Reg64::X29;
+    /// Link register, holds the return address of `bl` and `blr`.
+    pub const Lr: Reg64 = Reg64::X30;
+}
+
+/// 32-bit general-purpose registers. Writing to them zero-extends the result
+/// into the full 64-bit register.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg32 {
+    W0 = 0,
+    W1 = 1,
+    W2 = 2,
+    W3 = 3,
+    W4 = 4,
+    W5 = 5,
+    W6 = 6,
+    W7 = 7,
+    W8 = 8,
+    W9 = 9,
+    W10 = 10,
+    W11 = 11,
+    W12 = 12,
+    W13 = 13,
+    W14 = 14,
+    W15 = 15,
+    W16 = 16,
+    W17 = 17,
+    W18 = 18,
+    W19 = 19,
+    W20 = 20,
+    W21 = 21,
+    W22 = 22,
+    W23 = 23,
+    W24 = 24,
+    W25 = 25,
+    W26 = 26,
+    W27 = 27,
+    W28 = 28,
+    W29 = 29,
+    W30 = 30,
+    Wzr = 31,
+    Wsp = 0x3f,
+}
@@ -0,0 +1,6 @@
+mod arm_asm;
+mod instructions;
+mod regs;
+
+pub use arm_asm::{ArmAsm, Cond, Mem, Mode, Operand};
+pub use regs::Reg;
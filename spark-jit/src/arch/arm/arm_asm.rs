@@ -1 +1,364 @@
-pub struct ArmAsm {}
+use super::regs::Reg;
+use crate::arch::EncodeError;
+use crate::label::{Fixup, Label, LabelTable};
+use crate::writer::Writer;
+
+/// Assembler for the A32 and Thumb-2 instruction sets.
+///
+/// A32 instructions are little-endian 32-bit words. Thumb-2 instructions are emitted as
+/// one or two little-endian halfwords, with the first halfword of 32-bit instructions
+/// in the upper half of the instruction words used by the encoder.
+#[derive(Default)]
+pub struct ArmAsm {
+    pub(super) writer: Writer,
+    pub(super) labels: LabelTable<FixupKind>,
+    pub(super) mode: Mode,
+    /// Condition of the next instruction, set by [`ArmAsm::cond`].
+    pub(super) cond: Cond,
+}
+
+/// The instruction set instructions are emitted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Arm,
+    Thumb,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i64),
+}
+
+/// Addressing modes of loads and stores with an immediate offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Index {
+    /// `[base, #offset]`
+    Offset,
+    /// `[base, #offset]!`
+    Pre,
+    /// `[base], #offset`
+    Post,
+}
+
+/// A memory operand: a base register and an immediate offset.
+#[derive(Debug, Clone, Copy)]
+pub struct Mem {
+    pub(super) base: Reg,
+    pub(super) offset: i32,
+    pub(super) index: Index,
+}
+
+impl Mem {
+    /// `[base, #offset]`
+    pub fn new(base: Reg, offset: i32) -> Self {
+        Self {
+            base,
+            offset,
+            index: Index::Offset,
+        }
+    }
+
+    /// `[base, #offset]!`: the base register is updated before the access.
+    pub fn pre(base: Reg, offset: i32) -> Self {
+        Self {
+            base,
+            offset,
+            index: Index::Pre,
+        }
+    }
+
+    /// `[base], #offset`: the base register is updated after the access.
+    pub fn post(base: Reg, offset: i32) -> Self {
+        Self {
+            base,
+            offset,
+            index: Index::Post,
+        }
+    }
+}
+
+impl Operand {
+    pub(super) fn reg(&self) -> Option<Reg> {
+        match *self {
+            Operand::Reg(reg) => Some(reg),
+            Operand::Imm(_) => None,
+        }
+    }
+}
+
+/// Condition codes of conditionally executed instructions, as encoded in bits 28-31 of
+/// A32 instructions and in the `IT` instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cond {
+    Eq = 0x0,
+    Ne = 0x1,
+    Hs = 0x2,
+    Lo = 0x3,
+    Mi = 0x4,
+    Pl = 0x5,
+    Vs = 0x6,
+    Vc = 0x7,
+    Hi = 0x8,
+    Ls = 0x9,
+    Ge = 0xa,
+    Lt = 0xb,
+    Gt = 0xc,
+    Le = 0xd,
+    #[default]
+    Al = 0xe,
+}
+
+#[allow(non_upper_case_globals)]
+impl Cond {
+    pub const Cs: Cond = Cond::Hs;
+    pub const Cc: Cond = Cond::Lo;
+
+    /// Get the opposite condition (e.g. `Ne` for `Eq`). `Al` has no opposite and is
+    /// returned unchanged.
+    pub fn negate(self) -> Cond {
+        use Cond::*;
+
+        match self {
+            Eq => Ne,
+            Ne => Eq,
+            Hs => Lo,
+            Lo => Hs,
+            Mi => Pl,
+            Pl => Mi,
+            Vs => Vc,
+            Vc => Vs,
+            Hi => Ls,
+            Ls => Hi,
+            Ge => Lt,
+            Lt => Ge,
+            Gt => Le,
+            Le => Gt,
+            Al => Al,
+        }
+    }
+}
+
+/// Encoding of the offset of a label-relative branch.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum FixupKind {
+    /// A32 `b` and `bl`: a 24-bit word offset from the instruction address plus 8.
+    Arm24,
+    /// Thumb-2 `b.w` and `bl`: a 25-bit offset split into S, J1, J2, imm10 and imm11.
+    Thumb25,
+    /// Thumb-2 conditional `b.w`: a 21-bit offset split into S, J2, J1, imm6 and imm11.
+    Thumb21,
+}
+
+/// An encoded instruction of either instruction set.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Insn {
+    /// An A32 instruction, without its condition.
+    A32(u32),
+    /// A 16-bit Thumb instruction.
+    T16(u16),
+    /// A 32-bit Thumb-2 instruction, first halfword in the upper half.
+    T32(u32),
+}
+
+impl ArmAsm {
+    pub fn new() -> Self {
+        Self {
+            writer: Writer::new(),
+            labels: LabelTable::default(),
+            mode: Mode::Arm,
+            cond: Cond::Al,
+        }
+    }
+
+    /// The instruction set of the next instructions.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switch the instruction set of the next instructions. Switching to A32 after an odd
+    /// number of Thumb halfwords pads the code with a Thumb `nop` to keep it word-aligned.
+    ///
+    /// This doesn't switch the processor state: the code must be entered with an
+    /// interworking branch such as `bx` or `blx`.
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode == Mode::Arm && !self.offset().is_multiple_of(4) {
+            self.writer.emit16(0xbf00);
+        }
+        self.mode = mode;
+    }
+
+    /// Make the next instruction conditional. In Thumb mode the instruction is preceded
+    /// by an `IT` instruction, except for branches that encode the condition themselves.
+    ///
+    /// The condition is reset by the next instruction, even if it fails to encode.
+    pub fn cond(&mut self, cond: Cond) -> &mut Self {
+        self.cond = cond;
+        self
+    }
+
+    /// Create a new label. The label must be bound with [`ArmAsm::bind_label`] before
+    /// the code is executed, but it can be referenced by branches at any time. Labels
+    /// must be referenced from code of the same instruction set.
+    pub fn new_label(&mut self) -> Label {
+        self.labels.create()
+    }
+
+    /// Bind the label to the current offset and patch every pending reference to it.
+    ///
+    /// Fails if the label is already bound or if a branch can't reach the label.
+    pub fn bind_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        let offset = self.offset();
+        for fixup in self.labels.bind(label, offset)? {
+            self.patch_fixup(fixup, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Get the offset the label is bound to, if any.
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels.offset(label)
+    }
+
+    /// Check whether some emitted instructions still reference unbound labels.
+    pub fn has_unbound_references(&self) -> bool {
+        self.labels.has_pending()
+    }
+
+    /// Current offset in the code buffer.
+    pub fn offset(&self) -> usize {
+        self.code().len()
+    }
+
+    pub fn code(&self) -> &[u8] {
+        self.writer.bytes()
+    }
+
+    /// Take the condition set by [`ArmAsm::cond`] for the instruction being emitted.
+    pub(super) fn take_cond(&mut self) -> Cond {
+        std::mem::take(&mut self.cond)
+    }
+
+    /// Emit an instruction with the given condition. A32 instructions encode it in their
+    /// upper bits, Thumb instructions are preceded by `IT` for anything but `Al`.
+    pub(super) fn emit(&mut self, cond: Cond, insn: Insn) {
+        if !matches!(insn, Insn::A32(_)) && cond != Cond::Al {
+            // A single-instruction IT block: the mask only has its terminating bit set.
+            self.writer.emit16(0xbf08 | ((cond as u16) << 4));
+        }
+
+        match insn {
+            Insn::A32(insn) => self.writer.emit32(((cond as u32) << 28) | insn),
+            Insn::T16(insn) => self.writer.emit16(insn),
+            Insn::T32(insn) => {
+                self.writer.emit16((insn >> 16) as u16);
+                self.writer.emit16(insn as u16);
+            }
+        }
+    }
+
+    /// Emit a branch whose offset field refers to the label. The field is patched right
+    /// away if the label is bound, or once it is.
+    pub(super) fn emit_branch(
+        &mut self,
+        cond: Cond,
+        insn: Insn,
+        label: Label,
+        kind: FixupKind,
+    ) -> Result<(), EncodeError> {
+        // The branch itself follows the IT instruction, if there is one.
+        let it_len = match insn {
+            Insn::A32(_) => 0,
+            _ if cond == Cond::Al => 0,
+            _ => 2,
+        };
+        let fixup = Fixup {
+            label,
+            offset: self.offset() + it_len,
+            kind,
+        };
+
+        match self.labels.offset(label) {
+            Some(target) => {
+                // Check the range before emitting, so that a failed branch leaves nothing behind.
+                Self::encode_rel(fixup, target)?;
+                self.emit(cond, insn);
+                self.patch_fixup(fixup, target)
+            }
+            None => {
+                self.emit(cond, insn);
+                self.labels.add_pending(fixup);
+                Ok(())
+            }
+        }
+    }
+
+    /// Encode the offset from the branch to the target into the bits of its field.
+    fn encode_rel(fixup: Fixup<FixupKind>, target: usize) -> Result<u32, EncodeError> {
+        let out_of_range = Err(EncodeError::LabelOutOfRange(fixup.label));
+
+        match fixup.kind {
+            FixupKind::Arm24 => {
+                let rel = (target as i64 - (fixup.offset as i64 + 8)) >> 2;
+                if !(-(1 << 23)..(1 << 23)).contains(&rel) {
+                    return out_of_range;
+                }
+                Ok(rel as u32 & 0xff_ffff)
+            }
+            FixupKind::Thumb25 => {
+                let rel = target as i64 - (fixup.offset as i64 + 4);
+                if !(-(1 << 24)..(1 << 24)).contains(&rel) {
+                    return out_of_range;
+                }
+
+                let bit = |n: u32| (rel >> n) as u32 & 1;
+                let s = bit(24);
+                let j1 = !(bit(23) ^ s) & 1;
+                let j2 = !(bit(22) ^ s) & 1;
+                let imm10 = (rel >> 12) as u32 & 0x3ff;
+                let imm11 = (rel >> 1) as u32 & 0x7ff;
+                Ok((s << 26) | (imm10 << 16) | (j1 << 13) | (j2 << 11) | imm11)
+            }
+            FixupKind::Thumb21 => {
+                let rel = target as i64 - (fixup.offset as i64 + 4);
+                if !(-(1 << 20)..(1 << 20)).contains(&rel) {
+                    return out_of_range;
+                }
+
+                let bit = |n: u32| (rel >> n) as u32 & 1;
+                let imm6 = (rel >> 12) as u32 & 0x3f;
+                let imm11 = (rel >> 1) as u32 & 0x7ff;
+                Ok((bit(20) << 26) | (imm6 << 16) | (bit(18) << 13) | (bit(19) << 11) | imm11)
+            }
+        }
+    }
+
+    fn patch_fixup(&mut self, fixup: Fixup<FixupKind>, target: usize) -> Result<(), EncodeError> {
+        let field = Self::encode_rel(fixup, target)?;
+        let code = &self.code()[fixup.offset..fixup.offset + 4];
+
+        match fixup.kind {
+            FixupKind::Arm24 => {
+                let insn = u32::from_le_bytes(code.try_into().unwrap());
+                self.writer.emit32_at(fixup.offset, insn | field);
+            }
+            FixupKind::Thumb25 | FixupKind::Thumb21 => {
+                let hw1 = u16::from_le_bytes([code[0], code[1]]) as u32;
+                let hw2 = u16::from_le_bytes([code[2], code[3]]) as u32;
+                let insn = (hw1 << 16) | hw2 | field;
+                self.writer.emit16_at(fixup.offset, (insn >> 16) as u16);
+                self.writer.emit16_at(fixup.offset + 2, insn as u16);
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the error for an operand combination the instruction doesn't support.
+    pub(super) fn invalid(mnemonic: &'static str, operands: &[Operand]) -> EncodeError {
+        EncodeError::InvalidOperands {
+            mnemonic,
+            operands: format!("{:?}", operands),
+        }
+    }
+}
@@ -0,0 +1,1053 @@
+use super::arm_asm::{FixupKind, Index, Insn};
+use super::{ArmAsm, Cond, Mem, Mode, Operand, Reg};
+use crate::arch::{Assembler, EncodeError};
+use crate::label::Label;
+
+/// Data-processing operations that take a register or an immediate as their last operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DataOp {
+    And,
+    Eor,
+    Sub,
+    Rsb,
+    Add,
+    Tst,
+    Cmp,
+    Cmn,
+    Orr,
+    Mov,
+    Bic,
+    Mvn,
+}
+
+impl DataOp {
+    /// Opcode of the A32 encodings, bits 21-24.
+    fn arm(self) -> u32 {
+        use DataOp::*;
+
+        match self {
+            And => 0b0000,
+            Eor => 0b0001,
+            Sub => 0b0010,
+            Rsb => 0b0011,
+            Add => 0b0100,
+            Tst => 0b1000,
+            Cmp => 0b1010,
+            Cmn => 0b1011,
+            Orr => 0b1100,
+            Mov => 0b1101,
+            Bic => 0b1110,
+            Mvn => 0b1111,
+        }
+    }
+
+    /// Opcode of the Thumb-2 encodings, bits 21-24. Thumb-2 has no `mov` and `mvn`
+    /// opcodes, they are `orr` and `orn` with PC as the first source.
+    fn thumb(self) -> u32 {
+        use DataOp::*;
+
+        match self {
+            And | Tst => 0b0000,
+            Bic => 0b0001,
+            Orr | Mov => 0b0010,
+            Mvn => 0b0011,
+            Eor => 0b0100,
+            Add | Cmn => 0b1000,
+            Sub | Cmp => 0b1101,
+            Rsb => 0b1110,
+        }
+    }
+
+    /// Comparisons only set the flags and have no destination register.
+    fn is_compare(self) -> bool {
+        matches!(self, DataOp::Tst | DataOp::Cmp | DataOp::Cmn)
+    }
+
+    /// The equivalent operation on the negated or inverted immediate, tried when the
+    /// immediate itself can't be encoded.
+    fn complement(self, imm: u32) -> Option<(DataOp, u32)> {
+        use DataOp::*;
+
+        match self {
+            Add => Some((Sub, imm.wrapping_neg())),
+            Sub => Some((Add, imm.wrapping_neg())),
+            Cmp => Some((Cmn, imm.wrapping_neg())),
+            Cmn => Some((Cmp, imm.wrapping_neg())),
+            Mov => Some((Mvn, !imm)),
+            Mvn => Some((Mov, !imm)),
+            And => Some((Bic, !imm)),
+            Bic => Some((And, !imm)),
+            _ => None,
+        }
+    }
+}
+
+impl ArmAsm {
+    /// Encode an A32 modified immediate: an 8-bit value rotated right by an even amount.
+    fn arm_imm(value: u32) -> Option<u32> {
+        (0..16).find_map(|rot| {
+            let imm8 = value.rotate_left(rot * 2);
+            (imm8 <= 0xff).then_some((rot << 8) | imm8)
+        })
+    }
+
+    /// Encode a Thumb-2 modified immediate: an 8-bit value either replicated into some
+    /// of the bytes of the word, or with its top bit set and rotated right by 8 to 31.
+    fn thumb_imm(value: u32) -> Option<u32> {
+        let byte = value & 0xff;
+        let high = (value >> 8) & 0xff;
+
+        match value {
+            0..=0xff => Some(value),
+            _ if value == byte * 0x0001_0001 => Some(0x100 | byte),
+            _ if value == high * 0x0100_0100 => Some(0x200 | high),
+            _ if value == byte * 0x0101_0101 => Some(0x300 | byte),
+            _ => (8..32).find_map(|rot| {
+                let imm8 = value.rotate_left(rot);
+                (0x80..=0xff)
+                    .contains(&imm8)
+                    .then_some((rot << 7) | (imm8 & 0x7f))
+            }),
+        }
+    }
+
+    /// Spread a 12-bit Thumb-2 immediate over its `i`, `imm3` and `imm8` fields.
+    fn thumb_imm12(imm12: u32) -> u32 {
+        ((imm12 >> 11) << 26) | (((imm12 >> 8) & 0b111) << 12) | (imm12 & 0xff)
+    }
+
+    /// Encode a modified immediate for the current instruction set.
+    fn modified_imm(&self, value: u32) -> Option<u32> {
+        match self.mode {
+            Mode::Arm => Self::arm_imm(value),
+            Mode::Thumb => Self::thumb_imm(value),
+        }
+    }
+
+    /// Check that the immediate fits into 32 bits, either signed or unsigned.
+    fn imm32(imm: i64) -> Result<u32, EncodeError> {
+        match i32::try_from(imm).is_ok() || u32::try_from(imm).is_ok() {
+            true => Ok(imm as u32),
+            false => Err(EncodeError::ImmediateOutOfRange(imm)),
+        }
+    }
+
+    /// Get the number of a register operand of a data-processing instruction. Thumb-2
+    /// uses PC in these fields to select other instructions.
+    fn data_reg(
+        &self,
+        mnemonic: &'static str,
+        operands: &[Operand],
+        op: Operand,
+    ) -> Result<u32, EncodeError> {
+        match op.reg() {
+            Some(Reg::Pc) if self.mode == Mode::Thumb => Err(EncodeError::UnsupportedRegister(
+                "PC can't be used here in Thumb mode",
+            )),
+            Some(reg) => Ok(reg as u32),
+            None => Err(Self::invalid(mnemonic, operands)),
+        }
+    }
+
+    /// Emit a data-processing instruction. `dst` is `None` for comparisons and `src1` is
+    /// `None` for moves.
+    ///
+    /// Immediates that can't be encoded are retried with the complementary operation,
+    /// e.g. `add r0, r1, #-1` is `sub r0, r1, #1`. In Thumb mode, `add` and `sub` also
+    /// take any 12-bit immediate.
+    fn emit_data(
+        &mut self,
+        mnemonic: &'static str,
+        op: DataOp,
+        set_flags: bool,
+        dst: Option<Operand>,
+        src1: Option<Operand>,
+        src2: Operand,
+    ) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let operands: Vec<Operand> = dst.into_iter().chain(src1).chain([src2]).collect();
+
+        let rd = dst
+            .map(|dst| self.data_reg(mnemonic, &operands, dst))
+            .transpose()?;
+        let rn = src1
+            .map(|src1| self.data_reg(mnemonic, &operands, src1))
+            .transpose()?;
+        let s = (set_flags || op.is_compare()) as u32;
+
+        // Unused register fields are 0 in A32 and all ones in Thumb-2.
+        let fields = |unused: u32| (s << 20) | (rn.unwrap_or(unused) << 16);
+        let rd = |unused: u32| rd.unwrap_or(unused);
+
+        let insn = match src2 {
+            Operand::Reg(_) => {
+                let rm = self.data_reg(mnemonic, &operands, src2)?;
+                match self.mode {
+                    Mode::Arm => Insn::A32((op.arm() << 21) | fields(0) | (rd(0) << 12) | rm),
+                    Mode::Thumb => Insn::T32(
+                        0xea00_0000 | (op.thumb() << 21) | fields(0b1111) | (rd(0b1111) << 8) | rm,
+                    ),
+                }
+            }
+            Operand::Imm(imm) => {
+                let value = Self::imm32(imm)?;
+                let encoded = [(op, value)]
+                    .into_iter()
+                    .chain(op.complement(value))
+                    .find_map(|(op, value)| Some((op, self.modified_imm(value)?)));
+
+                match (encoded, self.mode) {
+                    (Some((op, imm12)), Mode::Arm) => {
+                        Insn::A32((1 << 25) | (op.arm() << 21) | fields(0) | (rd(0) << 12) | imm12)
+                    }
+                    (Some((op, imm12)), Mode::Thumb) => Insn::T32(
+                        0xf000_0000
+                            | Self::thumb_imm12(imm12)
+                            | (op.thumb() << 21)
+                            | fields(0b1111)
+                            | (rd(0b1111) << 8),
+                    ),
+                    // addw and subw
+                    (None, Mode::Thumb)
+                        if matches!(op, DataOp::Add | DataOp::Sub) && !set_flags =>
+                    {
+                        let (sub, imm12) = match value {
+                            0..=0xfff => (op == DataOp::Sub, value),
+                            _ if value.wrapping_neg() <= 0xfff => {
+                                (op == DataOp::Add, value.wrapping_neg())
+                            }
+                            _ => return Err(EncodeError::ImmediateOutOfRange(imm)),
+                        };
+                        let opcode = if sub { 0xf2a0_0000 } else { 0xf200_0000 };
+                        Insn::T32(
+                            opcode | Self::thumb_imm12(imm12) | fields(0b1111) | (rd(0b1111) << 8),
+                        )
+                    }
+                    (None, _) => return Err(EncodeError::ImmediateOutOfRange(imm)),
+                }
+            }
+        };
+
+        self.emit(cond, insn);
+        Ok(())
+    }
+
+    pub fn add(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("add", DataOp::Add, false, Some(dst), Some(src1), src2)
+    }
+
+    /// Add and set the flags.
+    pub fn adds(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("adds", DataOp::Add, true, Some(dst), Some(src1), src2)
+    }
+
+    pub fn sub(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("sub", DataOp::Sub, false, Some(dst), Some(src1), src2)
+    }
+
+    /// Subtract and set the flags.
+    pub fn subs(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("subs", DataOp::Sub, true, Some(dst), Some(src1), src2)
+    }
+
+    /// Reverse subtract: `dst = src2 - src1`.
+    pub fn rsb(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("rsb", DataOp::Rsb, false, Some(dst), Some(src1), src2)
+    }
+
+    pub fn and(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("and", DataOp::And, false, Some(dst), Some(src1), src2)
+    }
+
+    pub fn orr(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("orr", DataOp::Orr, false, Some(dst), Some(src1), src2)
+    }
+
+    pub fn eor(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("eor", DataOp::Eor, false, Some(dst), Some(src1), src2)
+    }
+
+    /// Bit clear: `dst = src1 & !src2`.
+    pub fn bic(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        self.emit_data("bic", DataOp::Bic, false, Some(dst), Some(src1), src2)
+    }
+
+    /// Move the bitwise inverse of a register or an immediate.
+    pub fn mvn(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_data("mvn", DataOp::Mvn, false, Some(dst), None, src)
+    }
+
+    pub fn cmp(&mut self, lhs: Operand, rhs: Operand) -> Result<(), EncodeError> {
+        self.emit_data("cmp", DataOp::Cmp, false, None, Some(lhs), rhs)
+    }
+
+    /// Compare with the negated operand, i.e. set the flags like `adds`.
+    pub fn cmn(&mut self, lhs: Operand, rhs: Operand) -> Result<(), EncodeError> {
+        self.emit_data("cmn", DataOp::Cmn, false, None, Some(lhs), rhs)
+    }
+
+    /// Set the flags like `ands`.
+    pub fn tst(&mut self, lhs: Operand, rhs: Operand) -> Result<(), EncodeError> {
+        self.emit_data("tst", DataOp::Tst, false, None, Some(lhs), rhs)
+    }
+
+    /// Move a register or an immediate into a register.
+    ///
+    /// Immediates that are neither a modified immediate nor the inverse of one are
+    /// materialized with `movw`, followed by `movt` if the upper halfword isn't zero.
+    pub fn mov(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let Operand::Imm(imm) = src else {
+            return self.emit_data("mov", DataOp::Mov, false, Some(dst), None, src);
+        };
+
+        let value = Self::imm32(imm)?;
+        if self.modified_imm(value).is_some() || self.modified_imm(!value).is_some() {
+            return self.emit_data("mov", DataOp::Mov, false, Some(dst), None, src);
+        }
+
+        let cond = self.take_cond();
+        let rd = self.data_reg("mov", &[dst, src], dst)?;
+        self.emit(cond, self.encode_mov_wide(false, rd, value as u16));
+        if value >> 16 != 0 {
+            self.emit(cond, self.encode_mov_wide(true, rd, (value >> 16) as u16));
+        }
+        Ok(())
+    }
+
+    /// Encode `movw`, or `movt` if `top` is set.
+    fn encode_mov_wide(&self, top: bool, rd: u32, imm16: u16) -> Insn {
+        let (imm16, top) = (imm16 as u32, top as u32);
+
+        match self.mode {
+            Mode::Arm => Insn::A32(
+                0x0300_0000 | (top << 22) | ((imm16 >> 12) << 16) | (rd << 12) | (imm16 & 0xfff),
+            ),
+            Mode::Thumb => Insn::T32(
+                0xf240_0000
+                    | (top << 23)
+                    | ((imm16 >> 12) << 16)
+                    | Self::thumb_imm12(imm16 & 0xfff)
+                    | (rd << 8),
+            ),
+        }
+    }
+
+    /// Move a 16-bit immediate into a register, zeroing the upper halfword.
+    pub fn movw(&mut self, dst: Operand, imm16: u16) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let rd = self.data_reg("movw", &[dst], dst)?;
+        self.emit(cond, self.encode_mov_wide(false, rd, imm16));
+        Ok(())
+    }
+
+    /// Move a 16-bit immediate into the upper halfword of a register, keeping the lower one.
+    pub fn movt(&mut self, dst: Operand, imm16: u16) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let rd = self.data_reg("movt", &[dst], dst)?;
+        self.emit(cond, self.encode_mov_wide(true, rd, imm16));
+        Ok(())
+    }
+
+    /// Multiply two registers, keeping the lower 32 bits of the result.
+    pub fn mul(&mut self, dst: Operand, src1: Operand, src2: Operand) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let operands = [dst, src1, src2];
+        let rd = self.data_reg("mul", &operands, dst)?;
+        let rn = self.data_reg("mul", &operands, src1)?;
+        let rm = self.data_reg("mul", &operands, src2)?;
+
+        let insn = match self.mode {
+            Mode::Arm => Insn::A32(0x0000_0090 | (rd << 16) | (rm << 8) | rn),
+            Mode::Thumb => Insn::T32(0xfb00_f000 | (rn << 16) | (rd << 8) | rm),
+        };
+        self.emit(cond, insn);
+        Ok(())
+    }
+
+    /// Emit a load or a store of a word or a byte. A32 takes offsets of up to 4095 bytes
+    /// either way. Thumb-2 takes offsets of up to 4095 bytes, but only up to 255 bytes
+    /// for negative offsets and with writeback.
+    fn emit_load_store(
+        &mut self,
+        mnemonic: &'static str,
+        load: bool,
+        byte: bool,
+        rt: Operand,
+        mem: Mem,
+    ) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let Some(rt) = rt.reg() else {
+            return Err(Self::invalid(mnemonic, &[rt]));
+        };
+        if self.mode == Mode::Thumb && mem.base == Reg::Pc {
+            return Err(EncodeError::UnsupportedRegister(
+                "PC can't be used as a base register in Thumb mode",
+            ));
+        }
+
+        let (rt, rn) = (rt as u32, mem.base as u32);
+        let (load, byte) = (load as u32, byte as u32);
+        let offset = mem.offset as i64;
+        let (up, abs) = ((offset >= 0) as u32, offset.unsigned_abs() as u32);
+        let out_of_range = Err(EncodeError::ImmediateOutOfRange(offset));
+
+        let insn = match self.mode {
+            Mode::Arm => {
+                if abs > 0xfff {
+                    return out_of_range;
+                }
+                let (pre, writeback) = match mem.index {
+                    Index::Offset => (1, 0),
+                    Index::Pre => (1, 1),
+                    Index::Post => (0, 0),
+                };
+                Insn::A32(
+                    0x0400_0000
+                        | (pre << 24)
+                        | (up << 23)
+                        | (byte << 22)
+                        | (writeback << 21)
+                        | (load << 20)
+                        | (rn << 16)
+                        | (rt << 12)
+                        | abs,
+                )
+            }
+            Mode::Thumb => {
+                let base = ((1 - byte) << 22) | (load << 20) | (rn << 16) | (rt << 12);
+                let (pre, writeback) = match mem.index {
+                    Index::Offset => (1, 0),
+                    Index::Pre => (1, 1),
+                    Index::Post => (0, 1),
+                };
+
+                match (mem.index, up, abs) {
+                    (Index::Offset, 1, 0..=0xfff) => Insn::T32(0xf880_0000 | base | abs),
+                    (_, _, 0..=0xff) => Insn::T32(
+                        0xf800_0800 | base | (pre << 10) | (up << 9) | (writeback << 8) | abs,
+                    ),
+                    _ => return out_of_range,
+                }
+            }
+        };
+
+        self.emit(cond, insn);
+        Ok(())
+    }
+
+    /// Load a word from memory.
+    pub fn ldr(&mut self, dst: Operand, src: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store("ldr", true, false, dst, src)
+    }
+
+    /// Store a word to memory.
+    pub fn str(&mut self, src: Operand, dst: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store("str", false, false, src, dst)
+    }
+
+    /// Load a byte from memory, zero-extending it.
+    pub fn ldrb(&mut self, dst: Operand, src: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store("ldrb", true, true, dst, src)
+    }
+
+    /// Store the lowest byte of a register to memory.
+    pub fn strb(&mut self, src: Operand, dst: Mem) -> Result<(), EncodeError> {
+        self.emit_load_store("strb", false, true, src, dst)
+    }
+
+    /// Build the bit mask of a register list.
+    fn reg_list(mnemonic: &'static str, regs: &[Reg]) -> Result<u32, EncodeError> {
+        match regs.iter().fold(0, |list, &reg| list | (1 << reg as u32)) {
+            0 => Err(Self::invalid(mnemonic, &[])),
+            list => Ok(list),
+        }
+    }
+
+    /// Push registers onto the stack, the lowest-numbered register at the lowest address.
+    /// A single register is pushed with `str reg, [sp, #-4]!`.
+    ///
+    /// Thumb-2 can't push SP or PC.
+    pub fn push(&mut self, regs: &[Reg]) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let list = Self::reg_list("push", regs)?;
+        let single = list.trailing_zeros() << 12;
+
+        let insn = match (self.mode, list.count_ones()) {
+            (Mode::Thumb, _) if list & (1 << Reg::Sp as u32 | 1 << Reg::Pc as u32) != 0 => {
+                return Err(EncodeError::UnsupportedRegister(
+                    "SP and PC can't be pushed in Thumb mode",
+                ));
+            }
+            (Mode::Arm, 1) => Insn::A32(0x052d_0004 | single),
+            (Mode::Arm, _) => Insn::A32(0x092d_0000 | list),
+            (Mode::Thumb, 1) => Insn::T32(0xf84d_0d04 | single),
+            (Mode::Thumb, _) => Insn::T32(0xe92d_0000 | list),
+        };
+        self.emit(cond, insn);
+        Ok(())
+    }
+
+    /// Pop registers from the stack, the lowest-numbered register from the lowest address.
+    /// A single register is popped with `ldr reg, [sp], #4`.
+    ///
+    /// Thumb-2 can't pop SP, nor both LR and PC.
+    pub fn pop(&mut self, regs: &[Reg]) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let list = Self::reg_list("pop", regs)?;
+        let single = list.trailing_zeros() << 12;
+        let lr_pc = 1 << Reg::Lr as u32 | 1 << Reg::Pc as u32;
+
+        let insn = match (self.mode, list.count_ones()) {
+            (Mode::Thumb, _) if list & (1 << Reg::Sp as u32) != 0 || list & lr_pc == lr_pc => {
+                return Err(EncodeError::UnsupportedRegister(
+                    "SP, or both LR and PC, can't be popped in Thumb mode",
+                ));
+            }
+            (Mode::Arm, 1) => Insn::A32(0x049d_0004 | single),
+            (Mode::Arm, _) => Insn::A32(0x08bd_0000 | list),
+            (Mode::Thumb, 1) => Insn::T32(0xf85d_0b04 | single),
+            (Mode::Thumb, _) => Insn::T32(0xe8bd_0000 | list),
+        };
+        self.emit(cond, insn);
+        Ok(())
+    }
+
+    /// Branch to a label. A32 branches reach +-32MiB. Thumb-2 branches reach +-16MiB, or
+    /// +-1MiB if they are conditional.
+    pub fn b(&mut self, label: Label) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+
+        match self.mode {
+            Mode::Arm => self.emit_branch(cond, Insn::A32(0x0a00_0000), label, FixupKind::Arm24),
+            Mode::Thumb if cond == Cond::Al => {
+                self.emit_branch(cond, Insn::T32(0xf000_9000), label, FixupKind::Thumb25)
+            }
+            // Conditional branches encode their condition instead of using IT.
+            Mode::Thumb => {
+                let insn = Insn::T32(0xf000_8000 | ((cond as u32) << 22));
+                self.emit_branch(Cond::Al, insn, label, FixupKind::Thumb21)
+            }
+        }
+    }
+
+    /// Call a label, saving the return address in LR. The label must be in code of the
+    /// same instruction set.
+    pub fn bl(&mut self, label: Label) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+
+        match self.mode {
+            Mode::Arm => self.emit_branch(cond, Insn::A32(0x0b00_0000), label, FixupKind::Arm24),
+            Mode::Thumb => {
+                self.emit_branch(cond, Insn::T32(0xf000_d000), label, FixupKind::Thumb25)
+            }
+        }
+    }
+
+    /// Emit `bx` or `blx` to a register.
+    fn emit_branch_reg(
+        &mut self,
+        mnemonic: &'static str,
+        link: bool,
+        target: Operand,
+    ) -> Result<(), EncodeError> {
+        let cond = self.take_cond();
+        let Some(rm) = target.reg() else {
+            return Err(Self::invalid(mnemonic, &[target]));
+        };
+
+        let (rm, link) = (rm as u32, link as u32);
+        let insn = match self.mode {
+            Mode::Arm => Insn::A32(0x012f_ff10 | (link << 5) | rm),
+            Mode::Thumb => Insn::T16((0x4700 | (link << 7) | (rm << 3)) as u16),
+        };
+        self.emit(cond, insn);
+        Ok(())
+    }
+
+    /// Branch to the address held by a register, switching to Thumb mode if its lowest
+    /// bit is set. `bx lr` returns from a function.
+    pub fn bx(&mut self, target: Operand) -> Result<(), EncodeError> {
+        self.emit_branch_reg("bx", false, target)
+    }
+
+    /// Call the address held by a register, switching to Thumb mode if its lowest bit
+    /// is set.
+    pub fn blx(&mut self, target: Operand) -> Result<(), EncodeError> {
+        self.emit_branch_reg("blx", true, target)
+    }
+}
+
+impl Assembler for ArmAsm {
+    type Reg = Reg;
+
+    /// The AAPCS calling convention.
+    const ARG_REGS: &'static [Reg] = &[Reg::R0, Reg::R1, Reg::R2, Reg::R3];
+    const RET_REG: Reg = Reg::R0;
+
+    fn new_label(&mut self) -> Label {
+        ArmAsm::new_label(self)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        ArmAsm::bind_label(self, label)
+    }
+
+    fn offset(&self) -> usize {
+        ArmAsm::offset(self)
+    }
+
+    fn code(&self) -> &[u8] {
+        ArmAsm::code(self)
+    }
+
+    fn load_imm(&mut self, dst: Reg, imm: i64) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::Imm(imm))
+    }
+
+    fn mov_reg(&mut self, dst: Reg, src: Reg) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::Reg(src))
+    }
+
+    fn add_reg(&mut self, dst: Reg, lhs: Reg, rhs: Reg) -> Result<(), EncodeError> {
+        self.add(Operand::Reg(dst), Operand::Reg(lhs), Operand::Reg(rhs))
+    }
+
+    fn sub_reg(&mut self, dst: Reg, lhs: Reg, rhs: Reg) -> Result<(), EncodeError> {
+        self.sub(Operand::Reg(dst), Operand::Reg(lhs), Operand::Reg(rhs))
+    }
+
+    fn mul_reg(&mut self, dst: Reg, lhs: Reg, rhs: Reg) -> Result<(), EncodeError> {
+        self.mul(Operand::Reg(dst), Operand::Reg(lhs), Operand::Reg(rhs))
+    }
+
+    fn load(&mut self, dst: Reg, base: Reg, offset: i32) -> Result<(), EncodeError> {
+        self.ldr(Operand::Reg(dst), Mem::new(base, offset))
+    }
+
+    fn store(&mut self, src: Reg, base: Reg, offset: i32) -> Result<(), EncodeError> {
+        self.str(Operand::Reg(src), Mem::new(base, offset))
+    }
+
+    fn jump(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.b(label)
+    }
+
+    fn call_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.bl(label)
+    }
+
+    fn call_reg(&mut self, target: Reg) -> Result<(), EncodeError> {
+        self.blx(Operand::Reg(target))
+    }
+
+    fn ret(&mut self) -> Result<(), EncodeError> {
+        self.bx(Operand::Reg(Reg::Lr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Reg::*;
+    use super::*;
+    use Operand::*;
+
+    #[test]
+    fn test_arm_codegen_data_processing() {
+        let mut codegen = ArmAsm::new();
+        codegen.add(Reg(R0), Reg(R1), Reg(R2)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0xff)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0x3fc)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0xff00_0000)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(-4)).unwrap();
+        codegen.adds(Reg(R3), Reg(R4), Imm(1)).unwrap();
+        codegen.sub(Reg(Sp), Reg(Sp), Imm(8)).unwrap();
+        codegen.subs(Reg(R0), Reg(R0), Reg(R1)).unwrap();
+        codegen.rsb(Reg(R0), Reg(R1), Imm(0)).unwrap();
+        codegen.and(Reg(R2), Reg(R3), Imm(0xff00)).unwrap();
+        codegen.and(Reg(R0), Reg(R0), Imm(0xffff_ff00)).unwrap();
+        codegen.orr(Reg(R0), Reg(R1), Reg(R2)).unwrap();
+        codegen.eor(Reg(R4), Reg(R5), Reg(R6)).unwrap();
+        codegen.bic(Reg(R0), Reg(R0), Imm(1)).unwrap();
+        codegen.mvn(Reg(R0), Reg(R1)).unwrap();
+        codegen.cmp(Reg(R0), Imm(-1)).unwrap();
+        codegen.cmp(Reg(R0), Reg(R1)).unwrap();
+        codegen.cmn(Reg(R2), Reg(R3)).unwrap();
+        codegen.tst(Reg(R0), Imm(1)).unwrap();
+        codegen.mul(Reg(R0), Reg(R1), Reg(R2)).unwrap();
+        codegen.mov(Reg(R0), Reg(R1)).unwrap();
+        codegen.mov(Reg(R0), Imm(0)).unwrap();
+        codegen.mov(Reg(R0), Imm(-1)).unwrap();
+        codegen.mov(Reg(R0), Imm(0x1234)).unwrap();
+        codegen.mov(Reg(R1), Imm(0x1234_5678)).unwrap();
+        codegen.movw(Reg(R2), 0xffff).unwrap();
+        codegen.movt(Reg(R2), 0x8000).unwrap();
+        codegen
+            .cond(Cond::Eq)
+            .add(Reg(R0), Reg(R0), Imm(1))
+            .unwrap();
+        codegen
+            .cond(Cond::Ne)
+            .mov(Reg(R3), Imm(0x1234_5678))
+            .unwrap();
+        codegen
+            .cond(Cond::Ge)
+            .mul(Reg(R4), Reg(R5), Reg(R6))
+            .unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x02, 0x00, 0x81, 0xe0, // add r0, r1, r2
+                0xff, 0x00, 0x81, 0xe2, // add r0, r1, #0xff
+                0xff, 0x0f, 0x81, 0xe2, // add r0, r1, #0x3fc
+                0xff, 0x04, 0x81, 0xe2, // add r0, r1, #0xff000000
+                0x04, 0x00, 0x41, 0xe2, // sub r0, r1, #4
+                0x01, 0x30, 0x94, 0xe2, // adds r3, r4, #1
+                0x08, 0xd0, 0x4d, 0xe2, // sub sp, sp, #8
+                0x01, 0x00, 0x50, 0xe0, // subs r0, r0, r1
+                0x00, 0x00, 0x61, 0xe2, // rsb r0, r1, #0
+                0xff, 0x2c, 0x03, 0xe2, // and r2, r3, #0xff00
+                0xff, 0x00, 0xc0, 0xe3, // bic r0, r0, #0xff
+                0x02, 0x00, 0x81, 0xe1, // orr r0, r1, r2
+                0x06, 0x40, 0x25, 0xe0, // eor r4, r5, r6
+                0x01, 0x00, 0xc0, 0xe3, // bic r0, r0, #1
+                0x01, 0x00, 0xe0, 0xe1, // mvn r0, r1
+                0x01, 0x00, 0x70, 0xe3, // cmn r0, #1
+                0x01, 0x00, 0x50, 0xe1, // cmp r0, r1
+                0x03, 0x00, 0x72, 0xe1, // cmn r2, r3
+                0x01, 0x00, 0x10, 0xe3, // tst r0, #1
+                0x91, 0x02, 0x00, 0xe0, // mul r0, r1, r2
+                0x01, 0x00, 0xa0, 0xe1, // mov r0, r1
+                0x00, 0x00, 0xa0, 0xe3, // mov r0, #0
+                0x00, 0x00, 0xe0, 0xe3, // mvn r0, #0
+                0x34, 0x02, 0x01, 0xe3, // movw r0, #0x1234
+                0x78, 0x16, 0x05, 0xe3, // movw r1, #0x5678
+                0x34, 0x12, 0x41, 0xe3, // movt r1, #0x1234
+                0xff, 0x2f, 0x0f, 0xe3, // movw r2, #0xffff
+                0x00, 0x20, 0x48, 0xe3, // movt r2, #0x8000
+                0x01, 0x00, 0x80, 0x02, // addeq r0, r0, #1
+                0x78, 0x36, 0x05, 0x13, // movwne r3, #0x5678
+                0x34, 0x32, 0x41, 0x13, // movtne r3, #0x1234
+                0x95, 0x06, 0x04, 0xa0, // mulge r4, r5, r6
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arm_codegen_load_store() {
+        let mut codegen = ArmAsm::new();
+        codegen.ldr(Reg(R0), Mem::new(R1, 0)).unwrap();
+        codegen.ldr(Reg(R0), Mem::new(R1, 4)).unwrap();
+        codegen.ldr(Reg(R0), Mem::new(R1, -4)).unwrap();
+        codegen.ldr(Reg(R2), Mem::new(Sp, 4095)).unwrap();
+        codegen.ldr(Reg(R3), Mem::pre(R4, 8)).unwrap();
+        codegen.ldr(Reg(R5), Mem::post(R6, -8)).unwrap();
+        codegen.ldr(Reg(R7), Mem::new(Pc, 8)).unwrap();
+        codegen.str(Reg(R2), Mem::pre(Sp, -4)).unwrap();
+        codegen.str(Reg(R8), Mem::new(R9, 0x100)).unwrap();
+        codegen.strb(Reg(R3), Mem::new(R4, 1)).unwrap();
+        codegen.ldrb(Reg(R5), Mem::post(R6, 1)).unwrap();
+        codegen
+            .cond(Cond::Lo)
+            .ldr(Reg(R0), Mem::new(R1, 0))
+            .unwrap();
+        codegen.push(&[R4, Lr]).unwrap();
+        codegen.push(&[R0]).unwrap();
+        codegen.push(&[R11, R4, R5, R10]).unwrap();
+        codegen.pop(&[R4, Pc]).unwrap();
+        codegen.pop(&[R0]).unwrap();
+        codegen.cond(Cond::Eq).pop(&[R0, R1]).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x00, 0x00, 0x91, 0xe5, // ldr r0, [r1]
+                0x04, 0x00, 0x91, 0xe5, // ldr r0, [r1, #4]
+                0x04, 0x00, 0x11, 0xe5, // ldr r0, [r1, #-4]
+                0xff, 0x2f, 0x9d, 0xe5, // ldr r2, [sp, #4095]
+                0x08, 0x30, 0xb4, 0xe5, // ldr r3, [r4, #8]!
+                0x08, 0x50, 0x16, 0xe4, // ldr r5, [r6], #-8
+                0x08, 0x70, 0x9f, 0xe5, // ldr r7, [pc, #8]
+                0x04, 0x20, 0x2d, 0xe5, // str r2, [sp, #-4]!
+                0x00, 0x81, 0x89, 0xe5, // str r8, [r9, #0x100]
+                0x01, 0x30, 0xc4, 0xe5, // strb r3, [r4, #1]
+                0x01, 0x50, 0xd6, 0xe4, // ldrb r5, [r6], #1
+                0x00, 0x00, 0x91, 0x35, // ldrlo r0, [r1]
+                0x10, 0x40, 0x2d, 0xe9, // push {r4, lr}
+                0x04, 0x00, 0x2d, 0xe5, // push {r0}
+                0x30, 0x0c, 0x2d, 0xe9, // push {r4, r5, r10, r11}
+                0x10, 0x80, 0xbd, 0xe8, // pop {r4, pc}
+                0x04, 0x00, 0x9d, 0xe4, // pop {r0}
+                0x03, 0x00, 0xbd, 0x08, // popeq {r0, r1}
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thumb_codegen_data_processing() {
+        let mut codegen = ArmAsm::new();
+        codegen.set_mode(Mode::Thumb);
+        codegen.add(Reg(R0), Reg(R1), Reg(R2)).unwrap();
+        codegen.add(Reg(R8), Reg(R9), Imm(0xff)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0x00ab_00ab)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0xab00_ab00)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0xabab_abab)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0x3fc)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(0x123)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(-0x123)).unwrap();
+        codegen.add(Reg(R0), Reg(R1), Imm(-4)).unwrap();
+        codegen.sub(Reg(Sp), Reg(Sp), Imm(8)).unwrap();
+        codegen.adds(Reg(R3), Reg(R4), Imm(1)).unwrap();
+        codegen.subs(Reg(R0), Reg(R0), Reg(R1)).unwrap();
+        codegen.rsb(Reg(R0), Reg(R1), Imm(0)).unwrap();
+        codegen.and(Reg(R0), Reg(R0), Imm(0xffff_ff00)).unwrap();
+        codegen.orr(Reg(R0), Reg(R1), Reg(R2)).unwrap();
+        codegen.eor(Reg(R4), Reg(R5), Imm(0x8000_0000)).unwrap();
+        codegen.cmp(Reg(R0), Imm(1)).unwrap();
+        codegen.cmp(Reg(R0), Imm(-1)).unwrap();
+        codegen.cmp(Reg(R10), Reg(R11)).unwrap();
+        codegen.tst(Reg(R0), Imm(1)).unwrap();
+        codegen.mov(Reg(R0), Reg(R1)).unwrap();
+        codegen.mov(Reg(R0), Imm(0xff)).unwrap();
+        codegen.mov(Reg(R0), Imm(-1)).unwrap();
+        codegen.mvn(Reg(R2), Reg(R3)).unwrap();
+        codegen.mov(Reg(R1), Imm(0x1234_5678)).unwrap();
+        codegen.mul(Reg(R0), Reg(R1), Reg(R2)).unwrap();
+        codegen
+            .cond(Cond::Eq)
+            .add(Reg(R0), Reg(R0), Imm(1))
+            .unwrap();
+        codegen.cond(Cond::Gt).mov(Reg(R0), Reg(R1)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x01, 0xeb, 0x02, 0x00, // add.w r0, r1, r2
+                0x09, 0xf1, 0xff, 0x08, // add.w r8, r9, #0xff
+                0x01, 0xf1, 0xab, 0x10, // add.w r0, r1, #0x00ab00ab
+                0x01, 0xf1, 0xab, 0x20, // add.w r0, r1, #0xab00ab00
+                0x01, 0xf1, 0xab, 0x30, // add.w r0, r1, #0xabababab
+                0x01, 0xf5, 0x7f, 0x70, // add.w r0, r1, #0x3fc
+                0x01, 0xf2, 0x23, 0x10, // addw r0, r1, #0x123
+                0xa1, 0xf2, 0x23, 0x10, // subw r0, r1, #0x123
+                0xa1, 0xf1, 0x04, 0x00, // sub.w r0, r1, #4
+                0xad, 0xf1, 0x08, 0x0d, // sub.w sp, sp, #8
+                0x14, 0xf1, 0x01, 0x03, // adds.w r3, r4, #1
+                0xb0, 0xeb, 0x01, 0x00, // subs.w r0, r0, r1
+                0xc1, 0xf1, 0x00, 0x00, // rsb.w r0, r1, #0
+                0x20, 0xf0, 0xff, 0x00, // bic r0, r0, #0xff
+                0x41, 0xea, 0x02, 0x00, // orr.w r0, r1, r2
+                0x85, 0xf0, 0x00, 0x44, // eor r4, r5, #0x80000000
+                0xb0, 0xf1, 0x01, 0x0f, // cmp.w r0, #1
+                0xb0, 0xf1, 0xff, 0x3f, // cmp.w r0, #0xffffffff
+                0xba, 0xeb, 0x0b, 0x0f, // cmp.w r10, r11
+                0x10, 0xf0, 0x01, 0x0f, // tst.w r0, #1
+                0x4f, 0xea, 0x01, 0x00, // mov.w r0, r1
+                0x4f, 0xf0, 0xff, 0x00, // mov.w r0, #0xff
+                0x4f, 0xf0, 0xff, 0x30, // mov.w r0, #0xffffffff
+                0x6f, 0xea, 0x03, 0x02, // mvn.w r2, r3
+                0x45, 0xf2, 0x78, 0x61, // movw r1, #0x5678
+                0xc1, 0xf2, 0x34, 0x21, // movt r1, #0x1234
+                0x01, 0xfb, 0x02, 0xf0, // mul r0, r1, r2
+                0x08, 0xbf, // it eq
+                0x00, 0xf1, 0x01, 0x00, // addeq.w r0, r0, #1
+                0xc8, 0xbf, // it gt
+                0x4f, 0xea, 0x01, 0x00, // movgt.w r0, r1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thumb_codegen_load_store() {
+        let mut codegen = ArmAsm::new();
+        codegen.set_mode(Mode::Thumb);
+        codegen.ldr(Reg(R0), Mem::new(R1, 0)).unwrap();
+        codegen.ldr(Reg(R2), Mem::new(Sp, 4095)).unwrap();
+        codegen.ldr(Reg(R0), Mem::new(R1, -4)).unwrap();
+        codegen.ldr(Reg(R3), Mem::pre(R4, 8)).unwrap();
+        codegen.ldr(Reg(R5), Mem::post(R6, -8)).unwrap();
+        codegen.str(Reg(R2), Mem::pre(Sp, -4)).unwrap();
+        codegen.str(Reg(R8), Mem::new(R9, 0x100)).unwrap();
+        codegen.strb(Reg(R3), Mem::new(R4, 1)).unwrap();
+        codegen.ldrb(Reg(R5), Mem::post(R6, 1)).unwrap();
+        codegen
+            .cond(Cond::Lo)
+            .ldr(Reg(R0), Mem::new(R1, 0))
+            .unwrap();
+        codegen.push(&[R4, Lr]).unwrap();
+        codegen.push(&[R0]).unwrap();
+        codegen.pop(&[R4, Pc]).unwrap();
+        codegen.pop(&[R8]).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0xd1, 0xf8, 0x00, 0x00, // ldr.w r0, [r1]
+                0xdd, 0xf8, 0xff, 0x2f, // ldr.w r2, [sp, #4095]
+                0x51, 0xf8, 0x04, 0x0c, // ldr r0, [r1, #-4]
+                0x54, 0xf8, 0x08, 0x3f, // ldr r3, [r4, #8]!
+                0x56, 0xf8, 0x08, 0x59, // ldr r5, [r6], #-8
+                0x4d, 0xf8, 0x04, 0x2d, // str r2, [sp, #-4]!
+                0xc9, 0xf8, 0x00, 0x81, // str.w r8, [r9, #0x100]
+                0x84, 0xf8, 0x01, 0x30, // strb.w r3, [r4, #1]
+                0x16, 0xf8, 0x01, 0x5b, // ldrb r5, [r6], #1
+                0x38, 0xbf, // it lo
+                0xd1, 0xf8, 0x00, 0x00, // ldrlo.w r0, [r1]
+                0x2d, 0xe9, 0x10, 0x40, // push.w {r4, lr}
+                0x4d, 0xf8, 0x04, 0x0d, // str r0, [sp, #-4]!
+                0xbd, 0xe8, 0x10, 0x80, // pop.w {r4, pc}
+                0x5d, 0xf8, 0x04, 0x8b, // ldr r8, [sp], #4
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arm_codegen_branches() {
+        let mut codegen = ArmAsm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+
+        codegen.bind_label(start).unwrap();
+        codegen.b(end).unwrap();
+        codegen.cond(Cond::Ne).b(start).unwrap();
+        codegen.bl(end).unwrap();
+        codegen.cond(Cond::Lt).bl(start).unwrap();
+        codegen.blx(Reg(R3)).unwrap();
+        codegen.bind_label(end).unwrap();
+        codegen.bx(Reg(Lr)).unwrap();
+
+        assert!(!codegen.has_unbound_references());
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x03, 0x00, 0x00, 0xea, // b end
+                0xfd, 0xff, 0xff, 0x1a, // bne start
+                0x01, 0x00, 0x00, 0xeb, // bl end
+                0xfb, 0xff, 0xff, 0xbb, // bllt start
+                0x33, 0xff, 0x2f, 0xe1, // blx r3
+                0x1e, 0xff, 0x2f, 0xe1, // bx lr
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thumb_codegen_branches() {
+        let mut codegen = ArmAsm::new();
+        codegen.set_mode(Mode::Thumb);
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+
+        codegen.bind_label(start).unwrap();
+        codegen.b(end).unwrap();
+        codegen.cond(Cond::Ne).b(start).unwrap();
+        codegen.bl(end).unwrap();
+        codegen.cond(Cond::Lt).bl(start).unwrap();
+        codegen.blx(Reg(R3)).unwrap();
+        codegen.bind_label(end).unwrap();
+        codegen.cond(Cond::Eq).bx(Reg(Lr)).unwrap();
+        codegen.bx(Reg(Lr)).unwrap();
+
+        assert!(!codegen.has_unbound_references());
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x00, 0xf0, 0x08, 0xb8, // b.w end
+                0x7f, 0xf4, 0xfc, 0xaf, // bne.w start
+                0x00, 0xf0, 0x04, 0xf8, // bl end
+                0xb8, 0xbf, // it lt
+                0xff, 0xf7, 0xf7, 0xff, // bllt start
+                0x98, 0x47, // blx r3
+                0x08, 0xbf, // it eq
+                0x70, 0x47, // bxeq lr
+                0x70, 0x47, // bx lr
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arm_codegen_mode_switch() {
+        let mut codegen = ArmAsm::new();
+        codegen.mov(Reg(R0), Imm(1)).unwrap();
+        codegen.set_mode(Mode::Thumb);
+        assert_eq!(codegen.mode(), Mode::Thumb);
+        codegen.bx(Reg(Lr)).unwrap();
+        codegen.set_mode(Mode::Arm);
+        codegen.bx(Reg(Lr)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x01, 0x00, 0xa0, 0xe3, // mov r0, #1
+                0x70, 0x47, // bx lr
+                0x00, 0xbf, // nop
+                0x1e, 0xff, 0x2f, 0xe1, // bx lr
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arm_codegen_invalid_operands() {
+        let mut codegen = ArmAsm::new();
+
+        assert!(matches!(
+            codegen.add(Imm(1), Reg(R1), Reg(R2)),
+            Err(EncodeError::InvalidOperands { .. })
+        ));
+        assert_eq!(
+            codegen.add(Reg(R0), Reg(R1), Imm(0x101)),
+            Err(EncodeError::ImmediateOutOfRange(0x101))
+        );
+        assert!(codegen.mov(Reg(R0), Imm(0x1_0000_0000)).is_err());
+        assert!(codegen.ldr(Reg(R0), Mem::new(R1, 4096)).is_err());
+        assert!(codegen.push(&[]).is_err());
+        // A failed instruction still consumes the condition.
+        assert!(codegen
+            .cond(Cond::Eq)
+            .orr(Reg(R0), Reg(R1), Imm(0x101))
+            .is_err());
+        assert_eq!(codegen.cond, Cond::Al);
+        assert!(codegen.code().is_empty());
+
+        codegen.set_mode(Mode::Thumb);
+        assert!(matches!(
+            codegen.add(Reg(R0), Reg(Pc), Reg(R1)),
+            Err(EncodeError::UnsupportedRegister(_))
+        ));
+        assert!(codegen.orr(Reg(R0), Reg(R1), Imm(0x101)).is_err());
+        assert!(codegen.adds(Reg(R0), Reg(R1), Imm(0x123)).is_err());
+        assert!(codegen.ldr(Reg(R0), Mem::new(R1, -256)).is_err());
+        assert!(codegen.ldr(Reg(R0), Mem::pre(R1, 256)).is_err());
+        assert!(codegen.ldr(Reg(R0), Mem::new(Pc, 0)).is_err());
+        assert!(codegen.push(&[R0, Sp]).is_err());
+        assert!(codegen.pop(&[Lr, Pc]).is_err());
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_thumb_codegen_branch_out_of_range() {
+        let mut codegen = ArmAsm::new();
+        codegen.set_mode(Mode::Thumb);
+        let label = codegen.new_label();
+        codegen.bind_label(label).unwrap();
+        for _ in 0..(1 << 18) {
+            codegen.mov(Reg(R0), Reg(R0)).unwrap();
+        }
+
+        let len = codegen.code().len();
+        assert_eq!(
+            codegen.cond(Cond::Eq).b(label),
+            Err(EncodeError::LabelOutOfRange(label))
+        );
+        assert_eq!(codegen.code().len(), len);
+        codegen.b(label).unwrap();
+    }
+}
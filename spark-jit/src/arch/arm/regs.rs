@@ -0,0 +1,29 @@
+/// General-purpose registers.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    R0 = 0,
+    R1 = 1,
+    R2 = 2,
+    R3 = 3,
+    R4 = 4,
+    R5 = 5,
+    R6 = 6,
+    R7 = 7,
+    R8 = 8,
+    R9 = 9,
+    R10 = 10,
+    R11 = 11,
+    R12 = 12,
+    Sp = 13,
+    Lr = 14,
+    Pc = 15,
+}
+
+#[allow(non_upper_case_globals)]
+impl Reg {
+    /// Frame pointer in A32 code.
+    pub const Fp: Reg = Reg::R11;
+    /// Intra-procedure-call scratch register.
+    pub const Ip: Reg = Reg::R12;
+}
@@ -1,43 +1,505 @@
-use crate::writer::Writer;
+use super::{Assembler, EncodeError};
+use crate::label::{Fixup, Label, LabelTable};
+use crate::writer::{Constant, Writer};
+
+mod disasm;
+pub mod instructions;
+mod macros;
+mod parser;
+mod regs;
+#[cfg(test)]
+mod verify;
+
+pub use disasm::{Disassembly, Instruction};
+pub use parser::{assemble, ParseError, ParseErrorKind};
+pub use regs::{Reg16, Reg32, Reg64, Reg8, Xmm, Ymm};
+
+/// The recommended NOP of every length, up to the longest one: `NOPS[n - 1]` is `n` bytes
+/// long. Apart from `0x90`, they are `nop r/m` with operand size prefixes and displacements.
+const NOPS: [&[u8]; 9] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0f, 0x1f, 0x00],
+    &[0x0f, 0x1f, 0x40, 0x00],
+    &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
 
 #[derive(Default)]
 pub struct X86Asm {
     writer: Writer,
+    labels: LabelTable<FixupKind>,
+    /// Every emitted instruction, sorted by offset.
+    insns: Vec<InsnInfo>,
+    /// References to symbols that are resolved by a [`Module`](crate::module::Module).
+    symbol_refs: Vec<SymbolRef>,
 }
 
-#[allow(unused)]
-#[derive(Debug, Clone, Copy)]
-pub enum Reg64 {
-    Rax = 0,
-    Rcx = 1,
-    Rdx = 2,
-    Rbx = 3,
-    Rsp = 4,
-    Rbp = 5,
-    Rsi = 6,
-    Rdi = 7,
-    R8 = 8,
-    R9 = 9,
-    R10 = 10,
-    R11 = 11,
-    R12 = 12,
-    R13 = 13,
-    R14 = 14,
-    R15 = 15,
+/// How a field refers to its symbol, see [`SymbolRef`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelocKind {
+    /// The 64-bit address of the symbol, e.g. the immediate of `mov r64, imm64`.
+    Abs64,
+    /// The 32-bit distance from the end of the field to the symbol, e.g. the target
+    /// of `call rel32`.
+    Rel32,
+    /// The 32-bit displacement of a `[rip + symbol]` operand. The field ends the
+    /// instruction, so the distance is also from the end of the field.
+    Rip32,
+}
+
+/// A field that refers to a symbol, see [`X86Asm::symbol_refs`]. The field is left zero
+/// until the code is linked, so the code doesn't depend on where it, or the symbol,
+/// is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRef {
+    /// Offset of the field in the code.
+    pub offset: usize,
+    pub kind: RelocKind,
+    /// Name of the referenced symbol.
+    pub symbol: String,
+}
+
+impl SymbolRef {
+    /// Length of the field in bytes.
+    pub fn size(&self) -> usize {
+        match self.kind {
+            RelocKind::Abs64 => 8,
+            RelocKind::Rel32 | RelocKind::Rip32 => 4,
+        }
+    }
+
+    /// Compute the bytes of the field, for code loaded at `base` and a symbol at
+    /// `target`, or `None` if a 32-bit field can't reach the symbol.
+    pub fn resolve(&self, base: usize, target: usize) -> Option<Vec<u8>> {
+        match self.kind {
+            RelocKind::Abs64 => Some((target as u64).to_le_bytes().to_vec()),
+            RelocKind::Rel32 | RelocKind::Rip32 => {
+                let end = base + self.offset + self.size();
+                let rel = i32::try_from(target as i64 - end as i64).ok()?;
+                Some(rel.to_le_bytes().to_vec())
+            }
+        }
+    }
+}
+
+/// Where an instruction was emitted, see [`X86Asm::instructions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsnInfo {
+    /// Offset of the first byte of the instruction in the code.
+    pub offset: usize,
+    /// Length of the instruction in bytes.
+    pub len: usize,
+    /// Name of the `X86Asm` method that emitted the instruction, e.g. `mov` or `jcc`.
+    pub mnemonic: &'static str,
+    /// Condition of `jcc`, `setcc` and `cmovcc`.
+    pub cond: Option<Cond>,
+}
+
+impl InsnInfo {
+    /// Offset right after the instruction, where execution continues if it doesn't branch.
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum Operand {
     Reg(Reg64),
+    Reg32(Reg32),
+    Reg16(Reg16),
+    Reg8(Reg8),
+    Xmm(Xmm),
+    Ymm(Ymm),
     Imm64(i64),
     Imm32(i32),
     Imm16(i16),
     Imm8(i8),
     MemDisp(Reg64, i32),
     MemAbs(Reg64),
+    Mem(Mem),
+    Label(Label),
+}
+
+/// Size of an operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+/// Scale factor of the index register in a memory operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    X1 = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+}
+
+/// Base of a memory operand.
+#[derive(Debug, Clone, Copy)]
+enum Base {
+    Reg(Reg64),
+    Rip,
+    /// RIP-relative reference to a label.
+    Label(Label),
+    /// RIP-relative reference to a constant of the pool.
+    Constant(Constant),
+    None,
+}
+
+/// A memory operand with an explicit size: `size ptr [base + index * scale + disp]`.
+///
+/// The size of [`Operand::MemDisp`] is implied by the other operand (and defaults to 64 bits),
+/// while a sized memory operand also works where no register fixes the operand size,
+/// e.g. `mov word ptr [rdi], 0x1234`.
+#[derive(Debug, Clone, Copy)]
+pub struct Mem {
+    size: Option<Size>,
+    base: Base,
+    index: Option<(Reg64, Scale)>,
+    disp: i32,
+}
+
+impl Mem {
+    /// `size ptr [base + disp]`
+    pub fn new(size: Size, base: Reg64, disp: i32) -> Self {
+        Self {
+            size: Some(size),
+            base: Base::Reg(base),
+            index: None,
+            disp,
+        }
+    }
+
+    /// `byte ptr [base + disp]`
+    pub fn byte(base: Reg64, disp: i32) -> Self {
+        Self::new(Size::Byte, base, disp)
+    }
+
+    /// `word ptr [base + disp]`
+    pub fn word(base: Reg64, disp: i32) -> Self {
+        Self::new(Size::Word, base, disp)
+    }
+
+    /// `dword ptr [base + disp]`
+    pub fn dword(base: Reg64, disp: i32) -> Self {
+        Self::new(Size::Dword, base, disp)
+    }
+
+    /// `qword ptr [base + disp]`
+    pub fn qword(base: Reg64, disp: i32) -> Self {
+        Self::new(Size::Qword, base, disp)
+    }
+
+    /// `size ptr [disp]`: an absolute address without a base register.
+    pub fn abs(size: Size, disp: i32) -> Self {
+        Self {
+            size: Some(size),
+            base: Base::None,
+            index: None,
+            disp,
+        }
+    }
+
+    /// `size ptr [rip + disp]`: an address relative to the end of the instruction.
+    pub fn rip(size: Size, disp: i32) -> Self {
+        Self {
+            size: Some(size),
+            base: Base::Rip,
+            index: None,
+            disp,
+        }
+    }
+
+    /// `size ptr [rip + label]`: a reference to a label, e.g. a jump table or data
+    /// emitted after the code.
+    pub fn label(size: Size, label: Label) -> Self {
+        Self {
+            size: Some(size),
+            base: Base::Label(label),
+            index: None,
+            disp: 0,
+        }
+    }
+
+    /// `size ptr [rip + constant]`: a reference to a literal of the constant pool,
+    /// see [`X86Asm::add_constant`].
+    pub fn constant(size: Size, constant: Constant) -> Self {
+        Self {
+            size: Some(size),
+            base: Base::Constant(constant),
+            index: None,
+            disp: 0,
+        }
+    }
+
+    /// Add a scaled index register, e.g. `[base + index * 8 + disp]`. RSP can't be used as
+    /// an index, and RIP-relative operands can't have one.
+    pub fn with_index(self, index: Reg64, scale: Scale) -> Self {
+        Self {
+            index: Some((index, scale)),
+            ..self
+        }
+    }
+
+    /// `[base + disp]`: a memory operand whose size is implied by the other operand, like
+    /// [`Operand::MemDisp`]. Vector instructions take such operands for 128 and 256-bit memory.
+    pub fn implied(base: Reg64, disp: i32) -> Self {
+        Self {
+            size: None,
+            base: Base::Reg(base),
+            index: None,
+            disp,
+        }
+    }
+
+    /// Check that the operand can be encoded.
+    fn validate(&self) -> Result<(), EncodeError> {
+        match (self.base, self.index) {
+            (_, Some((Reg64::Rsp, _))) => Err(EncodeError::UnsupportedRegister(
+                "RSP can't be used as an index register",
+            )),
+            (Base::Rip | Base::Label(_) | Base::Constant(_), Some(_)) => {
+                Err(EncodeError::UnsupportedRegister(
+                    "RIP-relative operands can't have an index register",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of the base register, as needed for REX.B.
+    fn base_num(&self) -> u8 {
+        match self.base {
+            Base::Reg(base) => base as u8,
+            Base::Rip | Base::Label(_) | Base::Constant(_) | Base::None => 0,
+        }
+    }
+
+    /// Number of the index register, as needed for REX.X.
+    fn index_num(&self) -> u8 {
+        self.index.map_or(0, |(index, _)| index as u8)
+    }
+}
+
+/// A general-purpose register of any size, as seen by the encoder.
+#[derive(Debug, Clone, Copy)]
+struct Gpr {
+    /// Register number (0-15).
+    num: u8,
+    size: Size,
+    /// One of AH, CH, DH or BH.
+    high_byte: bool,
+}
+
+impl Gpr {
+    /// SPL, BPL, SIL and DIL are only addressable with a REX prefix, even an empty one.
+    fn needs_rex(&self) -> bool {
+        self.size == Size::Byte && !self.high_byte && (4..8).contains(&self.num)
+    }
+}
+
+/// The operand addressed by the ModRM.rm field.
+#[derive(Debug, Clone, Copy)]
+enum Rm {
+    Reg(Gpr),
+    Xmm(Xmm),
+    Ymm(Ymm),
+    Mem(Mem),
+}
+
+/// The contents of the ModRM.reg field: either a register or an opcode extension.
+#[derive(Debug, Clone, Copy)]
+enum RegField {
+    Reg(Gpr),
+    Xmm(Xmm),
+    Slash(u8),
+}
+
+impl Operand {
+    fn gpr(&self) -> Option<Gpr> {
+        let (num, size) = match *self {
+            Operand::Reg(reg) => (reg as u8, Size::Qword),
+            Operand::Reg32(reg) => (reg as u8, Size::Dword),
+            Operand::Reg16(reg) => (reg as u8, Size::Word),
+            Operand::Reg8(reg) => (reg as u8, Size::Byte),
+            _ => return None,
+        };
+
+        Some(Gpr {
+            num: num & 0xf,
+            size,
+            high_byte: num & 0x10 != 0,
+        })
+    }
+
+    fn mem(&self) -> Option<Mem> {
+        match *self {
+            Operand::MemDisp(base, disp) => Some(Mem::implied(base, disp)),
+            Operand::MemAbs(base) => Some(Mem::implied(base, 0)),
+            Operand::Mem(mem) => Some(mem),
+            _ => None,
+        }
+    }
+
+    fn rm(&self) -> Option<Rm> {
+        self.gpr().map(Rm::Reg).or_else(|| self.mem().map(Rm::Mem))
+    }
+
+    /// Memory operand of an instruction that always operates on 64 bits, such as `push`
+    /// or `call`: either unsized or a qword.
+    fn mem64(&self) -> Option<Mem> {
+        self.mem()
+            .filter(|mem| matches!(mem.size, None | Some(Size::Qword)))
+    }
+
+    fn xmm(&self) -> Option<Xmm> {
+        match *self {
+            Operand::Xmm(xmm) => Some(xmm),
+            _ => None,
+        }
+    }
+
+    /// Number of an XMM or YMM register, and whether it's a YMM register.
+    fn vec(&self) -> Option<(u8, bool)> {
+        match *self {
+            Operand::Xmm(xmm) => Some((xmm as u8, false)),
+            Operand::Ymm(ymm) => Some((ymm as u8, true)),
+            _ => None,
+        }
+    }
+
+    fn imm(&self) -> Option<i64> {
+        match *self {
+            Operand::Imm64(imm) => Some(imm),
+            Operand::Imm32(imm) => Some(imm as i64),
+            Operand::Imm16(imm) => Some(imm as i64),
+            Operand::Imm8(imm) => Some(imm as i64),
+            _ => None,
+        }
+    }
+
+    /// Size of the operand, if it has one. Immediates and unsized memory operands don't.
+    fn size(&self) -> Option<Size> {
+        match self.rm()? {
+            Rm::Reg(gpr) => Some(gpr.size),
+            Rm::Xmm(_) | Rm::Ymm(_) => None,
+            Rm::Mem(mem) => mem.size,
+        }
+    }
+}
+
+/// Condition codes, as encoded in the low nibble of `jcc`, `setcc` and `cmovcc` opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    O = 0x0,
+    No = 0x1,
+    B = 0x2,
+    Ae = 0x3,
+    E = 0x4,
+    Ne = 0x5,
+    Be = 0x6,
+    A = 0x7,
+    S = 0x8,
+    Ns = 0x9,
+    P = 0xa,
+    Np = 0xb,
+    L = 0xc,
+    Ge = 0xd,
+    Le = 0xe,
+    G = 0xf,
+}
+
+#[allow(non_upper_case_globals)]
+impl Cond {
+    pub const Z: Cond = Cond::E;
+    pub const Nz: Cond = Cond::Ne;
+    pub const C: Cond = Cond::B;
+    pub const Nc: Cond = Cond::Ae;
+    pub const Nae: Cond = Cond::B;
+    pub const Nb: Cond = Cond::Ae;
+    pub const Na: Cond = Cond::Be;
+    pub const Nbe: Cond = Cond::A;
+    pub const Pe: Cond = Cond::P;
+    pub const Po: Cond = Cond::Np;
+    pub const Nge: Cond = Cond::L;
+    pub const Nl: Cond = Cond::Ge;
+    pub const Ng: Cond = Cond::Le;
+    pub const Nle: Cond = Cond::G;
+
+    /// Get the opposite condition (e.g. `Ne` for `E`).
+    pub fn negate(self) -> Cond {
+        use Cond::*;
+
+        match self {
+            O => No,
+            No => O,
+            B => Ae,
+            Ae => B,
+            E => Ne,
+            Ne => E,
+            Be => A,
+            A => Be,
+            S => Ns,
+            Ns => S,
+            P => Np,
+            Np => P,
+            L => Ge,
+            Ge => L,
+            Le => G,
+            G => Le,
+        }
+    }
+}
+
+/// Size of a label-relative field that is patched once the label is bound.
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    Rel8,
+    Rel32,
+    /// RIP-relative displacement followed by an immediate of the given length.
+    Disp32(u8),
+}
+
+/// Opcode maps selected by the `mmmmm` field of a VEX prefix.
+#[derive(Debug, Clone, Copy)]
+enum VexMap {
+    X0F = 0b00001,
+    X0F38 = 0b00010,
+}
+
+/// The fields of a VEX prefix that are fixed by the instruction.
+#[derive(Debug, Clone, Copy)]
+struct Vex {
+    /// The mandatory prefix the VEX prefix replaces: none, `0x66`, `0xf3` or `0xf2`.
+    prefix: Option<u8>,
+    map: VexMap,
+    w: bool,
+    /// 256-bit vector length.
+    l: bool,
+}
+
+impl Vex {
+    /// The 128-bit VEX prefix of a packed double or packed integer instruction, which
+    /// replaces the `0x66` prefix. Set `l` for the 256-bit form.
+    fn pd(map: VexMap, w: bool) -> Self {
+        Self {
+            prefix: Some(0x66),
+            map,
+            w,
+            l: false,
+        }
+    }
 }
 
-#[allow(dead_code)]
+#[derive(Clone, Copy)]
 enum ModRM {
     Mem = 0b00,
     Reg = 0b11,
@@ -49,339 +511,2289 @@ impl X86Asm {
     pub fn new() -> Self {
         Self {
             writer: Writer::new(),
+            labels: LabelTable::default(),
+            insns: Vec::new(),
+            symbol_refs: Vec::new(),
         }
     }
 
-    fn emit_rex_oi(&mut self, arg: Operand, w: u8) {
-        self.emit_rex_slash(arg, w);
+    /// Create a new label. The label must be bound with [`X86Asm::bind_label`] before the code
+    /// is executed, but it can be referenced by jumps, calls and `lea` at any time.
+    pub fn new_label(&mut self) -> Label {
+        self.labels.create()
     }
 
-    #[allow(clippy::identity_op)]
-    fn emit_rex_slash(&mut self, arg: Operand, w: u8) {
-        match arg {
-            Operand::Reg(reg) | Operand::MemDisp(reg, _) => {
-                if w == 0 && (reg as u8) < 8 {
-                    return;
-                }
-                let is_b = ((reg as u8) >= 8) as u8;
-                let rex = 0b0100_0000 | (w << 3) | (0 << 2) | (0 << 1) | (is_b << 0);
-                self.writer.emit8(rex);
-            }
-            _ => unimplemented!(),
+    /// Bind the label to the current offset and patch every pending reference to it.
+    ///
+    /// Fails if the label is already bound or if a short reference can't reach the label.
+    pub fn bind_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        let offset = self.offset();
+        for fixup in self.labels.bind(label, offset)? {
+            self.patch_fixup(fixup, offset)?;
         }
+        Ok(())
     }
 
-    #[allow(clippy::identity_op)]
-    fn emit_rex_mr(&mut self, dst: Operand, src: Operand, w: u8) {
-        match (dst, src) {
-            (Operand::Reg(dst_reg), Operand::Reg(src_reg))
-                if (dst_reg as u8) < 8 && (src_reg as u8) < 8 && w == 0 => {}
-            (Operand::Reg(dst_reg), Operand::Reg(src_reg))
-            | (Operand::MemDisp(dst_reg, _), Operand::Reg(src_reg)) => {
-                let is_b = (dst_reg as u8 >= 8) as u8;
-                let is_r = (src_reg as u8 >= 8) as u8;
-                let rex = 0b0100_0000 | (w << 3) | (is_r << 2) | (0 << 1) | (is_b << 0);
-                self.writer.emit8(rex);
-            }
-            _ => unimplemented!(),
-        };
+    /// Get the offset the label is bound to, if any.
+    pub fn label_offset(&self, label: Label) -> Option<usize> {
+        self.labels.offset(label)
     }
 
-    #[allow(clippy::identity_op)]
-    fn emit_rex_rm(&mut self, dst: Operand, src: Operand, w: u8) {
-        match (dst, src) {
-            (Operand::Reg(dst_reg), Operand::Reg(src_reg))
-                if (dst_reg as u8) < 8 && (src_reg as u8) < 8 => {}
-            (Operand::Reg(dst_reg), Operand::Reg(src_reg))
-            | (Operand::Reg(dst_reg), Operand::MemDisp(src_reg, _)) => {
-                let is_b = (src_reg as u8 >= 8) as u8;
-                let is_r = (dst_reg as u8 >= 8) as u8;
-                let rex = 0b0100_0000 | (w << 3) | (is_r << 2) | (0 << 1) | (is_b << 0);
-                self.writer.emit8(rex);
-            }
-            _ => unimplemented!(),
-        };
+    /// Check whether some emitted instructions still reference unbound labels.
+    pub fn has_unbound_references(&self) -> bool {
+        self.labels.has_pending()
     }
 
-    fn emit_modrm(&mut self, modrm_low: u8, rm: Operand) {
-        match rm {
-            Operand::Reg(_) => {
-                self.writer.emit8(((ModRM::Reg as u8) << 6) | modrm_low);
-            }
-            Operand::MemDisp(_, offset) => {
-                self.writer
-                    .emit8(((ModRM::MemDisp32 as u8) << 6) | modrm_low);
-                self.writer.emit32(offset as u32);
-            }
-            _ => unimplemented!(),
+    /// Current offset in the code buffer.
+    pub fn offset(&self) -> usize {
+        self.code().len()
+    }
+
+    /// Emit `len` bytes of NOPs, with as few instructions as possible. NOPs are at most
+    /// 9 bytes long, so longer padding is split into several of them.
+    pub fn nop(&mut self, len: usize) -> Result<(), EncodeError> {
+        for nop in Self::nops(len) {
+            self.record("nop", None, |asm| {
+                nop.iter().for_each(|&byte| asm.writer.emit8(byte));
+                Ok(())
+            })?;
         }
+        Ok(())
     }
 
-    fn emit_modrm_rm(&mut self, dst: Reg64, src: Operand) {
-        let src_reg = match src {
-            Operand::Reg(src_reg) => src_reg,
-            Operand::MemDisp(base_reg, _) => base_reg,
-            _ => unimplemented!(),
-        };
+    /// Pad the code with NOPs up to a multiple of `align`, which must be a power of two,
+    /// e.g. to align a loop head or a function entry to 16 or 32 bytes.
+    pub fn align(&mut self, align: usize) -> Result<(), EncodeError> {
+        let mut offset = self.offset();
+        self.writer.align(align, &NOPS)?;
+
+        // The writer pads with the same NOPs as `nop`, record them one by one.
+        for nop in Self::nops(self.offset() - offset) {
+            self.insns.push(InsnInfo {
+                offset,
+                len: nop.len(),
+                mnemonic: "nop",
+                cond: None,
+            });
+            offset += nop.len();
+        }
+        Ok(())
+    }
 
-        let modrm_low = (Self::encode_reg(dst) << 3) | Self::encode_reg(src_reg);
-        self.emit_modrm(modrm_low, src);
+    /// The NOPs that make up `len` bytes of padding, longest first.
+    fn nops(len: usize) -> impl Iterator<Item = &'static [u8]> {
+        let mut remaining = len;
+        std::iter::from_fn(move || {
+            (remaining > 0).then(|| {
+                let nop = NOPS[remaining.min(NOPS.len()) - 1];
+                remaining -= nop.len();
+                nop
+            })
+        })
     }
 
-    fn emit_modrm_mr(&mut self, dst: Operand, src: Reg64) {
-        let dst_reg = match dst {
-            Operand::Reg(dst_reg) => dst_reg,
-            Operand::MemDisp(base_reg, _) => base_reg,
-            _ => unimplemented!(),
-        };
+    /// Add an 8, 16 or 32-byte literal to the constant pool, to be referenced with
+    /// [`Mem::constant`]. The pool is placed after the code by [`X86Asm::finalize`].
+    pub fn add_constant(&mut self, bytes: &[u8]) -> Result<Constant, EncodeError> {
+        self.writer.add_constant(bytes)
+    }
 
-        let modrm_low = (Self::encode_reg(src) << 3) | Self::encode_reg(dst_reg);
-        self.emit_modrm(modrm_low, dst);
+    /// Call a function by name. The displacement is left zero until the code is linked
+    /// into a [`Module`](crate::module::Module) that defines the symbol.
+    pub fn call_symbol(&mut self, symbol: &str) -> Result<(), EncodeError> {
+        self.record("call", None, |asm| asm.emit_symbol_rel(0xe8, symbol))
     }
 
-    fn emit_modrm_slash(&mut self, slash: u8, rm: Operand) {
-        let reg = match rm {
-            Operand::Reg(dst_reg) => dst_reg,
-            Operand::MemDisp(base_reg, _) => base_reg,
-            _ => unimplemented!(),
-        };
+    /// Jump to a function by name, e.g. for a tail call. See [`X86Asm::call_symbol`].
+    pub fn jmp_symbol(&mut self, symbol: &str) -> Result<(), EncodeError> {
+        self.record("jmp", None, |asm| asm.emit_symbol_rel(0xe9, symbol))
+    }
 
-        let modrm_low = (slash << 3) | Self::encode_reg(reg);
-        self.emit_modrm(modrm_low, rm);
+    /// `lea dst, [rip + symbol]`: load the address of a symbol relative to the code.
+    pub fn lea_symbol(&mut self, dst: Reg64, symbol: &str) -> Result<(), EncodeError> {
+        self.record("lea", None, |asm| {
+            asm.emit_lea(Operand::Reg(dst), Operand::Mem(Mem::rip(Size::Qword, 0)))?;
+            // RIP-relative operands always end with a 32-bit displacement.
+            asm.add_symbol_ref(asm.offset() - 4, RelocKind::Rip32, symbol);
+            Ok(())
+        })
     }
 
-    fn encode_reg(reg: Reg64) -> u8 {
-        reg as u8 & 0b111
+    /// `mov dst, symbol`: load the absolute 64-bit address of a symbol.
+    pub fn mov_symbol(&mut self, dst: Reg64, symbol: &str) -> Result<(), EncodeError> {
+        self.record("mov", None, |asm| {
+            let dst = Operand::Reg(dst).gpr().unwrap();
+            asm.emit_op_plus_reg(Size::Qword, &[0xb8], dst)?;
+            asm.add_symbol_ref(asm.offset(), RelocKind::Abs64, symbol);
+            asm.writer.emit64(0);
+            Ok(())
+        })
     }
 
-    pub fn dump_generated_code(&self, base_addr: u64) {
-        use zydis::*;
+    /// Every field that refers to a symbol emitted so far, sorted by offset.
+    pub fn symbol_refs(&self) -> &[SymbolRef] {
+        &self.symbol_refs
+    }
 
-        let fmt = Formatter::intel();
-        let dec = Decoder::new64();
+    fn add_symbol_ref(&mut self, offset: usize, kind: RelocKind, symbol: &str) {
+        self.symbol_refs.push(SymbolRef {
+            offset,
+            kind,
+            symbol: symbol.to_string(),
+        });
+    }
 
-        for inst_info in dec.decode_all::<VisibleOperands>(self.code(), 0) {
-            let (ip, raw_bytes, inst) = inst_info.unwrap();
+    fn emit_symbol_rel(&mut self, opcode: u8, symbol: &str) -> Result<(), EncodeError> {
+        self.writer.emit8(opcode);
+        self.add_symbol_ref(self.offset(), RelocKind::Rel32, symbol);
+        self.writer.emit32(0);
+        Ok(())
+    }
 
-            // We use Some(ip) here since we want absolute addressing based on the given
-            // instruction pointer. If we wanted relative addressing, we'd use `None` instead.
-            println!(
-                "0x{:016X} {:<24} {}",
-                base_addr + ip,
-                hex::encode(raw_bytes),
-                fmt.format(Some(ip), &inst).unwrap()
-            );
-        }
+    /// Alignment the code must be loaded at, so that the constants of the pool are
+    /// aligned to their size.
+    pub fn alignment(&self) -> usize {
+        self.writer.alignment()
     }
 
-    pub fn code(&self) -> &[u8] {
-        self.writer.bytes()
+    /// Place the constant pool after the code emitted so far and patch every reference
+    /// to it. Must be called once the code is complete, before it's executed.
+    pub fn finalize(&mut self) -> Result<(), EncodeError> {
+        self.writer.finalize()
     }
 
-    pub fn neg(&mut self, dst: Operand) {
-        match dst {
-            Operand::Reg(_) => {
-                self.emit_rex_oi(dst, 1);
-                self.writer.emit8(0xf7);
-                self.emit_modrm_slash(3, dst);
+    /// Emit a placeholder for a label-relative displacement. The displacement is
+    /// relative to the end of the instruction, so only [`FixupKind::Disp32`] may be
+    /// followed by other fields.
+    fn emit_label_rel(&mut self, label: Label, kind: FixupKind) -> Result<(), EncodeError> {
+        let fixup = Fixup {
+            label,
+            offset: self.offset(),
+            kind,
+        };
+
+        match kind {
+            FixupKind::Rel8 => self.writer.emit8(0),
+            FixupKind::Rel32 | FixupKind::Disp32(_) => self.writer.emit32(0),
+        }
+
+        match self.labels.offset(label) {
+            Some(target) => self.patch_fixup(fixup, target),
+            None => {
+                self.labels.add_pending(fixup);
+                Ok(())
             }
-            _ => unimplemented!(),
         }
     }
 
-    pub fn add(&mut self, dst: Operand, src: Operand) {
-        match (dst, src) {
-            (Operand::Reg(_), Operand::Reg(src_reg)) => {
-                self.emit_rex_mr(dst, src, 1);
-                self.writer.emit8(0x01);
-                self.emit_modrm_mr(dst, src_reg);
+    fn patch_fixup(&mut self, fixup: Fixup<FixupKind>, target: usize) -> Result<(), EncodeError> {
+        let out_of_range = |_| EncodeError::LabelOutOfRange(fixup.label);
+
+        match fixup.kind {
+            FixupKind::Rel8 => {
+                let rel = target as i64 - (fixup.offset as i64 + 1);
+                let rel = i8::try_from(rel).map_err(out_of_range)?;
+                self.writer.emit8_at(fixup.offset, rel as u8);
             }
-            (Operand::Reg(_), Operand::Imm64(imm)) => {
-                self.emit_rex_slash(dst, 1);
-                self.writer.emit8(0x81);
-                self.emit_modrm_slash(0, dst);
-                self.writer.emit32(imm as u32);
+            FixupKind::Rel32 | FixupKind::Disp32(_) => {
+                let trailing = match fixup.kind {
+                    FixupKind::Disp32(trailing) => trailing as i64,
+                    _ => 0,
+                };
+                let rel = target as i64 - (fixup.offset as i64 + 4 + trailing);
+                let rel = i32::try_from(rel).map_err(out_of_range)?;
+                self.writer.emit32_at(fixup.offset, rel as u32);
             }
-            _ => unimplemented!(),
         }
+        Ok(())
     }
 
-    pub fn sub(&mut self, dst: Operand, src: Operand) {
-        match (dst, src) {
-            (Operand::Reg(_), Operand::Reg(src_reg)) => {
-                self.emit_rex_mr(dst, src, 1);
-                self.writer.emit8(0x29);
-                self.emit_modrm_mr(dst, src_reg);
+    /// Check whether a backward reference to the label fits into rel8, given the
+    /// length of the short instruction form.
+    fn fits_rel8(&self, label: Label, short_len: usize) -> bool {
+        match self.labels.offset(label) {
+            Some(target) => {
+                let rel = target as i64 - (self.offset() + short_len) as i64;
+                i8::try_from(rel).is_ok()
             }
-            (Operand::Reg(_), Operand::Imm64(imm)) => {
-                self.emit_rex_slash(dst, 1);
-                self.writer.emit8(0x81);
-                self.emit_modrm_slash(5, dst);
-                self.writer.emit32(imm as u32);
-            }
-            _ => unimplemented!(),
+            None => false,
         }
     }
 
-    pub fn sbb(&mut self, dst: Operand, src: Operand) {
-        match (dst, src) {
-            (Operand::Reg(_), Operand::Reg(src_reg)) => {
-                self.emit_rex_mr(dst, src, 1);
-                self.writer.emit8(0x19);
-                self.emit_modrm_mr(dst, src_reg);
-            }
-            (Operand::Reg(_), Operand::Imm64(imm)) => {
-                self.emit_rex_slash(dst, 1);
-                self.writer.emit8(0x81);
-                self.emit_modrm_slash(3, dst);
-                self.writer.emit32(imm as u32);
-            }
-            _ => unimplemented!(),
+    /// Build the error for an operand combination the instruction doesn't support.
+    fn invalid(mnemonic: &'static str, operands: &[Operand]) -> EncodeError {
+        EncodeError::InvalidOperands {
+            mnemonic,
+            operands: format!("{:?}", operands),
         }
     }
 
-    pub fn mul(&mut self, src: Operand) {
-        match src {
-            Operand::Reg(_) => {
-                self.emit_rex_oi(src, 1);
-                self.writer.emit8(0xf7);
-                self.emit_modrm_slash(4, src);
-            }
-            _ => unimplemented!(),
+    /// Resolve the operand size of an instruction from its register and memory operands.
+    /// Fails if the operands have different sizes.
+    fn operand_size(mnemonic: &'static str, operands: &[Operand]) -> Result<Size, EncodeError> {
+        let mut sizes = operands.iter().filter_map(Operand::size);
+        let size = sizes.next().unwrap_or(Size::Qword);
+
+        if sizes.any(|other| other != size) {
+            return Err(Self::invalid(mnemonic, operands));
         }
+
+        Ok(size)
     }
 
-    pub fn imul(&mut self, src: Operand) {
-        match src {
-            Operand::Reg(_) => {
-                self.emit_rex_oi(src, 1);
-                self.writer.emit8(0xf7);
-                self.emit_modrm_slash(5, src);
-            }
-            _ => unimplemented!(),
+    /// Emit the operand-size prefix for 16-bit operands.
+    fn emit_opsize_prefix(&mut self, size: Size) {
+        if size == Size::Word {
+            self.writer.emit8(0x66);
         }
     }
 
-    pub fn div(&mut self, src: Operand) {
-        match src {
-            Operand::Reg(_) => {
-                self.emit_rex_oi(src, 1);
-                self.writer.emit8(0xf7);
-                self.emit_modrm_slash(6, src);
-            }
-            _ => unimplemented!(),
+    /// Compute the REX prefix, if any of its bits are set or if one of the byte registers
+    /// requires it. Fails if a REX prefix is needed together with AH, CH, DH or BH.
+    #[allow(clippy::identity_op)]
+    fn rex(w: bool, r: u8, x: u8, b: u8, regs: &[Gpr]) -> Result<Option<u8>, EncodeError> {
+        let (w, r, x, b) = (w as u8, r >> 3, x >> 3, b >> 3);
+        if w | r | x | b == 0 && !regs.iter().any(Gpr::needs_rex) {
+            return Ok(None);
         }
+
+        if regs.iter().any(|reg| reg.high_byte) {
+            return Err(EncodeError::UnsupportedRegister(
+                "AH, CH, DH and BH can't be encoded with a REX prefix",
+            ));
+        }
+
+        Ok(Some(
+            0b0100_0000 | (w << 3) | (r << 2) | (x << 1) | (b << 0),
+        ))
     }
 
-    pub fn idiv(&mut self, src: Operand) {
-        match src {
-            Operand::Reg(_) => {
-                self.emit_rex_oi(src, 1);
-                self.writer.emit8(0xf7);
-                self.emit_modrm_slash(7, src);
+    /// Emit an instruction with a ModRM byte: operand-size prefix, REX prefix, opcode,
+    /// ModRM and displacement. REX.W is set for 64-bit operands.
+    fn emit_op_rm(
+        &mut self,
+        size: Size,
+        opcode: &[u8],
+        reg: RegField,
+        rm: Rm,
+    ) -> Result<(), EncodeError> {
+        self.emit_op_rm_imm(size, opcode, reg, rm, None)
+    }
+
+    /// Same as [`X86Asm::emit_op_rm`], followed by an immediate of the given size. RIP-relative
+    /// displacements depend on the length of the immediate.
+    fn emit_op_rm_imm(
+        &mut self,
+        size: Size,
+        opcode: &[u8],
+        reg: RegField,
+        rm: Rm,
+        imm: Option<(Size, i64)>,
+    ) -> Result<(), EncodeError> {
+        let prefixes: &[u8] = if size == Size::Word { &[0x66] } else { &[] };
+        self.emit_instr(prefixes, size == Size::Qword, opcode, reg, rm, imm)
+    }
+
+    /// Emit an SSE instruction: mandatory prefix, REX prefix, `0x0f` escape, opcode and ModRM.
+    fn emit_sse(
+        &mut self,
+        prefix: u8,
+        w: bool,
+        opcode: u8,
+        reg: RegField,
+        rm: Rm,
+    ) -> Result<(), EncodeError> {
+        self.emit_instr(&[prefix], w, &[0x0f, opcode], reg, rm, None)
+    }
+
+    /// Emit an instruction with a ModRM byte: legacy prefixes, REX prefix, opcode, ModRM,
+    /// displacement and immediate.
+    ///
+    /// Everything but label and constant references is validated before the first byte is
+    /// emitted. Those can only fail once the displacement is reached, so the bytes emitted
+    /// until then are removed: a failed instruction leaves no partial encoding behind.
+    fn emit_instr(
+        &mut self,
+        prefixes: &[u8],
+        w: bool,
+        opcode: &[u8],
+        reg: RegField,
+        rm: Rm,
+        imm: Option<(Size, i64)>,
+    ) -> Result<(), EncodeError> {
+        if let Some((imm_size, imm)) = imm {
+            Self::check_imm(imm_size, imm)?;
+        }
+
+        let mut regs = Vec::with_capacity(2);
+        let reg_num = match reg {
+            RegField::Reg(gpr) => {
+                regs.push(gpr);
+                gpr.num
+            }
+            RegField::Xmm(xmm) => xmm as u8,
+            RegField::Slash(slash) => slash,
+        };
+        let (index_num, rm_num) = match rm {
+            Rm::Reg(gpr) => {
+                regs.push(gpr);
+                (0, gpr.num)
+            }
+            Rm::Xmm(xmm) => (0, xmm as u8),
+            Rm::Ymm(ymm) => (0, ymm as u8),
+            Rm::Mem(mem) => {
+                mem.validate()?;
+                (mem.index_num(), mem.base_num())
             }
-            _ => unimplemented!(),
+        };
+        let rex = Self::rex(w, reg_num, index_num, rm_num, &regs)?;
+        let start = self.offset();
+
+        // Legacy and mandatory prefixes must precede the REX prefix.
+        for prefix in prefixes {
+            self.writer.emit8(*prefix);
+        }
+        if let Some(rex) = rex {
+            self.writer.emit8(rex);
+        }
+        for byte in opcode {
+            self.writer.emit8(*byte);
+        }
+        let trailing = imm.map_or(0, |(size, _)| Self::imm_len(size));
+        if let Err(err) = self.emit_modrm(reg_num & 0b111, rm, trailing) {
+            self.writer.truncate(start);
+            return Err(err);
         }
-    }
 
-    pub fn cqo(&mut self) {
-        self.writer.emit8(0x48);
-        self.writer.emit8(0x99);
+        if let Some((size, imm)) = imm {
+            self.emit_imm(size, imm);
+        }
+        Ok(())
     }
 
-    pub fn call(&mut self, target: Operand) {
-        match target {
-            Operand::Reg(_) => {
-                self.emit_rex_oi(target, 1);
-                self.writer.emit8(0xff);
-                self.emit_modrm_slash(2, target);
+    /// Emit a VEX-encoded instruction: VEX prefix, opcode and ModRM. `vvvv` is the extra
+    /// source register of three-operand forms and must be 0 when the instruction has none.
+    ///
+    /// The 2-byte form is used whenever the instruction doesn't need VEX.X, VEX.B, VEX.W
+    /// or an opcode map other than `0x0f`.
+    fn emit_vex(
+        &mut self,
+        vex: Vex,
+        opcode: u8,
+        reg: u8,
+        vvvv: u8,
+        rm: Rm,
+    ) -> Result<(), EncodeError> {
+        let (index_num, rm_num) = match rm {
+            Rm::Reg(gpr) => (0, gpr.num),
+            Rm::Xmm(xmm) => (0, xmm as u8),
+            Rm::Ymm(ymm) => (0, ymm as u8),
+            Rm::Mem(mem) => {
+                mem.validate()?;
+                (mem.index_num(), mem.base_num())
             }
-            _ => unimplemented!(),
+        };
+
+        // R, X, B and vvvv are stored inverted.
+        let r = (!reg >> 3) & 1;
+        let x = (!index_num >> 3) & 1;
+        let b = (!rm_num >> 3) & 1;
+        let vvvv = !vvvv & 0b1111;
+        let pp = match vex.prefix {
+            None => 0b00,
+            Some(0x66) => 0b01,
+            Some(0xf3) => 0b10,
+            Some(0xf2) => 0b11,
+            Some(prefix) => unreachable!("no VEX encoding for prefix {:#x}", prefix),
+        };
+        let last = (vvvv << 3) | ((vex.l as u8) << 2) | pp;
+
+        if x == 1 && b == 1 && !vex.w && matches!(vex.map, VexMap::X0F) {
+            self.writer.emit8(0xc5);
+            self.writer.emit8((r << 7) | last);
+        } else {
+            self.writer.emit8(0xc4);
+            self.writer
+                .emit8((r << 7) | (x << 6) | (b << 5) | vex.map as u8);
+            self.writer.emit8(((vex.w as u8) << 7) | last);
         }
+        self.writer.emit8(opcode);
+        self.emit_modrm(reg & 0b111, rm, 0)
     }
 
-    pub fn ret(&mut self) {
-        self.writer.emit8(0xc3);
+    /// Emit an instruction that encodes its register in the low bits of the last opcode byte.
+    fn emit_op_plus_reg(&mut self, size: Size, opcode: &[u8], reg: Gpr) -> Result<(), EncodeError> {
+        let rex = Self::rex(size == Size::Qword, 0, 0, reg.num, &[reg])?;
+        let (last, escape) = opcode.split_last().unwrap();
+
+        self.emit_opsize_prefix(size);
+        if let Some(rex) = rex {
+            self.writer.emit8(rex);
+        }
+        for byte in escape {
+            self.writer.emit8(*byte);
+        }
+        self.writer.emit8(last | (reg.num & 0b111));
+        Ok(())
     }
 
-    pub fn mov(&mut self, dst: Operand, src: Operand) {
-        match (dst, src) {
-            // mov reg, reg
-            (Operand::Reg(_), Operand::Reg(src_reg)) => {
-                self.emit_rex_mr(dst, src, 1);
-                self.writer.emit8(0x89);
-                self.emit_modrm_mr(dst, src_reg);
+    fn emit_modrm(&mut self, modrm_reg: u8, rm: Rm, trailing: u8) -> Result<(), EncodeError> {
+        match rm {
+            Rm::Reg(gpr) => {
+                self.writer
+                    .emit8(((ModRM::Reg as u8) << 6) | (modrm_reg << 3) | (gpr.num & 0b111));
+                Ok(())
             }
-            // mov reg, imm
-            (Operand::Reg(dst_reg), Operand::Imm64(imm)) => {
-                self.emit_rex_oi(dst, 1);
-                self.writer.emit8(0xb8 | Self::encode_reg(dst_reg));
-                self.writer.emit64(imm as u64);
+            Rm::Xmm(xmm) => {
+                self.writer
+                    .emit8(((ModRM::Reg as u8) << 6) | (modrm_reg << 3) | (xmm as u8 & 0b111));
+                Ok(())
             }
-            // mov [base_reg + offset], reg
-            (Operand::MemDisp(_, _), Operand::Reg(src_reg)) => {
-                self.emit_rex_mr(dst, src, 1);
-                self.writer.emit8(0x89);
-                self.emit_modrm_mr(dst, src_reg);
+            Rm::Ymm(ymm) => {
+                self.writer
+                    .emit8(((ModRM::Reg as u8) << 6) | (modrm_reg << 3) | (ymm as u8 & 0b111));
+                Ok(())
             }
-            // mov [base_reg + offset], imm
-            (Operand::MemDisp(_, _), Operand::Imm64(imm)) => {
-                self.emit_rex_slash(dst, 1);
-                self.writer.emit8(0xc7);
-                self.emit_modrm_slash(0, dst);
-                self.writer.emit32(imm as u32);
+            Rm::Mem(mem) => self.emit_modrm_mem(modrm_reg, mem, trailing),
+        }
+    }
+
+    /// Emit the ModRM byte, the SIB byte and the displacement of a memory operand, given
+    /// the length of the immediate that follows it.
+    fn emit_modrm_mem(&mut self, modrm_reg: u8, mem: Mem, trailing: u8) -> Result<(), EncodeError> {
+        // rm = 0b100 selects a SIB byte, and so does SIB.base = 0b101 with mod = 0b00
+        // for "no base". With mod = 0b00, rm = 0b101 means RIP-relative instead of RBP/R13.
+        const RM_SIB: u8 = 0b100;
+        const NO_BASE: u8 = 0b101;
+        const NO_INDEX: u8 = 0b100;
+
+        let sib = |base: u8| match mem.index {
+            Some((index, scale)) => ((scale as u8) << 6) | (Self::encode_reg(index) << 3) | base,
+            None => (NO_INDEX << 3) | base,
+        };
+
+        match mem.base {
+            Base::Rip => {
+                self.writer
+                    .emit8(((ModRM::Mem as u8) << 6) | (modrm_reg << 3) | NO_BASE);
+                self.writer.emit32(mem.disp as u32);
             }
-            // mov reg, [base_reg + offset]
-            (Operand::Reg(dst_reg), Operand::MemDisp(_, _)) => {
-                self.emit_rex_rm(dst, src, 1);
-                self.writer.emit8(0x8b);
-                self.emit_modrm_rm(dst_reg, src);
+            Base::Label(label) => {
+                self.writer
+                    .emit8(((ModRM::Mem as u8) << 6) | (modrm_reg << 3) | NO_BASE);
+                self.emit_label_rel(label, FixupKind::Disp32(trailing))?;
+            }
+            Base::Constant(constant) => {
+                self.writer
+                    .emit8(((ModRM::Mem as u8) << 6) | (modrm_reg << 3) | NO_BASE);
+                let offset = self.offset();
+                self.writer.emit32(0);
+                self.writer
+                    .reference_constant(constant, offset, offset + 4 + trailing as usize)?;
+            }
+            Base::None => {
+                self.writer
+                    .emit8(((ModRM::Mem as u8) << 6) | (modrm_reg << 3) | RM_SIB);
+                self.writer.emit8(sib(NO_BASE));
+                self.writer.emit32(mem.disp as u32);
             }
-            _ => {
-                dbg!(&dst, &src);
-                unimplemented!()
+            Base::Reg(base) => {
+                let base = Self::encode_reg(base);
+                // RBP and R13 can't be encoded without a displacement.
+                let modrm_mod = match mem.disp {
+                    0 if base != NO_BASE => ModRM::Mem,
+                    disp if i8::try_from(disp).is_ok() => ModRM::MemDisp8,
+                    _ => ModRM::MemDisp32,
+                };
+
+                // RSP and R12 can only be encoded as the base of a SIB byte.
+                if mem.index.is_some() || base == RM_SIB {
+                    self.writer
+                        .emit8(((modrm_mod as u8) << 6) | (modrm_reg << 3) | RM_SIB);
+                    self.writer.emit8(sib(base));
+                } else {
+                    self.writer
+                        .emit8(((modrm_mod as u8) << 6) | (modrm_reg << 3) | base);
+                }
+
+                match modrm_mod {
+                    ModRM::MemDisp8 => self.writer.emit8(mem.disp as u8),
+                    ModRM::MemDisp32 => self.writer.emit32(mem.disp as u32),
+                    _ => {}
+                }
             }
         }
+        Ok(())
     }
 
-    pub fn push(&mut self, src: Operand) {
-        match src {
-            Operand::Reg(src_reg) => {
-                self.emit_rex_oi(src, 1);
-                self.writer.emit8(0x50 | Self::encode_reg(src_reg));
-            }
-            Operand::Imm64(imm) => {
-                self.writer.emit8(0x68);
-                self.writer.emit32(imm as u32);
-            }
-            _ => unimplemented!(),
+    /// Check whether the immediate can be encoded for the given operand size, either as
+    /// a signed or as an unsigned value. 64-bit operations take 32-bit immediates that are
+    /// sign-extended by the CPU.
+    fn imm_fits(size: Size, imm: i64) -> bool {
+        match size {
+            Size::Byte => i8::try_from(imm).is_ok() || u8::try_from(imm).is_ok(),
+            Size::Word => i16::try_from(imm).is_ok() || u16::try_from(imm).is_ok(),
+            Size::Dword => i32::try_from(imm).is_ok() || u32::try_from(imm).is_ok(),
+            Size::Qword => i32::try_from(imm).is_ok(),
         }
     }
 
-    pub fn pop(&mut self, dst: Operand) {
-        match dst {
-            Operand::Reg(dst_reg) => {
-                self.emit_rex_oi(dst, 1);
-                self.writer.emit8(0x58 | Self::encode_reg(dst_reg));
-            }
-            _ => unimplemented!(),
+    /// Fail if the immediate doesn't fit, instead of silently truncating it.
+    fn check_imm(size: Size, imm: i64) -> Result<(), EncodeError> {
+        match Self::imm_fits(size, imm) {
+            true => Ok(()),
+            false => Err(EncodeError::ImmediateOutOfRange(imm)),
+        }
+    }
+
+    /// Length of an immediate of the given operand size.
+    fn imm_len(size: Size) -> u8 {
+        match size {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword | Size::Qword => 4,
+        }
+    }
+
+    /// Check whether the immediate of an operation of the given size can be encoded as
+    /// an imm8 that is sign-extended to the operand size, e.g. `0xffffffff` for dword operands.
+    fn imm8_fits(size: Size, imm: i64) -> bool {
+        let imm = match size {
+            Size::Byte | Size::Qword => imm,
+            Size::Word => imm as i16 as i64,
+            Size::Dword => imm as i32 as i64,
+        };
+        i8::try_from(imm).is_ok()
+    }
+
+    /// Emit an immediate of the given operand size, which must have been checked with
+    /// [`X86Asm::check_imm`]. 64-bit operations take 32-bit immediates that are
+    /// sign-extended by the CPU.
+    fn emit_imm(&mut self, size: Size, imm: i64) {
+        match size {
+            Size::Byte => self.writer.emit8(imm as u8),
+            Size::Word => self.writer.emit16(imm as u16),
+            Size::Dword | Size::Qword => self.writer.emit32(imm as u32),
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
+
+    fn encode_reg(reg: Reg64) -> u8 {
+        reg as u8 & 0b111
+    }
+
+    /// Print the disassembly of the code generated so far, as loaded at `base_addr`.
+    /// See [`X86Asm::disassemble`] to capture or annotate it.
+    pub fn dump_generated_code(&self, base_addr: u64) {
+        print!("{}", self.disassemble(base_addr));
+    }
+
+    pub fn code(&self) -> &[u8] {
+        self.writer.bytes()
+    }
+
+    /// Every instruction emitted so far, sorted by offset. Data such as the constant
+    /// pool isn't included.
+    pub fn instructions(&self) -> &[InsnInfo] {
+        &self.insns
+    }
+
+    /// Get the instruction that contains the byte at the offset, if any.
+    pub fn instruction_at(&self, offset: usize) -> Option<&InsnInfo> {
+        let i = self.insns.partition_point(|insn| insn.offset <= offset);
+        self.insns[..i].last().filter(|insn| offset < insn.end())
+    }
+
+    /// Run an emitter of a single instruction and record where it was emitted.
+    fn record(
+        &mut self,
+        mnemonic: &'static str,
+        cond: Option<Cond>,
+        emit: impl FnOnce(&mut Self) -> Result<(), EncodeError>,
+    ) -> Result<(), EncodeError> {
+        let offset = self.offset();
+        emit(self)?;
+        self.insns.push(InsnInfo {
+            offset,
+            len: self.offset() - offset,
+            mnemonic,
+            cond,
+        });
+        Ok(())
+    }
+
+    /// Emit one of the `0xf6`/`0xf7` group instructions (`not`, `neg`, `mul`, ...)
+    /// given its `/digit`.
+    fn emit_unary(
+        &mut self,
+        mnemonic: &'static str,
+        dst: Operand,
+        slash: u8,
+    ) -> Result<(), EncodeError> {
+        let Some(rm) = dst.rm() else {
+            return Err(Self::invalid(mnemonic, &[dst]));
+        };
+
+        let size = Self::operand_size(mnemonic, &[dst])?;
+        let opcode = if size == Size::Byte { 0xf6 } else { 0xf7 };
+        self.emit_op_rm(size, &[opcode], RegField::Slash(slash), rm)
+    }
+
+    fn emit_neg(&mut self, dst: Operand) -> Result<(), EncodeError> {
+        self.emit_unary("neg", dst, 3)
+    }
+
+    /// Emit one of the classic two-operand ALU instructions (`add`, `cmp`, `and`, ...)
+    /// given its `r/m, reg` opcode, its `reg, r/m` opcode and the `/digit` of its `r/m, imm` form.
+    /// The opcodes are the ones of the 16/32/64-bit forms, the 8-bit forms precede them.
+    fn emit_alu(
+        &mut self,
+        mnemonic: &'static str,
+        dst: Operand,
+        src: Operand,
+        opcode_mr: u8,
+        opcode_rm: u8,
+        slash: u8,
+    ) -> Result<(), EncodeError> {
+        let size = Self::operand_size(mnemonic, &[dst, src])?;
+        let byte_op = (size == Size::Byte) as u8;
+
+        match (dst.rm(), src.gpr(), src.rm(), src.imm()) {
+            // op reg, reg | op [base_reg + offset], reg
+            (Some(rm), Some(src_reg), _, _) => {
+                let opcode = opcode_mr - byte_op;
+                self.emit_op_rm(size, &[opcode], RegField::Reg(src_reg), rm)
+            }
+            // op reg, [base_reg + offset]
+            (Some(Rm::Reg(dst_reg)), None, Some(rm), _) => {
+                let opcode = opcode_rm - byte_op;
+                self.emit_op_rm(size, &[opcode], RegField::Reg(dst_reg), rm)
+            }
+            // op reg, imm8 | op [base_reg + offset], imm8 (sign-extended)
+            (Some(rm), None, None, Some(imm))
+                if size != Size::Byte
+                    && Self::imm_fits(size, imm)
+                    && Self::imm8_fits(size, imm) =>
+            {
+                let imm = Some((Size::Byte, imm as i8 as i64));
+                self.emit_op_rm_imm(size, &[0x83], RegField::Slash(slash), rm, imm)
+            }
+            // op reg, imm | op [base_reg + offset], imm
+            (Some(rm), None, None, Some(imm)) => {
+                let opcode = 0x81 - byte_op;
+                let imm = Some((size, imm));
+                self.emit_op_rm_imm(size, &[opcode], RegField::Slash(slash), rm, imm)
+            }
+            _ => Err(Self::invalid(mnemonic, &[dst, src])),
+        }
+    }
+
+    /// Emit one of the shift/rotate group instructions given its `/digit`. The count is
+    /// either CL (`Reg8(Cl)` or `Reg(Rcx)`) or an immediate.
+    fn emit_shift(
+        &mut self,
+        mnemonic: &'static str,
+        dst: Operand,
+        count: Operand,
+        slash: u8,
+    ) -> Result<(), EncodeError> {
+        let Some(rm) = dst.rm() else {
+            return Err(Self::invalid(mnemonic, &[dst, count]));
+        };
+
+        let size = Self::operand_size(mnemonic, &[dst])?;
+        let byte_op = (size == Size::Byte) as u8;
+
+        match count {
+            // op reg, cl | op [base_reg + offset], cl
+            Operand::Reg8(Reg8::Cl) | Operand::Reg(Reg64::Rcx) => {
+                self.emit_op_rm(size, &[0xd3 - byte_op], RegField::Slash(slash), rm)
+            }
+            // op reg, 1 | op [base_reg + offset], 1
+            _ if count.imm() == Some(1) => {
+                self.emit_op_rm(size, &[0xd1 - byte_op], RegField::Slash(slash), rm)
+            }
+            // op reg, imm8 | op [base_reg + offset], imm8
+            _ => match count.imm() {
+                Some(imm) => {
+                    let opcode = 0xc1 - byte_op;
+                    let imm = Some((Size::Byte, imm));
+                    self.emit_op_rm_imm(size, &[opcode], RegField::Slash(slash), rm, imm)
+                }
+                None => Err(Self::invalid(mnemonic, &[dst, count])),
+            },
+        }
+    }
+
+    fn emit_add(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("add", dst, src, 0x01, 0x03, 0)
+    }
+
+    fn emit_sub(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("sub", dst, src, 0x29, 0x2b, 5)
+    }
+
+    fn emit_sbb(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("sbb", dst, src, 0x19, 0x1b, 3)
+    }
+
+    fn emit_adc(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("adc", dst, src, 0x11, 0x13, 2)
+    }
+
+    fn emit_cmp(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("cmp", dst, src, 0x39, 0x3b, 7)
+    }
+
+    fn emit_and(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("and", dst, src, 0x21, 0x23, 4)
+    }
+
+    fn emit_or(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("or", dst, src, 0x09, 0x0b, 1)
+    }
+
+    fn emit_xor(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_alu("xor", dst, src, 0x31, 0x33, 6)
+    }
+
+    fn emit_not(&mut self, dst: Operand) -> Result<(), EncodeError> {
+        self.emit_unary("not", dst, 2)
+    }
+
+    fn emit_rol(&mut self, dst: Operand, count: Operand) -> Result<(), EncodeError> {
+        self.emit_shift("rol", dst, count, 0)
+    }
+
+    fn emit_ror(&mut self, dst: Operand, count: Operand) -> Result<(), EncodeError> {
+        self.emit_shift("ror", dst, count, 1)
+    }
+
+    fn emit_shl(&mut self, dst: Operand, count: Operand) -> Result<(), EncodeError> {
+        self.emit_shift("shl", dst, count, 4)
+    }
+
+    fn emit_shr(&mut self, dst: Operand, count: Operand) -> Result<(), EncodeError> {
+        self.emit_shift("shr", dst, count, 5)
+    }
+
+    fn emit_sar(&mut self, dst: Operand, count: Operand) -> Result<(), EncodeError> {
+        self.emit_shift("sar", dst, count, 7)
+    }
+
+    fn emit_test(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let size = Self::operand_size("test", &[dst, src])?;
+        let byte_op = (size == Size::Byte) as u8;
+
+        match (dst.rm(), src.gpr(), src.rm(), src.imm()) {
+            // test reg, reg | test [base_reg + offset], reg
+            (Some(rm), Some(src_reg), _, _) => {
+                self.emit_op_rm(size, &[0x85 - byte_op], RegField::Reg(src_reg), rm)
+            }
+            // test reg, [base_reg + offset] is the same instruction as test [base_reg + offset], reg
+            (Some(Rm::Reg(_)), None, Some(Rm::Mem(_)), _) => self.emit_test(src, dst),
+            // test reg, imm | test [base_reg + offset], imm
+            (Some(rm), None, None, Some(imm)) => {
+                let imm = Some((size, imm));
+                self.emit_op_rm_imm(size, &[0xf7 - byte_op], RegField::Slash(0), rm, imm)
+            }
+            _ => Err(Self::invalid("test", &[dst, src])),
+        }
+    }
+
+    fn emit_setcc(&mut self, cond: Cond, dst: Operand) -> Result<(), EncodeError> {
+        match (dst.rm(), dst.size()) {
+            (Some(rm), Some(Size::Byte)) | (Some(rm @ Rm::Mem(_)), None) => self.emit_op_rm(
+                Size::Byte,
+                &[0x0f, 0x90 | cond as u8],
+                RegField::Slash(0),
+                rm,
+            ),
+            _ => Err(Self::invalid("setcc", &[dst])),
+        }
+    }
+
+    fn emit_cmovcc(&mut self, cond: Cond, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let size = Self::operand_size("cmovcc", &[dst, src])?;
+
+        match (dst.gpr(), src.rm()) {
+            // cmovcc reg, reg | cmovcc reg, [base_reg + offset]
+            (Some(dst_reg), Some(rm)) if size != Size::Byte => {
+                self.emit_op_rm(size, &[0x0f, 0x40 | cond as u8], RegField::Reg(dst_reg), rm)
+            }
+            _ => Err(Self::invalid("cmovcc", &[dst, src])),
+        }
+    }
+
+    fn emit_mul(&mut self, src: Operand) -> Result<(), EncodeError> {
+        self.emit_unary("mul", src, 4)
+    }
+
+    fn emit_imul(&mut self, src: Operand) -> Result<(), EncodeError> {
+        self.emit_unary("imul", src, 5)
+    }
+
+    fn emit_div(&mut self, src: Operand) -> Result<(), EncodeError> {
+        self.emit_unary("div", src, 6)
+    }
+
+    fn emit_idiv(&mut self, src: Operand) -> Result<(), EncodeError> {
+        self.emit_unary("idiv", src, 7)
+    }
+
+    fn emit_imul_2(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let size = Self::operand_size("imul", &[dst, src])?;
+
+        match (dst.gpr(), src.rm()) {
+            // imul reg, reg | imul reg, [base_reg + offset]
+            (Some(dst_reg), Some(rm)) if size != Size::Byte => {
+                self.emit_op_rm(size, &[0x0f, 0xaf], RegField::Reg(dst_reg), rm)
+            }
+            _ => Err(Self::invalid("imul", &[dst, src])),
+        }
+    }
+
+    fn emit_imul_3(&mut self, dst: Operand, src: Operand, imm: Operand) -> Result<(), EncodeError> {
+        let size = Self::operand_size("imul", &[dst, src])?;
+
+        match (dst.gpr(), src.rm(), imm.imm()) {
+            // imul reg, reg, imm8 | imul reg, [base_reg + offset], imm8 (sign-extended)
+            (Some(dst_reg), Some(rm), Some(imm))
+                if size != Size::Byte
+                    && Self::imm_fits(size, imm)
+                    && Self::imm8_fits(size, imm) =>
+            {
+                let imm = Some((Size::Byte, imm as i8 as i64));
+                self.emit_op_rm_imm(size, &[0x6b], RegField::Reg(dst_reg), rm, imm)
+            }
+            // imul reg, reg, imm | imul reg, [base_reg + offset], imm
+            (Some(dst_reg), Some(rm), Some(imm)) if size != Size::Byte => {
+                let imm = Some((size, imm));
+                self.emit_op_rm_imm(size, &[0x69], RegField::Reg(dst_reg), rm, imm)
+            }
+            _ => Err(Self::invalid("imul", &[dst, src, imm])),
+        }
+    }
+
+    /// Emit `inc` or `dec` given its `/digit` in the `0xfe`/`0xff` group.
+    fn emit_inc_dec(
+        &mut self,
+        mnemonic: &'static str,
+        dst: Operand,
+        slash: u8,
+    ) -> Result<(), EncodeError> {
+        let Some(rm) = dst.rm() else {
+            return Err(Self::invalid(mnemonic, &[dst]));
+        };
+
+        let size = Self::operand_size(mnemonic, &[dst])?;
+        let opcode = if size == Size::Byte { 0xfe } else { 0xff };
+        self.emit_op_rm(size, &[opcode], RegField::Slash(slash), rm)
+    }
+
+    fn emit_inc(&mut self, dst: Operand) -> Result<(), EncodeError> {
+        self.emit_inc_dec("inc", dst, 0)
+    }
+
+    fn emit_dec(&mut self, dst: Operand) -> Result<(), EncodeError> {
+        self.emit_inc_dec("dec", dst, 1)
+    }
+
+    fn emit_bswap(&mut self, dst: Operand) -> Result<(), EncodeError> {
+        match dst.gpr() {
+            Some(reg) if matches!(reg.size, Size::Dword | Size::Qword) => {
+                self.emit_op_plus_reg(reg.size, &[0x0f, 0xc8], reg)
+            }
+            _ => Err(Self::invalid("bswap", &[dst])),
+        }
+    }
+
+    /// Emit one of the `0xf3 0x0f` bit-count instructions (`popcnt`, `lzcnt`, `tzcnt`)
+    /// given its opcode.
+    fn emit_bit_count(
+        &mut self,
+        mnemonic: &'static str,
+        dst: Operand,
+        src: Operand,
+        opcode: u8,
+    ) -> Result<(), EncodeError> {
+        let size = Self::operand_size(mnemonic, &[dst, src])?;
+
+        match (dst.gpr(), src.rm()) {
+            // op reg, reg | op reg, [base_reg + offset]
+            (Some(dst_reg), Some(rm)) if size != Size::Byte => {
+                // The operand-size prefix precedes the mandatory prefix.
+                let prefixes: &[u8] = if size == Size::Word {
+                    &[0x66, 0xf3]
+                } else {
+                    &[0xf3]
+                };
+                let reg = RegField::Reg(dst_reg);
+                self.emit_instr(
+                    prefixes,
+                    size == Size::Qword,
+                    &[0x0f, opcode],
+                    reg,
+                    rm,
+                    None,
+                )
+            }
+            _ => Err(Self::invalid(mnemonic, &[dst, src])),
+        }
+    }
+
+    fn emit_popcnt(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_bit_count("popcnt", dst, src, 0xb8)
+    }
+
+    fn emit_lzcnt(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_bit_count("lzcnt", dst, src, 0xbd)
+    }
+
+    fn emit_tzcnt(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_bit_count("tzcnt", dst, src, 0xbc)
+    }
+
+    pub fn cqo(&mut self) -> Result<(), EncodeError> {
+        self.record("cqo", None, |asm| {
+            asm.writer.emit8(0x48);
+            asm.writer.emit8(0x99);
+            Ok(())
+        })
+    }
+
+    fn emit_call(&mut self, target: Operand) -> Result<(), EncodeError> {
+        match target {
+            Operand::Reg(_) => {
+                let rm = target.rm().unwrap();
+                self.emit_op_rm(Size::Qword, &[0xff], RegField::Slash(2), rm)
+            }
+            Operand::Label(label) => {
+                self.writer.emit8(0xe8);
+                self.emit_label_rel(label, FixupKind::Rel32)
+            }
+            // Near calls always use 64-bit operands, so no REX.W is needed.
+            _ => match target.mem64() {
+                Some(mem) => {
+                    self.emit_op_rm(Size::Dword, &[0xff], RegField::Slash(2), Rm::Mem(mem))
+                }
+                None => Err(Self::invalid("call", &[target])),
+            },
+        }
+    }
+
+    fn emit_jmp(&mut self, target: Operand) -> Result<(), EncodeError> {
+        match target {
+            Operand::Label(label) if self.fits_rel8(label, 2) => {
+                self.writer.emit8(0xeb);
+                self.emit_label_rel(label, FixupKind::Rel8)
+            }
+            Operand::Label(label) => {
+                self.writer.emit8(0xe9);
+                self.emit_label_rel(label, FixupKind::Rel32)
+            }
+            Operand::Reg(_) => {
+                // Near jumps always use 64-bit operands, so no REX.W is needed.
+                let rm = target.rm().unwrap();
+                self.emit_op_rm(Size::Dword, &[0xff], RegField::Slash(4), rm)
+            }
+            _ => match target.mem64() {
+                Some(mem) => {
+                    self.emit_op_rm(Size::Dword, &[0xff], RegField::Slash(4), Rm::Mem(mem))
+                }
+                None => Err(Self::invalid("jmp", &[target])),
+            },
+        }
+    }
+
+    /// Fail if a bound label is out of reach of a short jump emitted at the current offset.
+    fn check_short(&self, label: Label) -> Result<(), EncodeError> {
+        match self.labels.offset(label) {
+            Some(_) if !self.fits_rel8(label, 2) => Err(EncodeError::LabelOutOfRange(label)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Unconditional jump that always uses the short rel8 form. Fails (possibly when the
+    /// label gets bound) if the label is out of rel8 range.
+    pub fn jmp_short(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.check_short(label)?;
+        self.record("jmp_short", None, |asm| {
+            asm.writer.emit8(0xeb);
+            asm.emit_label_rel(label, FixupKind::Rel8)
+        })
+    }
+
+    fn emit_jcc(&mut self, cond: Cond, target: Operand) -> Result<(), EncodeError> {
+        match target {
+            Operand::Label(label) if self.fits_rel8(label, 2) => {
+                self.writer.emit8(0x70 | cond as u8);
+                self.emit_label_rel(label, FixupKind::Rel8)
+            }
+            Operand::Label(label) => {
+                self.writer.emit8(0x0f);
+                self.writer.emit8(0x80 | cond as u8);
+                self.emit_label_rel(label, FixupKind::Rel32)
+            }
+            _ => Err(Self::invalid("jcc", &[target])),
+        }
+    }
+
+    /// Conditional jump that always uses the short rel8 form. Fails (possibly when the
+    /// label gets bound) if the label is out of rel8 range.
+    pub fn jcc_short(&mut self, cond: Cond, label: Label) -> Result<(), EncodeError> {
+        self.check_short(label)?;
+        self.record("jcc_short", Some(cond), |asm| {
+            asm.writer.emit8(0x70 | cond as u8);
+            asm.emit_label_rel(label, FixupKind::Rel8)
+        })
+    }
+
+    fn emit_lea(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let mem = match src {
+            // lea reg, [rip + label]
+            Operand::Label(label) => Some(Mem::label(Size::Qword, label)),
+            // lea reg, [base_reg + offset]
+            _ => src.mem(),
+        };
+
+        match (dst.gpr(), mem) {
+            (Some(dst_reg), Some(mem)) if dst_reg.size != Size::Byte => {
+                self.emit_op_rm(dst_reg.size, &[0x8d], RegField::Reg(dst_reg), Rm::Mem(mem))
+            }
+            _ => Err(Self::invalid("lea", &[dst, src])),
+        }
+    }
+
+    pub fn ret(&mut self) -> Result<(), EncodeError> {
+        self.record("ret", None, |asm| {
+            asm.writer.emit8(0xc3);
+            Ok(())
+        })
+    }
+
+    fn emit_mov(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let size = Self::operand_size("mov", &[dst, src])?;
+        let byte_op = (size == Size::Byte) as u8;
+
+        match (dst.rm(), src.gpr(), src.rm(), src.imm()) {
+            // mov reg, reg | mov [base_reg + offset], reg
+            (Some(rm), Some(src_reg), _, _) => {
+                self.emit_op_rm(size, &[0x89 - byte_op], RegField::Reg(src_reg), rm)
+            }
+            // mov reg, [base_reg + offset]
+            (Some(Rm::Reg(dst_reg)), None, Some(rm), _) => {
+                self.emit_op_rm(size, &[0x8b - byte_op], RegField::Reg(dst_reg), rm)
+            }
+            // mov reg64, imm32 (zero-extended by the 32-bit form)
+            (Some(Rm::Reg(dst_reg)), None, None, Some(imm))
+                if size == Size::Qword && u32::try_from(imm).is_ok() =>
+            {
+                self.emit_op_plus_reg(Size::Dword, &[0xb8], dst_reg)?;
+                self.emit_imm(Size::Dword, imm);
+                Ok(())
+            }
+            // mov reg64, imm32 (sign-extended)
+            (Some(rm @ Rm::Reg(_)), None, None, Some(imm))
+                if size == Size::Qword && Self::imm_fits(size, imm) =>
+            {
+                let imm = Some((size, imm));
+                self.emit_op_rm_imm(size, &[0xc7], RegField::Slash(0), rm, imm)
+            }
+            // mov reg, imm
+            (Some(Rm::Reg(dst_reg)), None, None, Some(imm)) => {
+                if size != Size::Qword {
+                    Self::check_imm(size, imm)?;
+                }
+
+                let opcode = if size == Size::Byte { 0xb0 } else { 0xb8 };
+                self.emit_op_plus_reg(size, &[opcode], dst_reg)?;
+                match size {
+                    Size::Qword => self.writer.emit64(imm as u64),
+                    _ => self.emit_imm(size, imm),
+                }
+                Ok(())
+            }
+            // mov [base_reg + offset], imm
+            (Some(rm @ Rm::Mem(_)), None, None, Some(imm)) => {
+                let imm = Some((size, imm));
+                self.emit_op_rm_imm(size, &[0xc7 - byte_op], RegField::Slash(0), rm, imm)
+            }
+            _ => Err(Self::invalid("mov", &[dst, src])),
+        }
+    }
+
+    /// Emit `movzx` or `movsx` given the opcode of the form with an 8-bit source. The
+    /// form with a 16-bit source follows it.
+    fn emit_movx(
+        &mut self,
+        mnemonic: &'static str,
+        dst: Operand,
+        src: Operand,
+        opcode: u8,
+    ) -> Result<(), EncodeError> {
+        match (dst.gpr(), src.rm(), src.size()) {
+            // op reg, reg8/16 | op reg, byte/word ptr [base_reg + offset]
+            (Some(dst_reg), Some(rm), Some(src_size))
+                if matches!(
+                    (dst_reg.size, src_size),
+                    (Size::Word, Size::Byte) | (Size::Dword | Size::Qword, Size::Byte | Size::Word)
+                ) =>
+            {
+                let opcode = opcode + (src_size == Size::Word) as u8;
+                self.emit_op_rm(dst_reg.size, &[0x0f, opcode], RegField::Reg(dst_reg), rm)
+            }
+            _ => Err(Self::invalid(mnemonic, &[dst, src])),
+        }
+    }
+
+    fn emit_movzx(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_movx("movzx", dst, src, 0xb6)
+    }
+
+    fn emit_movsx(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_movx("movsx", dst, src, 0xbe)
+    }
+
+    fn emit_movsxd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        match (dst.gpr(), src.rm(), src.size()) {
+            // movsxd reg64, reg32 | movsxd reg64, dword ptr [base_reg + offset]
+            (Some(dst_reg), Some(rm), Some(Size::Dword)) if dst_reg.size == Size::Qword => {
+                self.emit_op_rm(Size::Qword, &[0x63], RegField::Reg(dst_reg), rm)
+            }
+            _ => Err(Self::invalid("movsxd", &[dst, src])),
+        }
+    }
+
+    fn emit_xchg(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let size = Self::operand_size("xchg", &[dst, src])?;
+        let byte_op = (size == Size::Byte) as u8;
+
+        match (dst.gpr(), src.gpr(), dst.rm(), src.rm()) {
+            // xchg rax, reg | xchg reg, rax. `xchg rax, rax` would be `nop`, which doesn't
+            // zero-extend EAX in `xchg eax, eax`, so it takes the ModRM form.
+            (Some(dst_reg), Some(src_reg), _, _)
+                if size != Size::Byte
+                    && (dst_reg.num == 0 || src_reg.num == 0)
+                    && dst_reg.num != src_reg.num =>
+            {
+                let reg = if dst_reg.num == 0 { src_reg } else { dst_reg };
+                self.emit_op_plus_reg(size, &[0x90], reg)
+            }
+            // xchg reg, reg | xchg [base_reg + offset], reg
+            (_, Some(src_reg), Some(rm), _) => {
+                self.emit_op_rm(size, &[0x87 - byte_op], RegField::Reg(src_reg), rm)
+            }
+            // xchg reg, [base_reg + offset]
+            (Some(dst_reg), None, _, Some(rm @ Rm::Mem(_))) => {
+                self.emit_op_rm(size, &[0x87 - byte_op], RegField::Reg(dst_reg), rm)
+            }
+            _ => Err(Self::invalid("xchg", &[dst, src])),
+        }
+    }
+
+    // Pushes and pops always use 64-bit operands, so no REX.W is needed.
+    fn emit_push(&mut self, src: Operand) -> Result<(), EncodeError> {
+        match src {
+            Operand::Reg(_) => self.emit_op_plus_reg(Size::Dword, &[0x50], src.gpr().unwrap()),
+            // The immediate is sign-extended to 64 bits, larger values have to go
+            // through a register.
+            Operand::Imm64(_) | Operand::Imm32(_) | Operand::Imm8(_) => {
+                let imm = src.imm().unwrap();
+                Self::check_imm(Size::Qword, imm)?;
+                if Self::imm8_fits(Size::Qword, imm) {
+                    self.writer.emit8(0x6a);
+                    self.writer.emit8(imm as u8);
+                } else {
+                    self.writer.emit8(0x68);
+                    self.emit_imm(Size::Qword, imm);
+                }
+                Ok(())
+            }
+            _ => match src.mem64() {
+                Some(mem) => {
+                    self.emit_op_rm(Size::Dword, &[0xff], RegField::Slash(6), Rm::Mem(mem))
+                }
+                None => Err(Self::invalid("push", &[src])),
+            },
+        }
+    }
+
+    fn emit_pop(&mut self, dst: Operand) -> Result<(), EncodeError> {
+        match dst {
+            Operand::Reg(_) => self.emit_op_plus_reg(Size::Dword, &[0x58], dst.gpr().unwrap()),
+            _ => match dst.mem64() {
+                Some(mem) => {
+                    self.emit_op_rm(Size::Dword, &[0x8f], RegField::Slash(0), Rm::Mem(mem))
+                }
+                None => Err(Self::invalid("pop", &[dst])),
+            },
+        }
+    }
+
+    /// Resolve the `xmm/mem` operand of an SSE instruction. Memory operands either have an
+    /// implied size or the given one.
+    fn sse_rm(
+        mnemonic: &'static str,
+        operands: &[Operand],
+        op: Operand,
+        mem_size: Option<Size>,
+    ) -> Result<Rm, EncodeError> {
+        match (op, op.mem()) {
+            (Operand::Xmm(xmm), _) => Ok(Rm::Xmm(xmm)),
+            (_, Some(mem)) if mem.size.is_none() || mem.size == mem_size => Ok(Rm::Mem(mem)),
+            _ => Err(Self::invalid(mnemonic, operands)),
+        }
+    }
+
+    /// Emit an SSE instruction of the `op xmm, xmm/mem` form.
+    fn emit_sse_xmm_rm(
+        &mut self,
+        mnemonic: &'static str,
+        prefix: u8,
+        opcode: u8,
+        dst: Operand,
+        src: Operand,
+        mem_size: Option<Size>,
+    ) -> Result<(), EncodeError> {
+        let Some(dst_xmm) = dst.xmm() else {
+            return Err(Self::invalid(mnemonic, &[dst, src]));
+        };
+
+        let rm = Self::sse_rm(mnemonic, &[dst, src], src, mem_size)?;
+        self.emit_sse(prefix, false, opcode, RegField::Xmm(dst_xmm), rm)
+    }
+
+    fn emit_movsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        match (dst.mem(), src.xmm()) {
+            // movsd [base_reg + offset], xmm
+            (Some(_), Some(src_xmm)) => {
+                let rm = Self::sse_rm("movsd", &[dst, src], dst, Some(Size::Qword))?;
+                self.emit_sse(0xf2, false, 0x11, RegField::Xmm(src_xmm), rm)
+            }
+            // movsd xmm, xmm | movsd xmm, [base_reg + offset]
+            _ => self.emit_sse_xmm_rm("movsd", 0xf2, 0x10, dst, src, Some(Size::Qword)),
+        }
+    }
+
+    fn emit_movq(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let qword = |op: Operand| op.size().unwrap_or(Size::Qword) == Size::Qword;
+
+        match (dst.xmm(), dst.rm(), src.xmm(), src.rm()) {
+            // movq xmm, xmm
+            (Some(dst_xmm), _, Some(src_xmm), _) => {
+                self.emit_sse(0xf3, false, 0x7e, RegField::Xmm(dst_xmm), Rm::Xmm(src_xmm))
+            }
+            // movq xmm, reg64 | movq xmm, [base_reg + offset]
+            (Some(dst_xmm), _, None, Some(rm)) if qword(src) => {
+                self.emit_sse(0x66, true, 0x6e, RegField::Xmm(dst_xmm), rm)
+            }
+            // movq reg64, xmm | movq [base_reg + offset], xmm
+            (None, Some(rm), Some(src_xmm), _) if qword(dst) => {
+                self.emit_sse(0x66, true, 0x7e, RegField::Xmm(src_xmm), rm)
+            }
+            _ => Err(Self::invalid("movq", &[dst, src])),
+        }
+    }
+
+    fn emit_addsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("addsd", 0xf2, 0x58, dst, src, Some(Size::Qword))
+    }
+
+    fn emit_subsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("subsd", 0xf2, 0x5c, dst, src, Some(Size::Qword))
+    }
+
+    fn emit_mulsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("mulsd", 0xf2, 0x59, dst, src, Some(Size::Qword))
+    }
+
+    fn emit_divsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("divsd", 0xf2, 0x5e, dst, src, Some(Size::Qword))
+    }
+
+    fn emit_sqrtsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("sqrtsd", 0xf2, 0x51, dst, src, Some(Size::Qword))
+    }
+
+    fn emit_ucomisd(&mut self, lhs: Operand, rhs: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("ucomisd", 0x66, 0x2e, lhs, rhs, Some(Size::Qword))
+    }
+
+    fn emit_xorpd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        self.emit_sse_xmm_rm("xorpd", 0x66, 0x57, dst, src, None)
+    }
+
+    fn emit_cvtsi2sd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let size = src.size().unwrap_or(Size::Qword);
+
+        match (dst.xmm(), src.rm()) {
+            (Some(dst_xmm), Some(rm @ (Rm::Reg(_) | Rm::Mem(_))))
+                if matches!(size, Size::Dword | Size::Qword) =>
+            {
+                self.emit_sse(0xf2, size == Size::Qword, 0x2a, RegField::Xmm(dst_xmm), rm)
+            }
+            _ => Err(Self::invalid("cvtsi2sd", &[dst, src])),
+        }
+    }
+
+    fn emit_cvttsd2si(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        match dst.gpr() {
+            Some(dst_reg) if matches!(dst_reg.size, Size::Dword | Size::Qword) => {
+                let rm = Self::sse_rm("cvttsd2si", &[dst, src], src, Some(Size::Qword))?;
+                let w = dst_reg.size == Size::Qword;
+                self.emit_sse(0xf2, w, 0x2c, RegField::Reg(dst_reg), rm)
+            }
+            _ => Err(Self::invalid("cvttsd2si", &[dst, src])),
+        }
+    }
+
+    /// Resolve the `xmm/mem` or `ymm/mem` operand of an AVX instruction. Registers must
+    /// match the vector length, memory operands either have an implied size or the given one.
+    fn avx_rm(
+        mnemonic: &'static str,
+        operands: &[Operand],
+        op: Operand,
+        l: bool,
+        mem_size: Option<Size>,
+    ) -> Result<Rm, EncodeError> {
+        match (op, op.mem()) {
+            (Operand::Xmm(xmm), _) if !l => Ok(Rm::Xmm(xmm)),
+            (Operand::Ymm(ymm), _) if l => Ok(Rm::Ymm(ymm)),
+            (_, Some(mem)) if mem.size.is_none() || mem.size == mem_size => Ok(Rm::Mem(mem)),
+            _ => Err(Self::invalid(mnemonic, operands)),
+        }
+    }
+
+    /// Emit an AVX instruction of the `op dst, src1, src2/mem` form. The vector length
+    /// is taken from the destination, which can be an XMM or a YMM register.
+    fn emit_avx_3op(
+        &mut self,
+        mnemonic: &'static str,
+        vex: Vex,
+        opcode: u8,
+        operands: [Operand; 3],
+    ) -> Result<(), EncodeError> {
+        let [dst, src1, src2] = operands;
+        match (dst.vec(), src1.vec()) {
+            (Some((dst_num, l)), Some((src1_num, src1_l))) if l == src1_l => {
+                let rm = Self::avx_rm(mnemonic, &operands, src2, l, None)?;
+                self.emit_vex(Vex { l, ..vex }, opcode, dst_num, src1_num, rm)
+            }
+            _ => Err(Self::invalid(mnemonic, &operands)),
+        }
+    }
+
+    fn emit_vmovupd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let vex = |l| Vex {
+            l,
+            ..Vex::pd(VexMap::X0F, false)
+        };
+
+        match (dst.vec(), dst.mem(), src.vec()) {
+            // vmovupd [base_reg + offset], ymm
+            (None, Some(mem), Some((src_num, l))) if mem.size.is_none() => {
+                self.emit_vex(vex(l), 0x11, src_num, 0, Rm::Mem(mem))
+            }
+            // Encoding a high source register in ModRM.reg allows for the 2-byte VEX prefix.
+            (Some((dst_num, l)), _, Some((src_num, src_l)))
+                if l == src_l && dst_num < 8 && src_num >= 8 =>
+            {
+                let rm = Self::avx_rm("vmovupd", &[dst, src], dst, l, None)?;
+                self.emit_vex(vex(l), 0x11, src_num, 0, rm)
+            }
+            // vmovupd ymm, ymm | vmovupd ymm, [base_reg + offset]
+            (Some((dst_num, l)), _, _) => {
+                let rm = Self::avx_rm("vmovupd", &[dst, src], src, l, None)?;
+                self.emit_vex(vex(l), 0x10, dst_num, 0, rm)
+            }
+            _ => Err(Self::invalid("vmovupd", &[dst, src])),
+        }
+    }
+
+    fn emit_vaddpd(
+        &mut self,
+        dst: Operand,
+        src1: Operand,
+        src2: Operand,
+    ) -> Result<(), EncodeError> {
+        let vex = Vex::pd(VexMap::X0F, false);
+        self.emit_avx_3op("vaddpd", vex, 0x58, [dst, src1, src2])
+    }
+
+    fn emit_vmulpd(
+        &mut self,
+        dst: Operand,
+        src1: Operand,
+        src2: Operand,
+    ) -> Result<(), EncodeError> {
+        let vex = Vex::pd(VexMap::X0F, false);
+        self.emit_avx_3op("vmulpd", vex, 0x59, [dst, src1, src2])
+    }
+
+    fn emit_vfmadd231pd(
+        &mut self,
+        dst: Operand,
+        src1: Operand,
+        src2: Operand,
+    ) -> Result<(), EncodeError> {
+        let vex = Vex::pd(VexMap::X0F38, true);
+        self.emit_avx_3op("vfmadd231pd", vex, 0xb8, [dst, src1, src2])
+    }
+
+    fn emit_vpaddq(
+        &mut self,
+        dst: Operand,
+        src1: Operand,
+        src2: Operand,
+    ) -> Result<(), EncodeError> {
+        let vex = Vex::pd(VexMap::X0F, false);
+        self.emit_avx_3op("vpaddq", vex, 0xd4, [dst, src1, src2])
+    }
+
+    fn emit_vbroadcastsd(&mut self, dst: Operand, src: Operand) -> Result<(), EncodeError> {
+        let vex = Vex {
+            l: true,
+            ..Vex::pd(VexMap::X0F38, false)
+        };
+
+        match (dst, src) {
+            (Operand::Ymm(dst_ymm), Operand::Xmm(src_xmm)) => {
+                self.emit_vex(vex, 0x19, dst_ymm as u8, 0, Rm::Xmm(src_xmm))
+            }
+            (Operand::Ymm(dst_ymm), _) => {
+                let rm = Self::avx_rm("vbroadcastsd", &[dst, src], src, true, Some(Size::Qword))?;
+                match rm {
+                    Rm::Mem(_) => self.emit_vex(vex, 0x19, dst_ymm as u8, 0, rm),
+                    _ => Err(Self::invalid("vbroadcastsd", &[dst, src])),
+                }
+            }
+            _ => Err(Self::invalid("vbroadcastsd", &[dst, src])),
+        }
+    }
+}
+
+impl Assembler for X86Asm {
+    type Reg = Reg64;
+
+    /// The System V AMD64 ABI.
+    const ARG_REGS: &'static [Reg64] = &[
+        Reg64::Rdi,
+        Reg64::Rsi,
+        Reg64::Rdx,
+        Reg64::Rcx,
+        Reg64::R8,
+        Reg64::R9,
+    ];
+    const RET_REG: Reg64 = Reg64::Rax;
+
+    fn new_label(&mut self) -> Label {
+        X86Asm::new_label(self)
+    }
+
+    fn bind_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        X86Asm::bind_label(self, label)
+    }
+
+    fn offset(&self) -> usize {
+        X86Asm::offset(self)
+    }
+
+    fn code(&self) -> &[u8] {
+        X86Asm::code(self)
+    }
+
+    fn load_imm(&mut self, dst: Reg64, imm: i64) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::Imm64(imm))
+    }
+
+    fn mov_reg(&mut self, dst: Reg64, src: Reg64) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::Reg(src))
+    }
+
+    fn add_reg(&mut self, dst: Reg64, lhs: Reg64, rhs: Reg64) -> Result<(), EncodeError> {
+        // Addition commutes, so `dst = lhs + dst` is `add dst, lhs`.
+        let (lhs, rhs) = if dst == rhs { (rhs, lhs) } else { (lhs, rhs) };
+        if dst != lhs {
+            self.mov_reg(dst, lhs)?;
+        }
+        self.add(Operand::Reg(dst), Operand::Reg(rhs))
+    }
+
+    fn sub_reg(&mut self, dst: Reg64, lhs: Reg64, rhs: Reg64) -> Result<(), EncodeError> {
+        if dst == rhs && dst != lhs {
+            // Moving `lhs` into `dst` would overwrite `rhs`: compute `-rhs + lhs` instead.
+            self.neg(Operand::Reg(dst))?;
+            return self.add(Operand::Reg(dst), Operand::Reg(lhs));
+        }
+        if dst != lhs {
+            self.mov_reg(dst, lhs)?;
+        }
+        self.sub(Operand::Reg(dst), Operand::Reg(rhs))
+    }
+
+    fn mul_reg(&mut self, dst: Reg64, lhs: Reg64, rhs: Reg64) -> Result<(), EncodeError> {
+        let (lhs, rhs) = if dst == rhs { (rhs, lhs) } else { (lhs, rhs) };
+        if dst != lhs {
+            self.mov_reg(dst, lhs)?;
+        }
+        self.imul_2(dst, rhs)
+    }
+
+    fn load(&mut self, dst: Reg64, base: Reg64, offset: i32) -> Result<(), EncodeError> {
+        self.mov(Operand::Reg(dst), Operand::MemDisp(base, offset))
+    }
+
+    fn store(&mut self, src: Reg64, base: Reg64, offset: i32) -> Result<(), EncodeError> {
+        self.mov(Operand::MemDisp(base, offset), Operand::Reg(src))
+    }
+
+    fn jump(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.jmp(Operand::Label(label))
+    }
+
+    fn call_label(&mut self, label: Label) -> Result<(), EncodeError> {
+        self.call(Operand::Label(label))
+    }
+
+    fn call_reg(&mut self, target: Reg64) -> Result<(), EncodeError> {
+        self.call(Operand::Reg(target))
+    }
+
+    fn ret(&mut self) -> Result<(), EncodeError> {
+        X86Asm::ret(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x86_64_codegen_typed_operands() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        let end = codegen.new_label();
+        codegen.mov(Rax, -2i64).unwrap();
+        codegen.mov(Reg32::Ecx, Mem::dword(Rdi, 8)).unwrap();
+        codegen.mov(Mem::word(Rsp, 0), 0x1234i16).unwrap();
+        codegen.add(Rax, -1i32).unwrap();
+        codegen.cmp(Mem::byte(Rsi, 1), Reg8::Dl).unwrap();
+        codegen.shl(Reg32::Eax, Reg8::Cl).unwrap();
+        codegen.sar(Rdx, 3i8).unwrap();
+        let mem = Mem::qword(Rdi, 16).with_index(Rsi, Scale::X8);
+        codegen.lea(R8, mem).unwrap();
+        codegen.cmovcc(Cond::L, Rax, Rcx).unwrap();
+        codegen.setcc(Cond::E, Reg8::Al).unwrap();
+        codegen.addsd(Xmm::Xmm0, Mem::qword(Rdi, 0)).unwrap();
+        codegen
+            .vaddpd(Ymm::Ymm0, Ymm::Ymm1, Mem::implied(Rdi, 32))
+            .unwrap();
+        codegen.jcc(Cond::Ne, end).unwrap();
+        codegen.call(Rax).unwrap();
+        codegen.bind_label(end).unwrap();
+        codegen.ret().unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xc7, 0xc0, 0xfe, 0xff, 0xff, 0xff, // mov rax, -2
+                0x8b, 0x4f, 0x08, // mov ecx, dword ptr [rdi + 8]
+                0x66, 0xc7, 0x04, 0x24, 0x34, 0x12, // mov word ptr [rsp], 0x1234
+                0x48, 0x83, 0xc0, 0xff, // add rax, -1
+                0x38, 0x56, 0x01, // cmp byte ptr [rsi + 1], dl
+                0xd3, 0xe0, // shl eax, cl
+                0x48, 0xc1, 0xfa, 0x03, // sar rdx, 3
+                0x4c, 0x8d, 0x44, 0xf7, 0x10, // lea r8, [rdi + 8*rsi + 16]
+                0x48, 0x0f, 0x4c, 0xc1, // cmovl rax, rcx
+                0x0f, 0x94, 0xc0, // sete al
+                0xf2, 0x0f, 0x58, 0x07, // addsd xmm0, qword ptr [rdi]
+                0xc5, 0xf5, 0x58, 0x47, 0x20, // vaddpd ymm0, ymm1, ymmword ptr [rdi + 32]
+                0x0f, 0x85, 0x03, 0x00, 0x00, 0x00, // jne end
+                0x48, 0xff, 0xd0, // call rax
+                0xc3, // end: ret
+            ]
+        );
+
+        // The kinds of the operands are checked at compile time, their sizes aren't.
+        assert!(matches!(
+            codegen.mov(Rax, Mem::dword(Rdi, 0)),
+            Err(EncodeError::InvalidOperands {
+                mnemonic: "mov",
+                ..
+            })
+        ));
+        // Typed and dynamic operands encode the same instructions.
+        let mut typed = X86Asm::new();
+        let mut dynamic = X86Asm::new();
+        typed.xor(Mem::dword(R13, 4), 0x100i32).unwrap();
+        dynamic
+            .xor(Operand::Mem(Mem::dword(R13, 4)), Operand::Imm32(0x100))
+            .unwrap();
+        assert_eq!(typed.code(), dynamic.code());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_neg() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.neg(Reg(Rax)).unwrap();
+        codegen.neg(Reg(R8)).unwrap();
+        codegen.neg(Reg(Rsp)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xf7, 0xd8, // neg rax
+                0x49, 0xf7, 0xd8, // neg r8
+                0x48, 0xf7, 0xdc, // neg rsp
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_div() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.div(Reg(Rax)).unwrap();
+        codegen.div(Reg(R8)).unwrap();
+        codegen.div(Reg(Rsp)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xf7, 0xf0, // div rax
+                0x49, 0xf7, 0xf0, // div r8
+                0x48, 0xf7, 0xf4, // div rsp
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_idiv() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.idiv(Reg(Rax)).unwrap();
+        codegen.idiv(Reg(R8)).unwrap();
+        codegen.idiv(Reg(Rsp)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xf7, 0xf8, // idiv rax
+                0x49, 0xf7, 0xf8, // idiv r8
+                0x48, 0xf7, 0xfc, // idiv rsp
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_mul() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.mul(Reg(Rax)).unwrap();
+        codegen.mul(Reg(R8)).unwrap();
+        codegen.mul(Reg(Rsp)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xf7, 0xe0, // mul rax
+                0x49, 0xf7, 0xe0, // mul r8
+                0x48, 0xf7, 0xe4, // mul rsp
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_imul() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.imul(Reg(Rax)).unwrap();
+        codegen.imul(Reg(R8)).unwrap();
+        codegen.imul(Reg(Rsp)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xf7, 0xe8, // imul rax
+                0x49, 0xf7, 0xe8, // imul r8
+                0x48, 0xf7, 0xec, // imul rsp
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_sub() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.sub(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.sub(Reg(Rax), Imm64(0x1234)).unwrap();
+        codegen.sub(Reg(R15), Reg(Rbp)).unwrap();
+        codegen.sub(Reg(R8), Imm64(0x45464748)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x29, 0xd8, // sub rax, rbx
+                0x48, 0x81, 0xe8, 0x34, 0x12, 0x00, 0x00, // sub rax, 0x1234
+                0x49, 0x29, 0xef, // sub r15, rbp
+                0x49, 0x81, 0xe8, 0x48, 0x47, 0x46, 0x45, // sub r8, 0x45464748
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_sbb() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.sbb(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.sbb(Reg(Rax), Imm64(0x1234)).unwrap();
+        codegen.sbb(Reg(R15), Reg(Rbp)).unwrap();
+        codegen.sbb(Reg(R8), Imm64(0x45464748)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x19, 0xd8, // sbb rax, rbx
+                0x48, 0x81, 0xd8, 0x34, 0x12, 0x00, 0x00, // sbb rax, 0x1234
+                0x49, 0x19, 0xef, // sbb r15, rbp
+                0x49, 0x81, 0xd8, 0x48, 0x47, 0x46, 0x45, // sbb r8, 0x45464748
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_add() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.add(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.add(Reg(Rax), Imm64(0x1234)).unwrap();
+        codegen.add(Reg(R15), Reg(Rbp)).unwrap();
+        codegen.add(Reg(R8), Imm64(0x45464748)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x01, 0xd8, // add rax, rbx
+                0x48, 0x81, 0xc0, 0x34, 0x12, 0x00, 0x00, // add rax, 0x1234
+                0x49, 0x01, 0xef, // add r15, rbp
+                0x49, 0x81, 0xc0, 0x48, 0x47, 0x46, 0x45, // add r8, 0x45464748
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_call() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.call(Reg(Rax)).unwrap();
+        codegen.call(Reg(R15)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xff, 0xd0, // call rax
+                0x49, 0xff, 0xd7, // call r15
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_call_label() {
+        use Operand::*;
+
+        let mut codegen = X86Asm::new();
+        let func = codegen.new_label();
+        codegen.call(Label(func)).unwrap();
+        codegen.ret().unwrap();
+        codegen.bind_label(func).unwrap();
+        codegen.call(Label(func)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0xe8, 0x01, 0x00, 0x00, 0x00, // call func
+                0xc3, // ret
+                0xe8, 0xfb, 0xff, 0xff, 0xff, // func: call func
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_jmp() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        codegen.jmp(Label(start)).unwrap();
+        codegen.jmp(Label(end)).unwrap();
+        codegen.jmp_short(end).unwrap();
+        codegen.jmp(Reg(Rax)).unwrap();
+        codegen.jmp(Reg(R11)).unwrap();
+        codegen.bind_label(end).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        assert!(!codegen.has_unbound_references());
+        assert_eq!(codegen.label_offset(end), Some(14));
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0xeb, 0xfe, // start: jmp start
+                0xe9, 0x07, 0x00, 0x00, 0x00, // jmp end
+                0xeb, 0x05, // jmp end
+                0xff, 0xe0, // jmp rax
+                0x41, 0xff, 0xe3, // jmp r11
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_jmp_rel32_backward() {
+        use Operand::*;
+
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        for _ in 0..0x80 {
+            codegen.ret().unwrap();
+        }
+        codegen.jmp(Label(start)).unwrap();
+
+        let code = codegen.code();
+        assert_eq!(&code[0x80..], &[0xe9, 0x7b, 0xff, 0xff, 0xff]); // jmp start
+    }
+
+    #[test]
+    fn test_x86_64_codegen_jmp_short_out_of_range() {
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        codegen.jmp_short(end).unwrap();
+        for _ in 0..0x80 {
+            codegen.ret().unwrap();
+        }
+        assert_eq!(
+            codegen.bind_label(end),
+            Err(EncodeError::LabelOutOfRange(end))
+        );
+
+        let len = codegen.offset();
+        assert_eq!(
+            codegen.jcc_short(Cond::E, start),
+            Err(EncodeError::LabelOutOfRange(start))
+        );
+        assert_eq!(codegen.offset(), len);
+    }
+
+    #[test]
+    fn test_x86_64_codegen_jcc() {
+        use Operand::*;
+
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        codegen.jcc(Cond::E, Label(start)).unwrap();
+        codegen.jcc(Cond::Ne, Label(end)).unwrap();
+        codegen.jcc_short(Cond::L, end).unwrap();
+        codegen.bind_label(end).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x74, 0xfe, // start: je start
+                0x0f, 0x85, 0x02, 0x00, 0x00, 0x00, // jne end
+                0x7c, 0x00, // jl end
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_jcc_all_conditions() {
+        use Operand::*;
+
+        let conds = [
+            Cond::O,
+            Cond::No,
+            Cond::B,
+            Cond::Ae,
+            Cond::E,
+            Cond::Ne,
+            Cond::Be,
+            Cond::A,
+            Cond::S,
+            Cond::Ns,
+            Cond::P,
+            Cond::Np,
+            Cond::L,
+            Cond::Ge,
+            Cond::Le,
+            Cond::G,
+        ];
+
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        for cond in conds {
+            codegen.jcc(cond, Label(start)).unwrap();
+        }
+        for cond in conds {
+            codegen.jcc(cond, Label(end)).unwrap();
+        }
+        codegen.bind_label(end).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        for (i, cond) in conds.iter().enumerate() {
+            // jcc start
+            let rel = (-2 * (i as i8 + 1)) as u8;
+            assert_eq!(&code[i * 2..i * 2 + 2], &[0x70 + *cond as u8, rel]);
+
+            // jcc end
+            let rel = (6 * (15 - i) as u32).to_le_bytes();
+            let at = 32 + i * 6;
+            assert_eq!(
+                &code[at..at + 6],
+                &[0x0f, 0x80 + *cond as u8, rel[0], rel[1], rel[2], rel[3]]
+            );
+        }
+    }
+
+    #[test]
+    fn test_x86_64_cond_aliases() {
+        assert_eq!(Cond::Z, Cond::E);
+        assert_eq!(Cond::Nz, Cond::Ne);
+        assert_eq!(Cond::C, Cond::B);
+        assert_eq!(Cond::Nge, Cond::L);
+        assert_eq!(Cond::E.negate(), Cond::Ne);
+        assert_eq!(Cond::L.negate(), Cond::Ge);
+        assert_eq!(Cond::Be.negate(), Cond::A);
+        assert_eq!(Cond::P.negate().negate(), Cond::P);
+    }
+
+    #[test]
+    fn test_x86_64_codegen_cmp() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.cmp(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.cmp(Reg(R12), Reg(Rcx)).unwrap();
+        codegen.cmp(Reg(Rax), Imm64(0x1234)).unwrap();
+        codegen.cmp(Reg(R9), Imm64(0x12345678)).unwrap();
+        codegen.cmp(Reg(Rdx), MemDisp(R14, 0x10)).unwrap();
+        codegen.cmp(MemDisp(Rbx, 0x20), Reg(R10)).unwrap();
+        codegen.cmp(MemDisp(R15, 0x8), Imm64(0x7f)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x39, 0xd8, // cmp rax, rbx
+                0x49, 0x39, 0xcc, // cmp r12, rcx
+                0x48, 0x81, 0xf8, 0x34, 0x12, 0x00, 0x00, // cmp rax, 0x1234
+                0x49, 0x81, 0xf9, 0x78, 0x56, 0x34, 0x12, // cmp r9, 0x12345678
+                0x49, 0x3b, 0x56, 0x10, // cmp rdx, [r14 + 0x10]
+                0x4c, 0x39, 0x53, 0x20, // cmp [rbx + 0x20], r10
+                0x49, 0x83, 0x7f, 0x08, 0x7f, // cmp [r15 + 0x8], 0x7f
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_and() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.and(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.and(MemDisp(Rbx, 0x10), Reg(R9)).unwrap();
+        codegen.and(Reg(R8), MemDisp(Rsi, 0x10)).unwrap();
+        codegen.and(Reg(Rcx), Imm64(0xff)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x21, 0xd8, // and rax, rbx
+                0x4c, 0x21, 0x4b, 0x10, // and [rbx + 0x10], r9
+                0x4c, 0x23, 0x46, 0x10, // and r8, [rsi + 0x10]
+                0x48, 0x81, 0xe1, 0xff, 0x00, 0x00, 0x00, // and rcx, 0xff
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_or() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.or(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.or(Reg(R10), MemDisp(Rdi, 0x8)).unwrap();
+        codegen.or(MemDisp(R15, 0x8), Imm64(0x10)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x09, 0xd8, // or rax, rbx
+                0x4c, 0x0b, 0x57, 0x08, // or r10, [rdi + 0x8]
+                0x49, 0x83, 0x4f, 0x08, 0x10, // or [r15 + 0x8], 0x10
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_xor() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.xor(Reg(Rax), Reg(Rax)).unwrap();
+        codegen.xor(Reg(R15), Reg(R15)).unwrap();
+        codegen.xor(MemDisp(Rax, 0x8), Reg(Rcx)).unwrap();
+        codegen.xor(Reg(Rdx), Imm64(0x12345678)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x31, 0xc0, // xor rax, rax
+                0x4d, 0x31, 0xff, // xor r15, r15
+                0x48, 0x31, 0x48, 0x08, // xor [rax + 0x8], rcx
+                0x48, 0x81, 0xf2, 0x78, 0x56, 0x34, 0x12, // xor rdx, 0x12345678
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_not() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.not(Reg(Rax)).unwrap();
+        codegen.not(Reg(R12)).unwrap();
+        codegen.not(MemDisp(Rbx, 0x8)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xf7, 0xd0, // not rax
+                0x49, 0xf7, 0xd4, // not r12
+                0x48, 0xf7, 0x53, 0x08, // not [rbx + 0x8]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_shifts() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.shl(Reg(Rax), Imm8(1)).unwrap();
+        codegen.shl(Reg(R9), Reg(Rcx)).unwrap();
+        codegen.shl(Reg(Rdx), Imm8(4)).unwrap();
+        codegen.shl(MemDisp(Rbx, 0x8), Imm8(3)).unwrap();
+        codegen.shr(Reg(Rax), Imm8(1)).unwrap();
+        codegen.shr(Reg(R11), Reg(Rcx)).unwrap();
+        codegen.shr(MemDisp(R14, 0x8), Reg(Rcx)).unwrap();
+        codegen.sar(Reg(Rax), Imm8(63)).unwrap();
+        codegen.sar(Reg(R8), Imm8(1)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xd1, 0xe0, // shl rax, 1
+                0x49, 0xd3, 0xe1, // shl r9, cl
+                0x48, 0xc1, 0xe2, 0x04, // shl rdx, 4
+                0x48, 0xc1, 0x63, 0x08, 0x03, // shl [rbx + 0x8], 3
+                0x48, 0xd1, 0xe8, // shr rax, 1
+                0x49, 0xd3, 0xeb, // shr r11, cl
+                0x49, 0xd3, 0x6e, 0x08, // shr [r14 + 0x8], cl
+                0x48, 0xc1, 0xf8, 0x3f, // sar rax, 63
+                0x49, 0xd1, 0xf8, // sar r8, 1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_rotates() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.rol(Reg(Rcx), Reg(Rcx)).unwrap();
+        codegen.rol(MemDisp(Rax, 0x10), Imm8(1)).unwrap();
+        codegen.ror(Reg(R13), Imm8(7)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0xd3, 0xc1, // rol rcx, cl
+                0x48, 0xd1, 0x40, 0x10, // rol [rax + 0x10], 1
+                0x49, 0xc1, 0xcd, 0x07, // ror r13, 7
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_test() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.test(Reg(Rax), Reg(Rax)).unwrap();
+        codegen.test(Reg(R11), Reg(Rdi)).unwrap();
+        codegen.test(Reg(Rcx), Imm64(0x100)).unwrap();
+        codegen.test(MemDisp(Rax, 0x8), Reg(R8)).unwrap();
+        codegen.test(Reg(R8), MemDisp(Rax, 0x8)).unwrap();
+        codegen.test(MemDisp(R13, 0x8), Imm64(1)).unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x48, 0x85, 0xc0, // test rax, rax
+                0x49, 0x85, 0xfb, // test r11, rdi
+                0x48, 0xf7, 0xc1, 0x00, 0x01, 0x00, 0x00, // test rcx, 0x100
+                0x4c, 0x85, 0x40, 0x08, // test [rax + 0x8], r8
+                0x4c, 0x85, 0x40, 0x08, // test [rax + 0x8], r8
+                0x49, 0xf7, 0x45, 0x08, 0x01, 0x00, 0x00, 0x00, // test [r13 + 0x8], 1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_setcc() {
+        use super::Reg8::*;
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.setcc(Cond::E, Reg8(Al)).unwrap();
+        codegen.setcc(Cond::Ne, Reg8(Cl)).unwrap();
+        codegen.setcc(Cond::L, Reg8(Spl)).unwrap();
+        codegen.setcc(Cond::G, Reg8(Dil)).unwrap();
+        codegen.setcc(Cond::B, Reg8(R8b)).unwrap();
+        codegen.setcc(Cond::A, Reg8(R15b)).unwrap();
+        codegen.setcc(Cond::Le, MemDisp(Rbx, 0x4)).unwrap();
+        codegen
+            .setcc(Cond::Ge, Mem(super::Mem::byte(R14, 0x4)))
+            .unwrap();
+
+        codegen.dump_generated_code(0);
+
+        let code = codegen.code();
+        assert_eq!(
+            code,
+            &[
+                0x0f, 0x94, 0xc0, // sete al
+                0x0f, 0x95, 0xc1, // setne cl
+                0x40, 0x0f, 0x9c, 0xc4, // setl spl
+                0x40, 0x0f, 0x9f, 0xc7, // setg dil
+                0x41, 0x0f, 0x92, 0xc0, // setb r8b
+                0x41, 0x0f, 0x97, 0xc7, // seta r15b
+                0x0f, 0x9e, 0x43, 0x04, // setle [rbx + 0x4]
+                0x41, 0x0f, 0x9d, 0x46, 0x04, // setge [r14 + 0x4]
+            ]
+        );
+    }
 
     #[test]
-    fn test_x86_64_codegen_neg() {
+    fn test_x86_64_codegen_cmovcc() {
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.neg(Reg(Rax));
-        codegen.neg(Reg(R8));
-        codegen.neg(Reg(Rsp));
+        codegen.cmovcc(Cond::E, Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.cmovcc(Cond::Ne, Reg(R8), Reg(Rcx)).unwrap();
+        codegen.cmovcc(Cond::L, Reg(Rsi), Reg(R15)).unwrap();
+        codegen
+            .cmovcc(Cond::G, Reg(Rdx), MemDisp(Rbp, 0x10))
+            .unwrap();
+        codegen
+            .cmovcc(Cond::B, Reg(R10), MemDisp(R9, 0x10))
+            .unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -389,43 +2801,64 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0xf7, 0xd8, // neg rax
-                0x49, 0xf7, 0xd8, // neg r8
-                0x48, 0xf7, 0xdc, // neg rsp
+                0x48, 0x0f, 0x44, 0xc3, // cmove rax, rbx
+                0x4c, 0x0f, 0x45, 0xc1, // cmovne r8, rcx
+                0x49, 0x0f, 0x4c, 0xf7, // cmovl rsi, r15
+                0x48, 0x0f, 0x4f, 0x55, 0x10, // cmovg rdx, [rbp + 0x10]
+                0x4d, 0x0f, 0x42, 0x51, 0x10, // cmovb r10, [r9 + 0x10]
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_div() {
+    fn test_x86_64_codegen_lea() {
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.div(Reg(Rax));
-        codegen.div(Reg(R8));
-        codegen.div(Reg(Rsp));
+        let data = codegen.new_label();
+        codegen.lea(Reg(Rax), Label(data)).unwrap();
+        codegen.lea(Reg(R9), Label(data)).unwrap();
+        codegen.lea(Reg(Rcx), MemDisp(R14, 0x10)).unwrap();
+        codegen.bind_label(data).unwrap();
+
+        codegen.dump_generated_code(0);
 
         let code = codegen.code();
         assert_eq!(
             code,
             &[
-                0x48, 0xf7, 0xf0, // div rax
-                0x49, 0xf7, 0xf0, // div r8
-                0x48, 0xf7, 0xf4, // div rsp
+                0x48, 0x8d, 0x05, 0x0b, 0x00, 0x00, 0x00, // lea rax, [rip + data]
+                0x4c, 0x8d, 0x0d, 0x04, 0x00, 0x00, 0x00, // lea r9, [rip + data]
+                0x49, 0x8d, 0x4e, 0x10, // lea rcx, [r14 + 0x10]
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_idiv() {
+    fn test_x86_64_codegen_ret() {
+        let mut codegen = X86Asm::new();
+        codegen.ret().unwrap();
+
+        let code = codegen.code();
+        assert_eq!(code, &[0xc3]); // ret
+    }
+
+    #[test]
+    fn test_x86_64_codegen_mov() {
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.idiv(Reg(Rax));
-        codegen.idiv(Reg(R8));
-        codegen.idiv(Reg(Rsp));
+        codegen.mov(Reg(Rax), Imm64(0x123456789abcdef0)).unwrap();
+        codegen.mov(Reg(Rax), Reg(Rbx)).unwrap();
+        codegen.mov(MemDisp(Rax, 0x1337), Reg(Rbx)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(Rbx, 0x41414141)).unwrap();
+        codegen.mov(Reg(R8), Imm64(0x1234)).unwrap();
+        codegen.mov(Reg(Rsp), Reg(R15)).unwrap();
+        codegen
+            .mov(MemDisp(R15, 0x12345678), Imm64(0x41424344))
+            .unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -433,43 +2866,106 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0xf7, 0xf8, // idiv rax
-                0x49, 0xf7, 0xf8, // idiv r8
-                0x48, 0xf7, 0xfc, // idiv rsp
+                0x48, 0xb8, 0xf0, 0xde, 0xbc, 0x9a, 0x78, 0x56, 0x34, 0x12, // mov rax, 0x1234
+                0x48, 0x89, 0xd8, // mov rax, rbx
+                0x48, 0x89, 0x98, 0x37, 0x13, 0x00, 0x00, // mov [rax], rbx
+                0x48, 0x8b, 0x83, 0x41, 0x41, 0x41, 0x41, // mov rbx, [rax]
+                0x41, 0xb8, 0x34, 0x12, 0x00, 0x00, // mov r8d, 0x1234
+                0x4c, 0x89, 0xfc, // mov rsp, r15
+                0x49, 0xc7, 0x87, 0x78, 0x56, 0x34, 0x12, 0x44, 0x43, 0x42,
+                0x41, // mov [r15], 0x41424344
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_mul() {
+    fn test_x86_64_codegen_mov_sized() {
+        use super::Reg16::*;
+        use super::Reg32::*;
+        use super::Reg8::*;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.mul(Reg(Rax));
-        codegen.mul(Reg(R8));
-        codegen.mul(Reg(Rsp));
+        codegen.mov(Reg32(Eax), Reg32(Ecx)).unwrap();
+        codegen.mov(Reg32(R8d), Imm32(5)).unwrap();
+        codegen.mov(Reg16(Ax), Reg16(Bx)).unwrap();
+        codegen.mov(Reg16(R9w), Imm16(0x1234)).unwrap();
+        codegen.mov(Reg8(Al), Reg8(Cl)).unwrap();
+        codegen.mov(Reg8(Sil), Reg8(Dil)).unwrap();
+        codegen.mov(Reg8(Ah), Reg8(Bh)).unwrap();
+        codegen.mov(Reg8(R10b), Imm8(0x7f)).unwrap();
+        codegen.mov(MemDisp(Rdi, 0x100), Reg8(Al)).unwrap();
+        codegen
+            .mov(Mem(super::Mem::word(Rdi, 0x100)), Imm16(0x1234))
+            .unwrap();
+        codegen
+            .mov(Mem(super::Mem::dword(Rax, 0x100)), Imm32(0x12345678))
+            .unwrap();
+        codegen
+            .mov(Reg8(R10b), Mem(super::Mem::byte(Rsi, 0x100)))
+            .unwrap();
+        codegen.mov(Reg32(Eax), MemDisp(Rbx, 0x100)).unwrap();
+        codegen
+            .mov(Mem(super::Mem::byte(Rbx, 0x100)), Imm8(0x41))
+            .unwrap();
+
+        codegen.dump_generated_code(0);
 
         let code = codegen.code();
         assert_eq!(
             code,
             &[
-                0x48, 0xf7, 0xe0, // mul rax
-                0x49, 0xf7, 0xe0, // mul r8
-                0x48, 0xf7, 0xe4, // mul rsp
+                0x89, 0xc8, // mov eax, ecx
+                0x41, 0xb8, 0x05, 0x00, 0x00, 0x00, // mov r8d, 5
+                0x66, 0x89, 0xd8, // mov ax, bx
+                0x66, 0x41, 0xb9, 0x34, 0x12, // mov r9w, 0x1234
+                0x88, 0xc8, // mov al, cl
+                0x40, 0x88, 0xfe, // mov sil, dil
+                0x88, 0xfc, // mov ah, bh
+                0x41, 0xb2, 0x7f, // mov r10b, 0x7f
+                0x88, 0x87, 0x00, 0x01, 0x00, 0x00, // mov [rdi + 0x100], al
+                0x66, 0xc7, 0x87, 0x00, 0x01, 0x00, 0x00, 0x34,
+                0x12, // mov word [rdi + 0x100], 0x1234
+                0xc7, 0x80, 0x00, 0x01, 0x00, 0x00, 0x78, 0x56, 0x34,
+                0x12, // mov dword [rax + 0x100], 0x12345678
+                0x44, 0x8a, 0x96, 0x00, 0x01, 0x00, 0x00, // mov r10b, [rsi + 0x100]
+                0x8b, 0x83, 0x00, 0x01, 0x00, 0x00, // mov eax, [rbx + 0x100]
+                0xc6, 0x83, 0x00, 0x01, 0x00, 0x00, 0x41, // mov byte [rbx + 0x100], 0x41
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_imul() {
+    fn test_x86_64_codegen_sized_arith() {
+        use super::Reg16::*;
+        use super::Reg32::*;
+        use super::Reg8::*;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.imul(Reg(Rax));
-        codegen.imul(Reg(R8));
-        codegen.imul(Reg(Rsp));
+        codegen.add(Reg32(Eax), Reg32(Ecx)).unwrap();
+        codegen.sub(Reg16(R9w), Imm16(0x10)).unwrap();
+        codegen.cmp(Reg8(Al), Imm8(0x7f)).unwrap();
+        codegen.xor(Reg32(R12d), Reg32(R12d)).unwrap();
+        codegen
+            .and(Mem(super::Mem::byte(Rbx, 0x100)), Imm8(0xf))
+            .unwrap();
+        codegen.test(Reg8(Dil), Imm8(1)).unwrap();
+        codegen.test(MemDisp(Rax, 0x100), Reg16(Cx)).unwrap();
+        codegen.neg(Reg32(Ecx)).unwrap();
+        codegen.not(Reg8(Bpl)).unwrap();
+        codegen.shl(Reg16(Ax), Reg8(Cl)).unwrap();
+        codegen.sar(Reg8(R11b), Imm8(3)).unwrap();
+        codegen.mul(Reg32(R8d)).unwrap();
+        codegen.idiv(Reg16(Cx)).unwrap();
+        codegen.setcc(Cond::E, Reg8(R9b)).unwrap();
+        codegen
+            .cmovcc(Cond::L, Reg32(Eax), MemDisp(Rsi, 0x100))
+            .unwrap();
+        codegen.cmovcc(Cond::Ge, Reg16(Cx), Reg16(Dx)).unwrap();
+        codegen.lea(Reg32(Eax), MemDisp(Rbx, 0x100)).unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -477,23 +2973,73 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0xf7, 0xe8, // imul rax
-                0x49, 0xf7, 0xe8, // imul r8
-                0x48, 0xf7, 0xec, // imul rsp
+                0x01, 0xc8, // add eax, ecx
+                0x66, 0x41, 0x83, 0xe9, 0x10, // sub r9w, 0x10
+                0x80, 0xf8, 0x7f, // cmp al, 0x7f
+                0x45, 0x31, 0xe4, // xor r12d, r12d
+                0x80, 0xa3, 0x00, 0x01, 0x00, 0x00, 0x0f, // and byte [rbx + 0x100], 0xf
+                0x40, 0xf6, 0xc7, 0x01, // test dil, 1
+                0x66, 0x85, 0x88, 0x00, 0x01, 0x00, 0x00, // test [rax + 0x100], cx
+                0xf7, 0xd9, // neg ecx
+                0x40, 0xf6, 0xd5, // not bpl
+                0x66, 0xd3, 0xe0, // shl ax, cl
+                0x41, 0xc0, 0xfb, 0x03, // sar r11b, 3
+                0x41, 0xf7, 0xe0, // mul r8d
+                0x66, 0xf7, 0xf9, // idiv cx
+                0x41, 0x0f, 0x94, 0xc1, // sete r9b
+                0x0f, 0x4c, 0x86, 0x00, 0x01, 0x00, 0x00, // cmovl eax, [rsi + 0x100]
+                0x66, 0x0f, 0x4d, 0xca, // cmovge cx, dx
+                0x8d, 0x83, 0x00, 0x01, 0x00, 0x00, // lea eax, [rbx + 0x100]
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_sub() {
+    fn test_x86_64_codegen_mem_addressing() {
+        use super::Mem as M;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.sub(Reg(Rax), Reg(Rbx));
-        codegen.sub(Reg(Rax), Imm64(0x1234));
-        codegen.sub(Reg(R15), Reg(Rbp));
-        codegen.sub(Reg(R8), Imm64(0x45464748));
+        codegen.mov(Reg(Rax), MemDisp(Rsp, 0x100)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(R12, 0x100)).unwrap();
+        codegen.mov(Reg(Rax), MemAbs(Rbp)).unwrap();
+        codegen.mov(Reg(Rax), MemAbs(R13)).unwrap();
+        codegen.mov(Reg(Rax), MemAbs(Rax)).unwrap();
+        codegen.mov(Reg(Rax), MemAbs(Rsp)).unwrap();
+        codegen
+            .mov(
+                Reg(Rax),
+                Mem(M::qword(Rbx, 0x100).with_index(Rcx, Scale::X8)),
+            )
+            .unwrap();
+        codegen
+            .mov(Reg(Rax), Mem(M::qword(R12, 0).with_index(R13, Scale::X4)))
+            .unwrap();
+        codegen
+            .mov(Reg(Rax), Mem(M::qword(Rbp, 0).with_index(Rax, Scale::X2)))
+            .unwrap();
+        codegen
+            .mov(
+                Reg(R9),
+                Mem(M::abs(Size::Qword, 0x1000).with_index(Rcx, Scale::X8)),
+            )
+            .unwrap();
+        codegen
+            .mov(Reg(Rax), Mem(M::abs(Size::Qword, 0x1234)))
+            .unwrap();
+        codegen
+            .mov(Reg(Rax), Mem(M::rip(Size::Qword, 0x10)))
+            .unwrap();
+        codegen
+            .mov(
+                Mem(M::qword(R12, 0x100).with_index(R15, Scale::X8)),
+                Reg(Rdx),
+            )
+            .unwrap();
+        codegen
+            .cmp(Mem(M::dword(Rsp, 0x100)), Imm32(0x1000))
+            .unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -501,24 +3047,49 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0x29, 0xd8, // sub rax, rbx
-                0x48, 0x81, 0xe8, 0x34, 0x12, 0x00, 0x00, // sub rax, 0x1234
-                0x49, 0x29, 0xef, // sub r15, rbp
-                0x49, 0x81, 0xe8, 0x48, 0x47, 0x46, 0x45, // sub r8, 0x45464748
+                0x48, 0x8b, 0x84, 0x24, 0x00, 0x01, 0x00, 0x00, // mov rax, [rsp + 0x100]
+                0x49, 0x8b, 0x84, 0x24, 0x00, 0x01, 0x00, 0x00, // mov rax, [r12 + 0x100]
+                0x48, 0x8b, 0x45, 0x00, // mov rax, [rbp]
+                0x49, 0x8b, 0x45, 0x00, // mov rax, [r13]
+                0x48, 0x8b, 0x00, // mov rax, [rax]
+                0x48, 0x8b, 0x04, 0x24, // mov rax, [rsp]
+                0x48, 0x8b, 0x84, 0xcb, 0x00, 0x01, 0x00,
+                0x00, // mov rax, [rbx + rcx*8 + 0x100]
+                0x4b, 0x8b, 0x04, 0xac, // mov rax, [r12 + r13*4]
+                0x48, 0x8b, 0x44, 0x45, 0x00, // mov rax, [rbp + rax*2]
+                0x4c, 0x8b, 0x0c, 0xcd, 0x00, 0x10, 0x00, 0x00, // mov r9, [rcx*8 + 0x1000]
+                0x48, 0x8b, 0x04, 0x25, 0x34, 0x12, 0x00, 0x00, // mov rax, [0x1234]
+                0x48, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00, // mov rax, [rip + 0x10]
+                0x4b, 0x89, 0x94, 0xfc, 0x00, 0x01, 0x00,
+                0x00, // mov [r12 + r15*8 + 0x100], rdx
+                0x81, 0xbc, 0x24, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00,
+                0x00, // cmp dword [rsp + 0x100], 0x1000
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_sbb() {
+    fn test_x86_64_codegen_compact_encodings() {
+        use super::Reg32::*;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.sbb(Reg(Rax), Reg(Rbx));
-        codegen.sbb(Reg(Rax), Imm64(0x1234));
-        codegen.sbb(Reg(R15), Reg(Rbp));
-        codegen.sbb(Reg(R8), Imm64(0x45464748));
+        codegen.mov(Reg(Rax), Imm64(-1)).unwrap();
+        codegen.mov(Reg(Rcx), Imm64(0xffffffff)).unwrap();
+        codegen.mov(Reg(Rax), Imm64(0x100000000)).unwrap();
+        codegen.add(Reg(Rax), Imm64(-8)).unwrap();
+        codegen.sub(Reg(R14), Imm64(8)).unwrap();
+        codegen.add(Reg(Rsp), Imm64(0x80)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(Rbx, -8)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(Rbx, 0x7f)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(Rbx, 0x80)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(Rbp, 8)).unwrap();
+        codegen.mov(Reg(Rax), MemDisp(Rsp, 8)).unwrap();
+        codegen
+            .cmp(Mem(super::Mem::dword(Rsp, 8)), Imm32(-1))
+            .unwrap();
+        codegen.and(Reg32(Eax), Imm64(0xffffffff)).unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -526,24 +3097,67 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0x19, 0xd8, // sbb rax, rbx
-                0x48, 0x81, 0xd8, 0x34, 0x12, 0x00, 0x00, // sbb rax, 0x1234
-                0x49, 0x19, 0xef, // sbb r15, rbp
-                0x49, 0x81, 0xd8, 0x48, 0x47, 0x46, 0x45, // sbb r8, 0x45464748
+                0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff, // mov rax, -1
+                0xb9, 0xff, 0xff, 0xff, 0xff, // mov ecx, 0xffffffff
+                0x48, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+                0x00, // mov rax, 0x100000000
+                0x48, 0x83, 0xc0, 0xf8, // add rax, -8
+                0x49, 0x83, 0xee, 0x08, // sub r14, 8
+                0x48, 0x81, 0xc4, 0x80, 0x00, 0x00, 0x00, // add rsp, 0x80
+                0x48, 0x8b, 0x43, 0xf8, // mov rax, [rbx - 8]
+                0x48, 0x8b, 0x43, 0x7f, // mov rax, [rbx + 0x7f]
+                0x48, 0x8b, 0x83, 0x80, 0x00, 0x00, 0x00, // mov rax, [rbx + 0x80]
+                0x48, 0x8b, 0x45, 0x08, // mov rax, [rbp + 8]
+                0x48, 0x8b, 0x44, 0x24, 0x08, // mov rax, [rsp + 8]
+                0x83, 0x7c, 0x24, 0x08, 0xff, // cmp dword [rsp + 8], -1
+                0x83, 0xe0, 0xff, // and eax, 0xffffffff
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_add() {
+    fn test_x86_64_codegen_imm64_truncation() {
+        use super::Reg8::*;
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        assert_eq!(
+            codegen.add(Reg(Rax), Imm64(0x100000000)),
+            Err(EncodeError::ImmediateOutOfRange(0x100000000))
+        );
+        assert_eq!(
+            codegen.mov(Reg8(Al), Imm64(0x100)),
+            Err(EncodeError::ImmediateOutOfRange(0x100))
+        );
+        assert_eq!(
+            codegen.push(Imm64(-0x80000001)),
+            Err(EncodeError::ImmediateOutOfRange(-0x80000001))
+        );
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_rip_label() {
+        use super::Mem as M;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.add(Reg(Rax), Reg(Rbx));
-        codegen.add(Reg(Rax), Imm64(0x1234));
-        codegen.add(Reg(R15), Reg(Rbp));
-        codegen.add(Reg(R8), Imm64(0x45464748));
+        let data = codegen.new_label();
+        codegen
+            .mov(Reg(Rax), Mem(M::label(Size::Qword, data)))
+            .unwrap();
+        codegen
+            .cmp(Mem(M::label(Size::Dword, data)), Imm32(0x1000))
+            .unwrap();
+        codegen
+            .add(Reg(Rcx), Mem(M::label(Size::Qword, data)))
+            .unwrap();
+        codegen.bind_label(data).unwrap();
+        codegen
+            .mov(Reg(R9), Mem(M::label(Size::Qword, data)))
+            .unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -551,57 +3165,191 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0x01, 0xd8, // add rax, rbx
-                0x48, 0x81, 0xc0, 0x34, 0x12, 0x00, 0x00, // add rax, 0x1234
-                0x49, 0x01, 0xef, // add r15, rbp
-                0x49, 0x81, 0xc0, 0x48, 0x47, 0x46, 0x45, // add r8, 0x45464748
+                0x48, 0x8b, 0x05, 0x11, 0x00, 0x00, 0x00, // mov rax, [rip + data]
+                0x81, 0x3d, 0x07, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+                0x00, // cmp dword [rip + data], 0x1000
+                0x48, 0x03, 0x0d, 0x00, 0x00, 0x00, 0x00, // add rcx, [rip + data]
+                0x4c, 0x8b, 0x0d, 0xf9, 0xff, 0xff, 0xff, // data: mov r9, [rip + data]
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_call() {
+    fn test_x86_64_codegen_constant_pool() {
+        use super::Mem as M;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.call(Reg(Rax));
-        codegen.call(Reg(R15));
-
-        codegen.dump_generated_code(0);
+        let answer = codegen.add_constant(&42u64.to_le_bytes()).unwrap();
+        let mask = codegen.add_constant(&[0xff; 16]).unwrap();
+        codegen
+            .mov(Reg(Rax), Mem(M::constant(Size::Qword, answer)))
+            .unwrap();
+        codegen
+            .and(Mem(M::constant(Size::Byte, mask)), Imm8(0x7f))
+            .unwrap();
+        let same = codegen.add_constant(&42u64.to_le_bytes()).unwrap();
+        assert_eq!(same, answer);
+        codegen
+            .add(Reg(Rax), Mem(M::constant(Size::Qword, same)))
+            .unwrap();
+        codegen.ret().unwrap();
+        codegen.finalize().unwrap();
 
         let code = codegen.code();
         assert_eq!(
             code,
             &[
-                0x48, 0xff, 0xd0, // call rax
-                0x49, 0xff, 0xd7, // call r15
+                0x48, 0x8b, 0x05, 0x29, 0x00, 0x00, 0x00, // mov rax, [rip + answer]
+                0x80, 0x25, 0x12, 0x00, 0x00, 0x00, 0x7f, // and byte [rip + mask], 0x7f
+                0x48, 0x03, 0x05, 0x1b, 0x00, 0x00, 0x00, // add rax, [rip + answer]
+                0xc3, // ret
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                0xff, 0xff, // mask
+                0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // answer
             ]
         );
     }
 
     #[test]
-    fn test_x86_64_codegen_ret() {
+    fn test_x86_64_codegen_sse2() {
+        use super::Mem as M;
+        use super::Reg32::*;
+        use super::Xmm::*;
+        use Operand::*;
+        use Reg64::*;
+
         let mut codegen = X86Asm::new();
-        codegen.ret();
+        codegen.movsd(Xmm(Xmm0), Xmm(Xmm1)).unwrap();
+        codegen.movsd(Xmm(Xmm8), MemDisp(Rax, 8)).unwrap();
+        codegen.movsd(Mem(M::qword(R12, 0x100)), Xmm(Xmm9)).unwrap();
+        codegen.movq(Xmm(Xmm1), Reg(Rax)).unwrap();
+        codegen.movq(Xmm(Xmm10), Reg(R11)).unwrap();
+        codegen.movq(Reg(Rdx), Xmm(Xmm15)).unwrap();
+        codegen.movq(MemDisp(Rsp, 8), Xmm(Xmm2)).unwrap();
+        codegen.movq(Xmm(Xmm3), MemAbs(Rbx)).unwrap();
+        codegen.movq(Xmm(Xmm4), Xmm(Xmm12)).unwrap();
+        codegen.addsd(Xmm(Xmm0), Xmm(Xmm1)).unwrap();
+        codegen.subsd(Xmm(Xmm9), MemDisp(Rbp, -8)).unwrap();
+        codegen.mulsd(Xmm(Xmm2), Xmm(Xmm10)).unwrap();
+        codegen.divsd(Xmm(Xmm3), Xmm(Xmm4)).unwrap();
+        codegen.sqrtsd(Xmm(Xmm5), Xmm(Xmm13)).unwrap();
+        codegen.ucomisd(Xmm(Xmm0), Xmm(Xmm1)).unwrap();
+        codegen
+            .ucomisd(Xmm(Xmm14), Mem(M::rip(Size::Qword, 0x10)))
+            .unwrap();
+        codegen.xorpd(Xmm(Xmm0), Xmm(Xmm0)).unwrap();
+        codegen.xorpd(Xmm(Xmm11), MemAbs(Rax)).unwrap();
+        codegen.cvtsi2sd(Xmm(Xmm0), Reg(Rax)).unwrap();
+        codegen.cvtsi2sd(Xmm(Xmm1), Reg32(R9d)).unwrap();
+        codegen.cvtsi2sd(Xmm(Xmm8), MemAbs(Rdi)).unwrap();
+        codegen.cvttsd2si(Reg(Rax), Xmm(Xmm0)).unwrap();
+        codegen.cvttsd2si(Reg32(R10d), Xmm(Xmm9)).unwrap();
+
+        codegen.dump_generated_code(0);
 
         let code = codegen.code();
-        assert_eq!(code, &[0xc3]); // ret
+        assert_eq!(
+            code,
+            &[
+                0xf2, 0x0f, 0x10, 0xc1, // movsd xmm0, xmm1
+                0xf2, 0x44, 0x0f, 0x10, 0x40, 0x08, // movsd xmm8, [rax + 8]
+                0xf2, 0x45, 0x0f, 0x11, 0x8c, 0x24, 0x00, 0x01, 0x00,
+                0x00, // movsd [r12 + 0x100], xmm9
+                0x66, 0x48, 0x0f, 0x6e, 0xc8, // movq xmm1, rax
+                0x66, 0x4d, 0x0f, 0x6e, 0xd3, // movq xmm10, r11
+                0x66, 0x4c, 0x0f, 0x7e, 0xfa, // movq rdx, xmm15
+                0x66, 0x48, 0x0f, 0x7e, 0x54, 0x24, 0x08, // movq [rsp + 8], xmm2
+                0x66, 0x48, 0x0f, 0x6e, 0x1b, // movq xmm3, [rbx]
+                0xf3, 0x41, 0x0f, 0x7e, 0xe4, // movq xmm4, xmm12
+                0xf2, 0x0f, 0x58, 0xc1, // addsd xmm0, xmm1
+                0xf2, 0x44, 0x0f, 0x5c, 0x4d, 0xf8, // subsd xmm9, [rbp - 8]
+                0xf2, 0x41, 0x0f, 0x59, 0xd2, // mulsd xmm2, xmm10
+                0xf2, 0x0f, 0x5e, 0xdc, // divsd xmm3, xmm4
+                0xf2, 0x41, 0x0f, 0x51, 0xed, // sqrtsd xmm5, xmm13
+                0x66, 0x0f, 0x2e, 0xc1, // ucomisd xmm0, xmm1
+                0x66, 0x44, 0x0f, 0x2e, 0x35, 0x10, 0x00, 0x00,
+                0x00, // ucomisd xmm14, [rip + 0x10]
+                0x66, 0x0f, 0x57, 0xc0, // xorpd xmm0, xmm0
+                0x66, 0x44, 0x0f, 0x57, 0x18, // xorpd xmm11, [rax]
+                0xf2, 0x48, 0x0f, 0x2a, 0xc0, // cvtsi2sd xmm0, rax
+                0xf2, 0x41, 0x0f, 0x2a, 0xc9, // cvtsi2sd xmm1, r9d
+                0xf2, 0x4c, 0x0f, 0x2a, 0x07, // cvtsi2sd xmm8, [rdi]
+                0xf2, 0x48, 0x0f, 0x2c, 0xc0, // cvttsd2si rax, xmm0
+                0xf2, 0x45, 0x0f, 0x2c, 0xd1, // cvttsd2si r10d, xmm9
+            ]
+        );
     }
 
     #[test]
-    fn test_x86_64_codegen_mov() {
+    fn test_x86_64_codegen_sse2_invalid_operands() {
+        use super::Reg8::*;
+        use super::Xmm::*;
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        assert!(codegen.addsd(Reg(Rax), Xmm(Xmm0)).is_err());
+        assert!(codegen.addsd(Xmm(Xmm0), Reg(Rax)).is_err());
+        assert!(codegen
+            .movsd(Xmm(Xmm0), Mem(super::Mem::dword(Rax, 0)))
+            .is_err());
+        assert!(codegen.movq(Xmm(Xmm0), Reg8(Al)).is_err());
+        assert!(codegen.cvtsi2sd(Xmm(Xmm0), Xmm(Xmm1)).is_err());
+        assert!(codegen.cvttsd2si(Reg8(Al), Xmm(Xmm1)).is_err());
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_avx() {
+        use super::Mem as M;
+        use super::Xmm::*;
+        use super::Ymm::*;
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.mov(Reg(Rax), Imm64(0x123456789abcdef0));
-        codegen.mov(Reg(Rax), Reg(Rbx));
-        codegen.mov(MemDisp(Rax, 0x1337), Reg(Rbx));
-        codegen.mov(Reg(Rax), MemDisp(Rbx, 0x41414141));
-        codegen.mov(Reg(R8), Imm64(0x1234));
-        codegen.mov(Reg(Rsp), Reg(R15));
-        codegen.mov(MemDisp(R15, 0x12345678), Imm64(0x41424344));
+        codegen.vmovupd(Ymm(Ymm0), MemAbs(Rax)).unwrap();
+        codegen.vmovupd(Ymm(Ymm9), MemDisp(R12, 0x20)).unwrap();
+        codegen
+            .vmovupd(
+                Mem(M::implied(Rdi, 0).with_index(Rcx, Scale::X8)),
+                Ymm(Ymm15),
+            )
+            .unwrap();
+        codegen.vmovupd(Xmm(Xmm1), Xmm(Xmm2)).unwrap();
+        codegen.vmovupd(Ymm(Ymm3), Ymm(Ymm12)).unwrap();
+        codegen.vaddpd(Ymm(Ymm0), Ymm(Ymm1), Ymm(Ymm2)).unwrap();
+        codegen.vaddpd(Ymm(Ymm8), Ymm(Ymm9), Ymm(Ymm10)).unwrap();
+        codegen.vaddpd(Xmm(Xmm0), Xmm(Xmm1), MemAbs(Rax)).unwrap();
+        codegen
+            .vaddpd(
+                Ymm(Ymm2),
+                Ymm(Ymm3),
+                Mem(M::implied(R9, 0).with_index(R10, Scale::X8)),
+            )
+            .unwrap();
+        codegen.vmulpd(Ymm(Ymm0), Ymm(Ymm14), Ymm(Ymm1)).unwrap();
+        codegen.vmulpd(Xmm(Xmm4), Xmm(Xmm5), Xmm(Xmm6)).unwrap();
+        codegen
+            .vfmadd231pd(Ymm(Ymm0), Ymm(Ymm1), Ymm(Ymm2))
+            .unwrap();
+        codegen
+            .vfmadd231pd(Xmm(Xmm3), Xmm(Xmm4), Xmm(Xmm13))
+            .unwrap();
+        codegen
+            .vfmadd231pd(Ymm(Ymm8), Ymm(Ymm9), MemDisp(Rsp, 0x40))
+            .unwrap();
+        codegen.vpaddq(Ymm(Ymm0), Ymm(Ymm1), Ymm(Ymm2)).unwrap();
+        codegen.vpaddq(Xmm(Xmm7), Xmm(Xmm8), Xmm(Xmm9)).unwrap();
+        codegen.vbroadcastsd(Ymm(Ymm0), MemAbs(Rax)).unwrap();
+        codegen
+            .vbroadcastsd(Ymm(Ymm11), Mem(M::rip(Size::Qword, 0x10)))
+            .unwrap();
+        codegen.vbroadcastsd(Ymm(Ymm1), Xmm(Xmm2)).unwrap();
+        codegen.vbroadcastsd(Ymm(Ymm12), Xmm(Xmm13)).unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -609,26 +3357,152 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0xb8, 0xf0, 0xde, 0xbc, 0x9a, 0x78, 0x56, 0x34, 0x12, // mov rax, 0x1234
-                0x48, 0x89, 0xd8, // mov rax, rbx
-                0x48, 0x89, 0x98, 0x37, 0x13, 0x00, 0x00, // mov [rax], rbx
-                0x48, 0x8b, 0x83, 0x41, 0x41, 0x41, 0x41, // mov rbx, [rax]
-                0x49, 0xb8, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r8, 0x1234
-                0x4c, 0x89, 0xfc, // mov rsp, r15
-                0x49, 0xc7, 0x87, 0x78, 0x56, 0x34, 0x12, 0x44, 0x43, 0x42,
-                0x41, // mov [r15], 0x41424344
+                0xc5, 0xfd, 0x10, 0x00, // vmovupd ymm0, [rax]
+                0xc4, 0x41, 0x7d, 0x10, 0x4c, 0x24, 0x20, // vmovupd ymm9, [r12 + 0x20]
+                0xc5, 0x7d, 0x11, 0x3c, 0xcf, // vmovupd [rdi + rcx * 8], ymm15
+                0xc5, 0xf9, 0x10, 0xca, // vmovupd xmm1, xmm2
+                0xc5, 0x7d, 0x11, 0xe3, // vmovupd ymm3, ymm12
+                0xc5, 0xf5, 0x58, 0xc2, // vaddpd ymm0, ymm1, ymm2
+                0xc4, 0x41, 0x35, 0x58, 0xc2, // vaddpd ymm8, ymm9, ymm10
+                0xc5, 0xf1, 0x58, 0x00, // vaddpd xmm0, xmm1, [rax]
+                0xc4, 0x81, 0x65, 0x58, 0x14, 0xd1, // vaddpd ymm2, ymm3, [r9 + r10 * 8]
+                0xc5, 0x8d, 0x59, 0xc1, // vmulpd ymm0, ymm14, ymm1
+                0xc5, 0xd1, 0x59, 0xe6, // vmulpd xmm4, xmm5, xmm6
+                0xc4, 0xe2, 0xf5, 0xb8, 0xc2, // vfmadd231pd ymm0, ymm1, ymm2
+                0xc4, 0xc2, 0xd9, 0xb8, 0xdd, // vfmadd231pd xmm3, xmm4, xmm13
+                0xc4, 0x62, 0xb5, 0xb8, 0x44, 0x24,
+                0x40, // vfmadd231pd ymm8, ymm9, [rsp + 0x40]
+                0xc5, 0xf5, 0xd4, 0xc2, // vpaddq ymm0, ymm1, ymm2
+                0xc4, 0xc1, 0x39, 0xd4, 0xf9, // vpaddq xmm7, xmm8, xmm9
+                0xc4, 0xe2, 0x7d, 0x19, 0x00, // vbroadcastsd ymm0, [rax]
+                0xc4, 0x62, 0x7d, 0x19, 0x1d, 0x10, 0x00, 0x00,
+                0x00, // vbroadcastsd ymm11, [rip + 0x10]
+                0xc4, 0xe2, 0x7d, 0x19, 0xca, // vbroadcastsd ymm1, xmm2
+                0xc4, 0x42, 0x7d, 0x19, 0xe5, // vbroadcastsd ymm12, xmm13
             ]
         );
     }
 
+    #[test]
+    fn test_x86_64_codegen_avx_invalid_operands() {
+        use super::Xmm::*;
+        use super::Ymm::*;
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        // Mixed vector lengths.
+        assert!(codegen.vaddpd(Ymm(Ymm0), Xmm(Xmm1), Ymm(Ymm2)).is_err());
+        assert!(codegen.vmulpd(Xmm(Xmm0), Xmm(Xmm1), Ymm(Ymm2)).is_err());
+        assert!(codegen.vmovupd(Ymm(Ymm0), Xmm(Xmm1)).is_err());
+        // General-purpose registers.
+        assert!(codegen.vpaddq(Ymm(Ymm0), Ymm(Ymm1), Reg(Rax)).is_err());
+        // vbroadcastsd only has a 256-bit form.
+        assert!(codegen.vbroadcastsd(Xmm(Xmm0), MemAbs(Rax)).is_err());
+        assert!(codegen.vbroadcastsd(Ymm(Ymm0), Ymm(Ymm1)).is_err());
+        assert!(codegen
+            .vbroadcastsd(Ymm(Ymm0), Mem(super::Mem::dword(Rax, 0)))
+            .is_err());
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_rsp_index() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        let mem = super::Mem::qword(Rax, 0).with_index(Rsp, Scale::X1);
+        assert_eq!(
+            codegen.mov(Reg(Rcx), Mem(mem)),
+            Err(EncodeError::UnsupportedRegister(
+                "RSP can't be used as an index register"
+            ))
+        );
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_high_byte_with_rex() {
+        use super::Reg8::*;
+        use Operand::*;
+
+        let mut codegen = X86Asm::new();
+        assert_eq!(
+            codegen.mov(Reg8(Ah), Reg8(R8b)),
+            Err(EncodeError::UnsupportedRegister(
+                "AH, CH, DH and BH can't be encoded with a REX prefix"
+            ))
+        );
+        assert!(codegen.code().is_empty());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_size_mismatch() {
+        use super::Reg32::*;
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        assert_eq!(
+            codegen.mov(Reg(Rax), Reg32(Ecx)),
+            Err(EncodeError::InvalidOperands {
+                mnemonic: "mov",
+                operands: "[Reg(Rax), Reg32(Ecx)]".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_invalid_operands() {
+        use Operand::*;
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        assert!(matches!(
+            codegen.mov(Imm64(1), Reg(Rax)),
+            Err(EncodeError::InvalidOperands {
+                mnemonic: "mov",
+                ..
+            })
+        ));
+        assert!(matches!(
+            codegen.neg(Imm64(1)),
+            Err(EncodeError::InvalidOperands {
+                mnemonic: "neg",
+                ..
+            })
+        ));
+        assert!(matches!(
+            codegen.call(Imm64(0x1000)),
+            Err(EncodeError::InvalidOperands {
+                mnemonic: "call",
+                ..
+            })
+        ));
+
+        let label = codegen.new_label();
+        codegen.bind_label(label).unwrap();
+        assert_eq!(
+            codegen.bind_label(label),
+            Err(EncodeError::LabelAlreadyBound(label))
+        );
+        assert!(codegen.code().is_empty());
+    }
+
     #[test]
     fn test_x86_64_codegen_push() {
         use Operand::*;
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.push(Reg(Rax));
-        codegen.push(Imm64(0x12345678));
+        codegen.push(Reg(Rax)).unwrap();
+        codegen.push(Reg(R12)).unwrap();
+        codegen.push(Imm64(0x12345678)).unwrap();
+        codegen.push(Imm64(-1)).unwrap();
+        codegen.push(Imm8(0x7f)).unwrap();
+        codegen.push(Imm32(0x80)).unwrap();
+        codegen.push(Imm64(-0x8000_0000)).unwrap();
 
         codegen.dump_generated_code(0);
 
@@ -636,10 +3510,24 @@ mod tests {
         assert_eq!(
             code,
             &[
-                0x48, 0x50, // push rax
+                0x50, // push rax
+                0x41, 0x54, // push r12
                 0x68, 0x78, 0x56, 0x34, 0x12, // push 0x12345678
+                0x6a, 0xff, // push -1
+                0x6a, 0x7f, // push 0x7f
+                0x68, 0x80, 0x00, 0x00, 0x00, // push 0x80
+                0x68, 0x00, 0x00, 0x00, 0x80, // push -0x80000000
             ]
         );
+
+        // The immediate is sign-extended, so 0xffffffff would push -1.
+        for imm in [0xffff_ffff, 0x8000_0000, i64::MIN] {
+            assert!(matches!(
+                codegen.push(Imm64(imm)),
+                Err(EncodeError::ImmediateOutOfRange(_))
+            ));
+        }
+        assert!(codegen.push(Reg32(super::Reg32::Eax)).is_err());
     }
 
     #[test]
@@ -648,11 +3536,390 @@ mod tests {
         use Reg64::*;
 
         let mut codegen = X86Asm::new();
-        codegen.pop(Reg(Rax));
+        codegen.pop(Reg(Rax)).unwrap();
+        codegen.pop(Reg(R15)).unwrap();
 
         codegen.dump_generated_code(0);
 
         let code = codegen.code();
-        assert_eq!(code, &[0x48, 0x58]); // pop rax
+        assert_eq!(
+            code,
+            &[
+                0x58, // pop rax
+                0x41, 0x5f, // pop r15
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_extend() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.movzx(Reg32::Eax, Mem::byte(Rdi, 0)).unwrap();
+        codegen.movzx(R9, Mem::word(Rsp, 8)).unwrap();
+        codegen.movzx(Reg32::Esi, Reg8::Dil).unwrap();
+        codegen.movzx(Reg16::Ax, Reg8::Ah).unwrap();
+        codegen.movsx(Rax, Reg8::Cl).unwrap();
+        codegen.movsx(Reg32::R10d, Reg16::R11w).unwrap();
+        codegen.movsxd(Rax, Reg32::Ecx).unwrap();
+        codegen.movsxd(R8, Mem::dword(Rsi, -4)).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x0f, 0xb6, 0x07, // movzx eax, byte ptr [rdi]
+                0x4c, 0x0f, 0xb7, 0x4c, 0x24, 0x08, // movzx r9, word ptr [rsp + 8]
+                0x40, 0x0f, 0xb6, 0xf7, // movzx esi, dil
+                0x66, 0x0f, 0xb6, 0xc4, // movzx ax, ah
+                0x48, 0x0f, 0xbe, 0xc1, // movsx rax, cl
+                0x45, 0x0f, 0xbf, 0xd3, // movsx r10d, r11w
+                0x48, 0x63, 0xc1, // movsxd rax, ecx
+                0x4c, 0x63, 0x46, 0xfc, // movsxd r8, dword ptr [rsi - 4]
+            ]
+        );
+
+        // The source must be smaller than the destination, and memory sources need a size.
+        let invalid = [
+            codegen.movzx(Operand::Reg16(Reg16::Ax), Operand::Reg16(Reg16::Cx)),
+            codegen.movzx(Rax, Mem::dword(Rdi, 0)),
+            codegen.movsx(Rax, Mem::implied(Rdi, 0)),
+            codegen.movsxd(Operand::Reg32(Reg32::Eax), Operand::Reg32(Reg32::Ecx)),
+            codegen.movzx(R8, Reg8::Ah),
+        ];
+        assert!(invalid.iter().all(Result::is_err));
+        assert_eq!(codegen.code().len(), 32);
+    }
+
+    #[test]
+    fn test_x86_64_codegen_imul_forms() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.imul_2(Rax, Rcx).unwrap();
+        codegen.imul_2(Reg32::R9d, Mem::dword(Rdi, 0)).unwrap();
+        codegen.imul_2(Reg16::Ax, Reg16::Bx).unwrap();
+        codegen.imul_3(Rdx, Rcx, 100).unwrap();
+        codegen
+            .imul_3(Reg32::Eax, Mem::dword(Rbx, 8), 0x12345)
+            .unwrap();
+        codegen.imul_3(Reg16::Cx, Reg16::Dx, 0x7fffi16).unwrap();
+        codegen.imul_3(Rax, Rcx, -1).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x48, 0x0f, 0xaf, 0xc1, // imul rax, rcx
+                0x44, 0x0f, 0xaf, 0x0f, // imul r9d, dword ptr [rdi]
+                0x66, 0x0f, 0xaf, 0xc3, // imul ax, bx
+                0x48, 0x6b, 0xd1, 0x64, // imul rdx, rcx, 100
+                0x69, 0x43, 0x08, 0x45, 0x23, 0x01,
+                0x00, // imul eax, dword ptr [rbx + 8], 0x12345
+                0x66, 0x69, 0xca, 0xff, 0x7f, // imul cx, dx, 0x7fff
+                0x48, 0x6b, 0xc1, 0xff, // imul rax, rcx, -1
+            ]
+        );
+
+        let (al, cl) = (Operand::Reg8(Reg8::Al), Operand::Reg8(Reg8::Cl));
+        assert!(codegen.imul_2(al, cl).is_err());
+        assert!(matches!(
+            codegen.imul_3(
+                Operand::Reg(Rax),
+                Operand::Reg(Rcx),
+                Operand::Imm64(1 << 32)
+            ),
+            Err(EncodeError::ImmediateOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_x86_64_codegen_xchg() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.xchg(Rax, Rcx).unwrap();
+        codegen.xchg(Rcx, Rax).unwrap();
+        codegen.xchg(Reg32::Eax, Reg32::Eax).unwrap();
+        codegen.xchg(Reg32::R8d, Reg32::Eax).unwrap();
+        codegen.xchg(Reg16::Ax, Reg16::R9w).unwrap();
+        codegen.xchg(Reg8::Al, Reg8::Cl).unwrap();
+        codegen.xchg(Rdx, Rbx).unwrap();
+        codegen.xchg(Mem::qword(Rdi, 0), Rsi).unwrap();
+        codegen.xchg(Reg32::Esi, Mem::dword(Rdi, 0)).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x48, 0x91, // xchg rax, rcx
+                0x48, 0x91, // xchg rcx, rax
+                0x87, 0xc0, // xchg eax, eax
+                0x41, 0x90, // xchg r8d, eax
+                0x66, 0x41, 0x91, // xchg ax, r9w
+                0x86, 0xc8, // xchg al, cl
+                0x48, 0x87, 0xda, // xchg rdx, rbx
+                0x48, 0x87, 0x37, // xchg qword ptr [rdi], rsi
+                0x87, 0x37, // xchg esi, dword ptr [rdi]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_codegen_int_misc() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.inc(Rax).unwrap();
+        codegen.inc(Reg8::R9b).unwrap();
+        codegen.dec(Mem::word(Rdi, 0)).unwrap();
+        codegen.dec(Mem::dword(Rsp, 4)).unwrap();
+        codegen.adc(Rax, Rcx).unwrap();
+        codegen.adc(Reg32::R8d, 1).unwrap();
+        codegen.adc(Rcx, Mem::qword(Rdx, 0)).unwrap();
+        codegen.bswap(Reg32::Eax).unwrap();
+        codegen.bswap(R12).unwrap();
+        codegen.popcnt(Rax, Rcx).unwrap();
+        codegen.popcnt(Reg16::R9w, Mem::word(Rdi, 0)).unwrap();
+        codegen.lzcnt(Reg32::Eax, Reg32::R15d).unwrap();
+        codegen.tzcnt(Rdx, Mem::rip(Size::Qword, 0x10)).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x48, 0xff, 0xc0, // inc rax
+                0x41, 0xfe, 0xc1, // inc r9b
+                0x66, 0xff, 0x0f, // dec word ptr [rdi]
+                0xff, 0x4c, 0x24, 0x04, // dec dword ptr [rsp + 4]
+                0x48, 0x11, 0xc8, // adc rax, rcx
+                0x41, 0x83, 0xd0, 0x01, // adc r8d, 1
+                0x48, 0x13, 0x0a, // adc rcx, qword ptr [rdx]
+                0x0f, 0xc8, // bswap eax
+                0x49, 0x0f, 0xcc, // bswap r12
+                0xf3, 0x48, 0x0f, 0xb8, 0xc1, // popcnt rax, rcx
+                0x66, 0xf3, 0x44, 0x0f, 0xb8, 0x0f, // popcnt r9w, word ptr [rdi]
+                0xf3, 0x41, 0x0f, 0xbd, 0xc7, // lzcnt eax, r15d
+                0xf3, 0x48, 0x0f, 0xbc, 0x15, 0x10, 0x00, 0x00,
+                0x00, // tzcnt rdx, qword ptr [rip + 0x10]
+            ]
+        );
+
+        assert!(codegen.bswap(Operand::Reg16(Reg16::Ax)).is_err());
+        let (al, cl) = (Operand::Reg8(Reg8::Al), Operand::Reg8(Reg8::Cl));
+        assert!(codegen.popcnt(al, cl).is_err());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_mem_operands() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        let slot = codegen.new_label();
+        codegen.add(Mem::qword(R14, -8), Rax).unwrap();
+        codegen.sub(Mem::dword(Rdi, 0), 1).unwrap();
+        codegen.sbb(Mem::word(Rsp, 2), 0x1234i16).unwrap();
+        codegen.adc(Mem::byte(Rax, 0), Reg8::Cl).unwrap();
+        codegen.neg(Mem::qword(Rdi, 0)).unwrap();
+        codegen.mul(Mem::dword(Rsi, 4)).unwrap();
+        codegen.imul(Mem::qword(R12, 0)).unwrap();
+        codegen.div(Mem::byte(Rbp, -1)).unwrap();
+        codegen.idiv(Mem::word(Rdi, 0)).unwrap();
+        codegen.call(Mem::rip(Size::Qword, 0x10)).unwrap();
+        codegen.call(Mem::implied(Rax, 0)).unwrap();
+        let table = Mem::qword(R13, 0).with_index(Rcx, Scale::X8);
+        codegen.jmp(table).unwrap();
+        codegen.push(Mem::qword(Rbx, 0)).unwrap();
+        codegen.push(Mem::implied(R8, 16)).unwrap();
+        codegen.pop(Mem::qword(Rsp, 0)).unwrap();
+        codegen.pop(Mem::qword(R15, 0)).unwrap();
+        codegen.call(Mem::label(Size::Qword, slot)).unwrap();
+        codegen.ret().unwrap();
+        codegen.bind_label(slot).unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x49, 0x01, 0x46, 0xf8, // add qword ptr [r14 - 8], rax
+                0x83, 0x2f, 0x01, // sub dword ptr [rdi], 1
+                0x66, 0x81, 0x5c, 0x24, 0x02, 0x34, 0x12, // sbb word ptr [rsp + 2], 0x1234
+                0x10, 0x08, // adc byte ptr [rax], cl
+                0x48, 0xf7, 0x1f, // neg qword ptr [rdi]
+                0xf7, 0x66, 0x04, // mul dword ptr [rsi + 4]
+                0x49, 0xf7, 0x2c, 0x24, // imul qword ptr [r12]
+                0xf6, 0x75, 0xff, // div byte ptr [rbp - 1]
+                0x66, 0xf7, 0x3f, // idiv word ptr [rdi]
+                0xff, 0x15, 0x10, 0x00, 0x00, 0x00, // call qword ptr [rip + 0x10]
+                0xff, 0x10, // call qword ptr [rax]
+                0x41, 0xff, 0x64, 0xcd, 0x00, // jmp qword ptr [r13 + 8*rcx]
+                0xff, 0x33, // push qword ptr [rbx]
+                0x41, 0xff, 0x70, 0x10, // push qword ptr [r8 + 16]
+                0x8f, 0x04, 0x24, // pop qword ptr [rsp]
+                0x41, 0x8f, 0x07, // pop qword ptr [r15]
+                0xff, 0x15, 0x01, 0x00, 0x00, 0x00, // call qword ptr [rip + slot]
+                0xc3, // ret
+            ]
+        );
+
+        // Stack and branch operands are always 64-bit.
+        let invalid = [
+            codegen.push(Mem::dword(Rbx, 0)),
+            codegen.pop(Mem::word(Rbx, 0)),
+            codegen.call(Mem::byte(Rax, 0)),
+            codegen.jmp(Mem::dword(Rax, 0)),
+        ];
+        assert!(invalid.iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn test_x86_64_codegen_relocations() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        codegen.call_symbol("f").unwrap();
+        codegen.lea_symbol(R9, "table").unwrap();
+        codegen.mov_symbol(Rax, "pow").unwrap();
+        codegen.jmp_symbol("f").unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0xe8, 0x00, 0x00, 0x00, 0x00, // call f
+                0x4c, 0x8d, 0x0d, 0x00, 0x00, 0x00, 0x00, // lea r9, [rip + table]
+                0x48, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // movabs rax, pow
+                0xe9, 0x00, 0x00, 0x00, 0x00, // jmp f
+            ]
+        );
+
+        let symbol_ref = |offset, kind, symbol: &str| SymbolRef {
+            offset,
+            kind,
+            symbol: symbol.to_string(),
+        };
+        assert_eq!(
+            codegen.symbol_refs(),
+            [
+                symbol_ref(1, RelocKind::Rel32, "f"),
+                symbol_ref(8, RelocKind::Rip32, "table"),
+                symbol_ref(14, RelocKind::Abs64, "pow"),
+                symbol_ref(23, RelocKind::Rel32, "f"),
+            ]
+        );
+        let mnemonics: Vec<_> = codegen.instructions().iter().map(|i| i.mnemonic).collect();
+        assert_eq!(mnemonics, ["call", "lea", "mov", "jmp"]);
+
+        // Code at 0x1000, `f` at 0x2000.
+        let rel32 = &codegen.symbol_refs()[0];
+        assert_eq!(
+            rel32.resolve(0x1000, 0x2000),
+            Some(0xffbu32.to_le_bytes().to_vec())
+        );
+        assert_eq!(
+            rel32.resolve(0x1000, 0x1000),
+            Some((-5i32).to_le_bytes().to_vec())
+        );
+        assert_eq!(rel32.resolve(0x1000, 0x1_0000_1005), None);
+        let abs64 = &codegen.symbol_refs()[2];
+        assert_eq!(abs64.size(), 8);
+        assert_eq!(
+            abs64.resolve(0x1000, 0x1_0000_1005),
+            Some(0x1_0000_1005u64.to_le_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_x86_64_instruction_table() {
+        use Reg64::*;
+
+        let mut codegen = X86Asm::new();
+        let end = codegen.new_label();
+        codegen.mov(Rax, Rdi).unwrap();
+        codegen.cqo().unwrap();
+        codegen.idiv(Rsi).unwrap();
+        codegen.idiv(Reg32::Esi).unwrap();
+        codegen.jcc(Cond::E, end).unwrap();
+        codegen.test(Rax, Mem::qword(Rdi, 0)).unwrap();
+        assert!(codegen
+            .add(Operand::Reg(Rax), Operand::Reg32(Reg32::Ecx))
+            .is_err());
+        codegen.bind_label(end).unwrap();
+        codegen.ret().unwrap();
+
+        let insn = |offset, len, mnemonic, cond| InsnInfo {
+            offset,
+            len,
+            mnemonic,
+            cond,
+        };
+        assert_eq!(
+            codegen.instructions(),
+            &[
+                insn(0, 3, "mov", None),
+                insn(3, 2, "cqo", None),
+                insn(5, 3, "idiv", None),
+                insn(8, 2, "idiv", None),
+                insn(10, 6, "jcc", Some(Cond::E)),
+                insn(16, 3, "test", None),
+                insn(19, 1, "ret", None),
+            ]
+        );
+        assert_eq!(codegen.instruction_at(0).unwrap().offset, 0);
+        assert_eq!(codegen.instruction_at(7).unwrap().end(), 8);
+        assert_eq!(codegen.instruction_at(9).unwrap().end(), 10);
+        assert_eq!(codegen.instruction_at(19).unwrap().mnemonic, "ret");
+        assert!(codegen.instruction_at(20).is_none());
+    }
+
+    #[test]
+    fn test_x86_64_codegen_nop() {
+        let mut codegen = X86Asm::new();
+        for len in 1..=9 {
+            codegen.nop(len).unwrap();
+        }
+        assert_eq!(codegen.code(), NOPS.concat());
+
+        codegen.nop(0).unwrap();
+        codegen.nop(20).unwrap();
+        let lens: Vec<_> = codegen.instructions()[9..]
+            .iter()
+            .map(|insn| insn.len)
+            .collect();
+        assert_eq!(lens, [9, 9, 2]);
+
+        let disasm = codegen.disassemble(0);
+        assert!(disasm.iter().all(|insn| insn.mnemonic == "nop"));
+        assert_eq!(disasm.instructions().len(), 12);
+    }
+
+    #[test]
+    fn test_x86_64_codegen_align() {
+        let mut codegen = X86Asm::new();
+        codegen.align(16).unwrap();
+        assert_eq!(codegen.offset(), 0);
+
+        codegen.ret().unwrap();
+        codegen.align(16).unwrap();
+        assert_eq!(codegen.offset(), 16);
+        codegen.ret().unwrap();
+        codegen.align(32).unwrap();
+        assert_eq!(codegen.offset(), 32);
+        assert_eq!(codegen.code()[1..10], *NOPS[8]);
+        assert_eq!(codegen.code()[10..16], *NOPS[5]);
+
+        let texts: Vec<_> = codegen
+            .disassemble(0)
+            .iter()
+            .map(|insn| insn.mnemonic.clone())
+            .collect();
+        assert_eq!(texts, ["ret", "nop", "nop", "ret", "nop", "nop"]);
+        assert_eq!(codegen.instruction_at(12), codegen.instructions().get(2));
+        assert_eq!(codegen.instructions()[2].offset, 10);
+        assert_eq!(codegen.instructions()[2].len, 6);
+
+        assert!(matches!(
+            codegen.align(24),
+            Err(EncodeError::InvalidAlignment(24))
+        ));
+        assert!(matches!(
+            codegen.align(0),
+            Err(EncodeError::InvalidAlignment(0))
+        ));
     }
 }
@@ -0,0 +1,233 @@
+//! Disassembly of the generated code, see [`X86Asm::disassemble`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::X86Asm;
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Offset of the instruction in the code.
+    pub offset: usize,
+    /// Address of the instruction: the base address of the disassembly plus the offset.
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    /// Lowercase mnemonic, e.g. `mov`.
+    pub mnemonic: String,
+    /// The visible operands in Intel syntax, e.g. `[rdi+0x08]`. Branch targets
+    /// are absolute addresses.
+    pub operands: Vec<String>,
+    /// The whole instruction in Intel syntax, including prefixes such as `lock`.
+    pub text: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:016X} {:<24} {}",
+            self.address,
+            hex::encode(&self.bytes),
+            self.text
+        )
+    }
+}
+
+/// The instructions of a code buffer, with labels and comments attached to their offsets.
+///
+/// Formatting it with `Display` prints one instruction per line, preceded by its labels
+/// and followed by its comments:
+///
+/// ```text
+/// loop:
+/// 0x0000000000000000 4883c701                 add rdi, 0x01 ; next element
+/// ```
+///
+/// Bytes that don't decode to an instruction, such as constants or data emitted after
+/// the code, are shown as single `.byte` directives.
+#[derive(Debug, Clone, Default)]
+pub struct Disassembly {
+    instructions: Vec<Instruction>,
+    labels: BTreeMap<usize, Vec<String>>,
+    comments: BTreeMap<usize, Vec<String>>,
+}
+
+impl Disassembly {
+    /// Decode the code, as loaded at `base_addr`.
+    pub fn new(code: &[u8], base_addr: u64) -> Self {
+        use zydis::{Decoder, Formatter, OutputBuffer, VisibleOperands};
+
+        let fmt = Formatter::intel();
+        let dec = Decoder::new64();
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+
+        while offset < code.len() {
+            let address = base_addr + offset as u64;
+            let insn = match dec.decode_first::<VisibleOperands>(&code[offset..]) {
+                Ok(Some(insn)) => insn,
+                _ => {
+                    let byte = format!("{:#04x}", code[offset]);
+                    instructions.push(Instruction {
+                        offset,
+                        address,
+                        bytes: vec![code[offset]],
+                        mnemonic: ".byte".to_string(),
+                        text: format!(".byte {}", byte),
+                        operands: vec![byte],
+                    });
+                    offset += 1;
+                    continue;
+                }
+            };
+
+            // Format with the address of the instruction, so that branch targets and
+            // RIP-relative operands are shown as absolute addresses.
+            let operands = (0..insn.operands().len())
+                .map(|i| {
+                    let mut buffer = [0u8; 256];
+                    let mut buffer = OutputBuffer::new(&mut buffer);
+                    fmt.format_operand(Some(address), &insn, &mut buffer, i, None)
+                        .unwrap();
+                    buffer.as_str().unwrap().to_string()
+                })
+                .collect();
+            let len = insn.length as usize;
+            instructions.push(Instruction {
+                offset,
+                address,
+                bytes: code[offset..offset + len].to_vec(),
+                mnemonic: insn.mnemonic.static_string().unwrap_or("").to_string(),
+                operands,
+                text: fmt.format(Some(address), &insn).unwrap(),
+            });
+            offset += len;
+        }
+
+        Self {
+            instructions,
+            labels: BTreeMap::new(),
+            comments: BTreeMap::new(),
+        }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Instruction> {
+        self.instructions.iter()
+    }
+
+    /// Get the instruction starting at the offset, if any.
+    pub fn instruction_at(&self, offset: usize) -> Option<&Instruction> {
+        self.instructions
+            .binary_search_by_key(&offset, |insn| insn.offset)
+            .ok()
+            .map(|i| &self.instructions[i])
+    }
+
+    /// Add a label before the instruction at the offset. Annotations at offsets that
+    /// aren't the start of an instruction aren't shown.
+    pub fn label(&mut self, offset: usize, name: impl Into<String>) -> &mut Self {
+        self.labels.entry(offset).or_default().push(name.into());
+        self
+    }
+
+    /// Add a comment after the instruction at the offset.
+    pub fn comment(&mut self, offset: usize, text: impl Into<String>) -> &mut Self {
+        self.comments.entry(offset).or_default().push(text.into());
+        self
+    }
+}
+
+impl<'a> IntoIterator for &'a Disassembly {
+    type Item = &'a Instruction;
+    type IntoIter = std::slice::Iter<'a, Instruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for insn in &self.instructions {
+            for label in self.labels.get(&insn.offset).into_iter().flatten() {
+                writeln!(f, "{}:", label)?;
+            }
+            write!(f, "{}", insn)?;
+            if let Some(comments) = self.comments.get(&insn.offset) {
+                write!(f, " ; {}", comments.join("; "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl X86Asm {
+    /// Disassemble the code generated so far, as loaded at `base_addr`.
+    pub fn disassemble(&self, base_addr: u64) -> Disassembly {
+        Disassembly::new(self.code(), base_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86::Reg64::*;
+    use crate::arch::x86::{Cond, Mem};
+
+    #[test]
+    fn test_x86_64_disassemble() {
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        codegen.mov(Rax, Mem::qword(Rdi, 8)).unwrap();
+        codegen.add(Rdi, 1i32).unwrap();
+        codegen.jcc(Cond::Ne, start).unwrap();
+        codegen.ret().unwrap();
+
+        let mut disasm = codegen.disassemble(0x1000);
+        let insns = disasm.instructions();
+        assert_eq!(insns.len(), 4);
+        assert_eq!(
+            insns[0],
+            Instruction {
+                offset: 0,
+                address: 0x1000,
+                bytes: vec![0x48, 0x8b, 0x47, 0x08],
+                mnemonic: "mov".to_string(),
+                operands: vec!["rax".to_string(), "[rdi+0x08]".to_string()],
+                text: "mov rax, [rdi+0x08]".to_string(),
+            }
+        );
+        assert_eq!(insns[2].mnemonic, "jnz");
+        assert_eq!(insns[2].operands, ["0x0000000000001000"]);
+        assert_eq!(disasm.instruction_at(8).unwrap().bytes, [0x75, 0xf6]);
+        assert!(disasm.instruction_at(9).is_none());
+
+        disasm
+            .label(0, "loop")
+            .comment(4, "next")
+            .comment(4, "element");
+        assert_eq!(
+            disasm.to_string(),
+            "loop:\n\
+             0x0000000000001000 488b4708                 mov rax, [rdi+0x08]\n\
+             0x0000000000001004 4883c701                 add rdi, 0x01 ; next; element\n\
+             0x0000000000001008 75f6                     jnz 0x0000000000001000\n\
+             0x000000000000100A c3                       ret\n"
+        );
+    }
+
+    #[test]
+    fn test_x86_64_disassemble_invalid() {
+        let disasm = Disassembly::new(&[0x90, 0x06, 0xc3], 0);
+        let text: Vec<_> = disasm.iter().map(|insn| insn.text.as_str()).collect();
+        assert_eq!(text, ["nop", ".byte 0x06", "ret"]);
+        assert_eq!(disasm.instructions()[1].operands, ["0x06"]);
+    }
+}
@@ -1 +1,208 @@
+//! Statically typed operand forms of the x86-64 instructions.
+//!
+//! Every instruction has a trait with one implementation per valid operand combination,
+//! e.g. [`Mov<Reg64, i64>`] or [`Add<Reg32, Mem>`], and the corresponding method of
+//! [`X86Asm`](super::X86Asm) is generic over it. Invalid combinations fail to compile:
+//!
+//! ```compile_fail
+//! use spark_jit::arch::x86::{Mem, X86Asm};
+//! use spark_jit::arch::x86::Reg64::*;
+//!
+//! let mut codegen = X86Asm::new();
+//! codegen.mov(Mem::qword(Rdi, 0), Mem::qword(Rsi, 0)).unwrap();
+//! ```
+//!
+//! Every instruction is also implemented for [`Operand`]s, which are checked when the
+//! instruction is emitted. Constraints that don't depend on the kind of the operands,
+//! such as the size of a [`Mem`] matching the size of a register, are always checked
+//! at runtime.
+
+use super::{Mem, Operand, Reg16, Reg32, Reg64, Reg8, Xmm, Ymm};
+use crate::label::Label;
+
+/// Declare the trait of an instruction, the generic `X86Asm` method that dispatches to
+/// it and its implementations: one for [`Operand`]s and one per typed operand combination.
+/// All of them are emitted by the given `emit_*` method of `X86Asm`.
+macro_rules! instruction {
+    (
+        $(#[$attr:meta])*
+        $trait:ident::$method:ident $sig:tt => $emit:ident {
+            $($ops:tt),* $(,)?
+        }
+    ) => {
+        instruction!(@decl [$(#[$attr])*] $trait $method $emit $sig);
+        $(instruction!(@impl $trait $method $emit $sig $ops);)*
+    };
+
+    (@decl [$(#[$attr:meta])*] $trait:ident $method:ident $emit:ident
+        (cond: Cond, $($arg:ident: $ty:ident),+)) => {
+        #[doc = concat!("Operand forms of [`X86Asm::", stringify!($method), "`].")]
+        pub trait $trait<$($ty),+> {
+            fn $method(
+                &mut self,
+                cond: Cond,
+                $($arg: $ty),+
+            ) -> Result<(), crate::arch::EncodeError>;
+        }
+
+        impl X86Asm {
+            $(#[$attr])*
+            pub fn $method<$($ty),+>(
+                &mut self,
+                cond: Cond,
+                $($arg: $ty),+
+            ) -> Result<(), crate::arch::EncodeError>
+            where
+                Self: $trait<$($ty),+>,
+            {
+                $trait::$method(self, cond, $($arg),+)
+            }
+        }
+
+        instruction!(@impl $trait $method $emit (cond: Cond, $($arg: $ty),+)
+            ($(operand!($ty)),+));
+    };
+
+    (@decl [$(#[$attr:meta])*] $trait:ident $method:ident $emit:ident
+        ($($arg:ident: $ty:ident),+)) => {
+        #[doc = concat!("Operand forms of [`X86Asm::", stringify!($method), "`].")]
+        pub trait $trait<$($ty),+> {
+            fn $method(&mut self, $($arg: $ty),+) -> Result<(), crate::arch::EncodeError>;
+        }
+
+        impl X86Asm {
+            $(#[$attr])*
+            pub fn $method<$($ty),+>(
+                &mut self,
+                $($arg: $ty),+
+            ) -> Result<(), crate::arch::EncodeError>
+            where
+                Self: $trait<$($ty),+>,
+            {
+                $trait::$method(self, $($arg),+)
+            }
+        }
+
+        instruction!(@impl $trait $method $emit ($($arg: $ty),+) ($(operand!($ty)),+));
+    };
+
+    (@impl $trait:ident $method:ident $emit:ident
+        (cond: Cond, $($arg:ident: $ty:ident),+) ($($op:ty),+)) => {
+        impl $trait<$($op),+> for X86Asm {
+            fn $method(
+                &mut self,
+                cond: Cond,
+                $($arg: $op),+
+            ) -> Result<(), crate::arch::EncodeError> {
+                self.record(stringify!($method), Some(cond), |asm| {
+                    asm.$emit(cond, $($arg.into()),+)
+                })
+            }
+        }
+    };
+
+    (@impl $trait:ident $method:ident $emit:ident
+        ($($arg:ident: $ty:ident),+) ($($op:ty),+)) => {
+        impl $trait<$($op),+> for X86Asm {
+            fn $method(&mut self, $($arg: $op),+) -> Result<(), crate::arch::EncodeError> {
+                self.record(stringify!($method), None, |asm| asm.$emit($($arg.into()),+))
+            }
+        }
+    };
+}
+
+/// Expands to [`Operand`] for every operand of the dynamic form of an instruction.
+macro_rules! operand {
+    ($ty:ident) => {
+        Operand
+    };
+}
+
+mod alu;
+mod avx;
+mod branch;
 mod mov;
+mod sse;
+
+pub use alu::{
+    Adc, Add, And, Bswap, Cmp, Dec, Div, Idiv, Imul, Imul2, Imul3, Inc, Lzcnt, Mul, Neg, Not, Or,
+    Popcnt, Rol, Ror, Sar, Sbb, Shl, Shr, Sub, Test, Tzcnt, Xor,
+};
+pub use avx::{Vaddpd, Vbroadcastsd, Vfmadd231pd, Vmovupd, Vmulpd, Vpaddq};
+pub use branch::{Call, Jcc, Jmp};
+pub use mov::{Cmovcc, Lea, Mov, Movsx, Movsxd, Movzx, Pop, Push, Setcc, Xchg};
+pub use sse::{
+    Addsd, Cvtsi2sd, Cvttsd2si, Divsd, Movq, Movsd, Mulsd, Sqrtsd, Subsd, Ucomisd, Xorpd,
+};
+
+impl From<Reg64> for Operand {
+    fn from(reg: Reg64) -> Self {
+        Operand::Reg(reg)
+    }
+}
+
+impl From<Reg32> for Operand {
+    fn from(reg: Reg32) -> Self {
+        Operand::Reg32(reg)
+    }
+}
+
+impl From<Reg16> for Operand {
+    fn from(reg: Reg16) -> Self {
+        Operand::Reg16(reg)
+    }
+}
+
+impl From<Reg8> for Operand {
+    fn from(reg: Reg8) -> Self {
+        Operand::Reg8(reg)
+    }
+}
+
+impl From<Xmm> for Operand {
+    fn from(xmm: Xmm) -> Self {
+        Operand::Xmm(xmm)
+    }
+}
+
+impl From<Ymm> for Operand {
+    fn from(ymm: Ymm) -> Self {
+        Operand::Ymm(ymm)
+    }
+}
+
+impl From<Mem> for Operand {
+    fn from(mem: Mem) -> Self {
+        Operand::Mem(mem)
+    }
+}
+
+impl From<Label> for Operand {
+    fn from(label: Label) -> Self {
+        Operand::Label(label)
+    }
+}
+
+impl From<i64> for Operand {
+    fn from(imm: i64) -> Self {
+        Operand::Imm64(imm)
+    }
+}
+
+impl From<i32> for Operand {
+    fn from(imm: i32) -> Self {
+        Operand::Imm32(imm)
+    }
+}
+
+impl From<i16> for Operand {
+    fn from(imm: i16) -> Self {
+        Operand::Imm16(imm)
+    }
+}
+
+impl From<i8> for Operand {
+    fn from(imm: i8) -> Self {
+        Operand::Imm8(imm)
+    }
+}
@@ -0,0 +1,231 @@
+use crate::arch::x86::{Mem, Operand, Reg16, Reg32, Reg64, Reg8, X86Asm};
+
+instruction! {
+    Add::add(dst: Dst, src: Src) => emit_add {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Sub::sub(dst: Dst, src: Src) => emit_sub {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Sbb::sbb(dst: Dst, src: Src) => emit_sbb {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    /// Add with carry.
+    Adc::adc(dst: Dst, src: Src) => emit_adc {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Cmp::cmp(dst: Dst, src: Src) => emit_cmp {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    And::and(dst: Dst, src: Src) => emit_and {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Or::or(dst: Dst, src: Src) => emit_or {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Xor::xor(dst: Dst, src: Src) => emit_xor {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Test::test(dst: Dst, src: Src) => emit_test {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i32), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    Not::not(dst: Dst) => emit_not {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    Inc::inc(dst: Dst) => emit_inc {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    Dec::dec(dst: Dst) => emit_dec {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    Neg::neg(dst: Dst) => emit_neg {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    Mul::mul(src: Src) => emit_mul {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    Imul::imul(src: Src) => emit_imul {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    /// Signed multiply of the destination by the source, truncated to the size of the
+    /// destination. Unlike [`X86Asm::imul`], RDX is left untouched.
+    Imul2::imul_2(dst: Dst, src: Src) => emit_imul_2 {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
+
+instruction! {
+    /// Signed multiply of the source by an immediate into the destination, truncated to
+    /// the size of the destination.
+    Imul3::imul_3(dst: Dst, src: Src, imm: Imm) => emit_imul_3 {
+        (Reg64, Reg64, i32), (Reg32, Reg32, i32), (Reg16, Reg16, i16),
+        (Reg64, Mem, i32), (Reg32, Mem, i32), (Reg16, Mem, i16),
+    }
+}
+
+instruction! {
+    Div::div(src: Src) => emit_div {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    Idiv::idiv(src: Src) => emit_idiv {
+        (Reg64), (Reg32), (Reg16), (Reg8), (Mem),
+    }
+}
+
+instruction! {
+    /// Rotate left. The count is an immediate or CL.
+    Rol::rol(dst: Dst, count: Count) => emit_rol {
+        (Reg64, i8), (Reg32, i8), (Reg16, i8), (Reg8, i8), (Mem, i8),
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg8, Reg8), (Mem, Reg8),
+    }
+}
+
+instruction! {
+    /// Rotate right. The count is an immediate or CL.
+    Ror::ror(dst: Dst, count: Count) => emit_ror {
+        (Reg64, i8), (Reg32, i8), (Reg16, i8), (Reg8, i8), (Mem, i8),
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg8, Reg8), (Mem, Reg8),
+    }
+}
+
+instruction! {
+    /// Shift left. The count is an immediate or CL.
+    Shl::shl(dst: Dst, count: Count) => emit_shl {
+        (Reg64, i8), (Reg32, i8), (Reg16, i8), (Reg8, i8), (Mem, i8),
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg8, Reg8), (Mem, Reg8),
+    }
+}
+
+instruction! {
+    /// Logical shift right. The count is an immediate or CL.
+    Shr::shr(dst: Dst, count: Count) => emit_shr {
+        (Reg64, i8), (Reg32, i8), (Reg16, i8), (Reg8, i8), (Mem, i8),
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg8, Reg8), (Mem, Reg8),
+    }
+}
+
+instruction! {
+    /// Arithmetic shift right. The count is an immediate or CL.
+    Sar::sar(dst: Dst, count: Count) => emit_sar {
+        (Reg64, i8), (Reg32, i8), (Reg16, i8), (Reg8, i8), (Mem, i8),
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg8, Reg8), (Mem, Reg8),
+    }
+}
+
+instruction! {
+    /// Reverse the byte order of a 32 or 64-bit register.
+    Bswap::bswap(dst: Dst) => emit_bswap {
+        (Reg64), (Reg32),
+    }
+}
+
+instruction! {
+    /// Count the set bits of the source.
+    Popcnt::popcnt(dst: Dst, src: Src) => emit_popcnt {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
+
+instruction! {
+    /// Count the leading zero bits of the source. Requires LZCNT (ABM), without which the
+    /// encoding is `bsr`.
+    Lzcnt::lzcnt(dst: Dst, src: Src) => emit_lzcnt {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
+
+instruction! {
+    /// Count the trailing zero bits of the source. Requires BMI1, without which the
+    /// encoding is `bsf`.
+    Tzcnt::tzcnt(dst: Dst, src: Src) => emit_tzcnt {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
@@ -0,0 +1,43 @@
+use crate::arch::x86::{Mem, Operand, X86Asm, Xmm, Ymm};
+
+instruction! {
+    /// Move packed doubles between vector registers and unaligned memory.
+    Vmovupd::vmovupd(dst: Dst, src: Src) => emit_vmovupd {
+        (Xmm, Xmm), (Ymm, Ymm), (Xmm, Mem), (Ymm, Mem), (Mem, Xmm), (Mem, Ymm),
+    }
+}
+
+instruction! {
+    Vaddpd::vaddpd(dst: Dst, src1: Src1, src2: Src2) => emit_vaddpd {
+        (Xmm, Xmm, Xmm), (Xmm, Xmm, Mem), (Ymm, Ymm, Ymm), (Ymm, Ymm, Mem),
+    }
+}
+
+instruction! {
+    Vmulpd::vmulpd(dst: Dst, src1: Src1, src2: Src2) => emit_vmulpd {
+        (Xmm, Xmm, Xmm), (Xmm, Xmm, Mem), (Ymm, Ymm, Ymm), (Ymm, Ymm, Mem),
+    }
+}
+
+instruction! {
+    /// Fused multiply-add of packed doubles: `dst = src1 * src2 + dst`, with a single
+    /// rounding. Requires FMA support.
+    Vfmadd231pd::vfmadd231pd(dst: Dst, src1: Src1, src2: Src2) => emit_vfmadd231pd {
+        (Xmm, Xmm, Xmm), (Xmm, Xmm, Mem), (Ymm, Ymm, Ymm), (Ymm, Ymm, Mem),
+    }
+}
+
+instruction! {
+    /// Add packed 64-bit integers. The 256-bit form requires AVX2.
+    Vpaddq::vpaddq(dst: Dst, src1: Src1, src2: Src2) => emit_vpaddq {
+        (Xmm, Xmm, Xmm), (Xmm, Xmm, Mem), (Ymm, Ymm, Ymm), (Ymm, Ymm, Mem),
+    }
+}
+
+instruction! {
+    /// Broadcast a double to all four lanes of a YMM register. Broadcasting from an XMM
+    /// register requires AVX2.
+    Vbroadcastsd::vbroadcastsd(dst: Dst, src: Src) => emit_vbroadcastsd {
+        (Ymm, Xmm), (Ymm, Mem),
+    }
+}
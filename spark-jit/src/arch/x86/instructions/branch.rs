@@ -0,0 +1,25 @@
+use crate::arch::x86::{Cond, Mem, Operand, Reg64, X86Asm};
+use crate::label::Label;
+
+instruction! {
+    /// Call a label, or indirectly through a register or a qword in memory.
+    Call::call(target: Target) => emit_call {
+        (Reg64), (Label), (Mem),
+    }
+}
+
+instruction! {
+    /// Unconditional jump. Jumps to bound labels use the short rel8 form when possible,
+    /// jumps to unbound labels always use rel32 (see [`X86Asm::jmp_short`]). Indirect jumps
+    /// go through a register or a qword in memory.
+    Jmp::jmp(target: Target) => emit_jmp {
+        (Reg64), (Label), (Mem),
+    }
+}
+
+instruction! {
+    /// Conditional jump. Uses the same rel8/rel32 selection as [`X86Asm::jmp`].
+    Jcc::jcc(cond: Cond, target: Target) => emit_jcc {
+        (Label),
+    }
+}
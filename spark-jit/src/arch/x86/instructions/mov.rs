@@ -1,7 +1,80 @@
-trait Mov<Src, Dst> {}
+use crate::arch::x86::{Cond, Mem, Operand, Reg16, Reg32, Reg64, Reg8, X86Asm};
+use crate::label::Label;
 
-// impl Mov<Register, Register> for Mov {}
-// impl Mov<Register, Memory> for Mov {}
-// impl Mov<Register, Immediate> for Mov {}
-// impl Mov<Memory, Register> for Mov {}
-// impl Mov<Memory, Immediate> for Mov {}
+instruction! {
+    Mov::mov(dst: Dst, src: Src) => emit_mov {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+        (Reg64, i64), (Reg32, i32), (Reg16, i16), (Reg8, i8),
+        (Mem, i32), (Mem, i16), (Mem, i8),
+    }
+}
+
+instruction! {
+    /// Move a byte or a word into a larger register, zero-extended.
+    Movzx::movzx(dst: Dst, src: Src) => emit_movzx {
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg64, Reg16), (Reg32, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
+
+instruction! {
+    /// Move a byte or a word into a larger register, sign-extended.
+    Movsx::movsx(dst: Dst, src: Src) => emit_movsx {
+        (Reg64, Reg8), (Reg32, Reg8), (Reg16, Reg8), (Reg64, Reg16), (Reg32, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
+
+instruction! {
+    /// Move a dword into a 64-bit register, sign-extended.
+    Movsxd::movsxd(dst: Dst, src: Src) => emit_movsxd {
+        (Reg64, Reg32), (Reg64, Mem),
+    }
+}
+
+instruction! {
+    /// Exchange the operands. With a memory operand, the exchange is implicitly locked.
+    Xchg::xchg(dst: Dst, src: Src) => emit_xchg {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16), (Reg8, Reg8),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem), (Reg8, Mem),
+        (Mem, Reg64), (Mem, Reg32), (Mem, Reg16), (Mem, Reg8),
+    }
+}
+
+instruction! {
+    Lea::lea(dst: Dst, src: Src) => emit_lea {
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+        (Reg64, Label),
+    }
+}
+
+instruction! {
+    /// Push a qword. Immediates are sign-extended to 64 bits.
+    Push::push(src: Src) => emit_push {
+        (Reg64), (i32), (i8), (Mem),
+    }
+}
+
+instruction! {
+    Pop::pop(dst: Dst) => emit_pop {
+        (Reg64), (Mem),
+    }
+}
+
+instruction! {
+    /// Move the source into the destination register if the condition holds.
+    Cmovcc::cmovcc(cond: Cond, dst: Dst, src: Src) => emit_cmovcc {
+        (Reg64, Reg64), (Reg32, Reg32), (Reg16, Reg16),
+        (Reg64, Mem), (Reg32, Mem), (Reg16, Mem),
+    }
+}
+
+instruction! {
+    /// Set the byte register (or the byte in memory) to 1 if the condition holds,
+    /// and to 0 otherwise.
+    Setcc::setcc(cond: Cond, dst: Dst) => emit_setcc {
+        (Reg8), (Mem),
+    }
+}
@@ -0,0 +1,77 @@
+use crate::arch::x86::{Mem, Operand, Reg32, Reg64, X86Asm, Xmm};
+
+instruction! {
+    /// Move a scalar double between XMM registers and memory. Moves between registers
+    /// only replace the low 64 bits of the destination.
+    Movsd::movsd(dst: Dst, src: Src) => emit_movsd {
+        (Xmm, Xmm), (Xmm, Mem), (Mem, Xmm),
+    }
+}
+
+instruction! {
+    /// Move 64 bits between a general-purpose register (or memory) and an XMM register.
+    /// The upper bits of a destination XMM register are zeroed.
+    Movq::movq(dst: Dst, src: Src) => emit_movq {
+        (Xmm, Xmm), (Xmm, Reg64), (Xmm, Mem), (Reg64, Xmm), (Mem, Xmm),
+    }
+}
+
+instruction! {
+    Addsd::addsd(dst: Dst, src: Src) => emit_addsd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    Subsd::subsd(dst: Dst, src: Src) => emit_subsd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    Mulsd::mulsd(dst: Dst, src: Src) => emit_mulsd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    Divsd::divsd(dst: Dst, src: Src) => emit_divsd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    Sqrtsd::sqrtsd(dst: Dst, src: Src) => emit_sqrtsd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    /// Compare two scalar doubles and set ZF, PF and CF like an unsigned comparison.
+    /// An unordered result (a NaN operand) sets all three flags.
+    Ucomisd::ucomisd(lhs: Lhs, rhs: Rhs) => emit_ucomisd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    /// Bitwise xor of two packed doubles. The memory operand is 128 bits wide and must
+    /// be 16-byte aligned.
+    Xorpd::xorpd(dst: Dst, src: Src) => emit_xorpd {
+        (Xmm, Xmm), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    /// Convert a signed 32 or 64-bit integer to a scalar double.
+    Cvtsi2sd::cvtsi2sd(dst: Dst, src: Src) => emit_cvtsi2sd {
+        (Xmm, Reg64), (Xmm, Reg32), (Xmm, Mem),
+    }
+}
+
+instruction! {
+    /// Convert a scalar double to a signed 32 or 64-bit integer, truncating towards zero.
+    Cvttsd2si::cvttsd2si(dst: Dst, src: Src) => emit_cvttsd2si {
+        (Reg64, Xmm), (Reg32, Xmm), (Reg64, Mem), (Reg32, Mem),
+    }
+}
@@ -0,0 +1,450 @@
+//! The [`x86!`](crate::x86) macro.
+
+/// Emit a sequence of Intel-syntax instructions with an [`X86Asm`](crate::X86Asm).
+///
+/// The instructions are separated by `;` and expand to calls of the typed instruction
+/// methods, so unknown mnemonics, misspelled registers and invalid operand combinations
+/// are compile errors. The macro evaluates to `Result<(), EncodeError>` and stops at the
+/// first instruction that fails to encode.
+///
+/// ```
+/// use spark_jit::{x86, X86Asm};
+///
+/// let mut codegen = X86Asm::new();
+/// let done = codegen.new_label();
+/// let offset = 16;
+/// x86!(codegen;
+///     push r12;
+///     mov r14, rdi;
+///     add r14, 8;
+///     mov rax, qword ptr [r14 + rcx*8 - 8];
+///     cmp dword ptr [rsi + (offset)], -1;
+///     je done;
+///     pop r12
+/// )
+/// .unwrap();
+/// codegen.bind_label(done).unwrap();
+/// ```
+///
+/// Operands are registers, memory operands (`size ptr [base + index*scale + disp]`, where
+/// the size can be left out when the other operand implies it), integer literals and
+/// Rust expressions: identifiers such as labels or register constants, and any expression
+/// in parentheses. Immediates take the type the instruction expects, e.g. `i8` for shift
+/// counts; a suffix such as `0x1122334455667788i64` selects another form.
+///
+/// ```compile_fail
+/// use spark_jit::{x86, X86Asm};
+///
+/// let mut codegen = X86Asm::new();
+/// x86!(codegen; mov qword ptr [rdi], qword ptr [rsi]).unwrap();
+/// ```
+#[macro_export]
+macro_rules! x86 {
+    ($asm:ident; $($body:tt)*) => {
+        $crate::x86!(@stmt $asm [] $($body)*)
+    };
+
+    // Statements, with the instructions parsed so far as `X86Asm` calls.
+    (@stmt $asm:ident []) => {
+        Ok::<(), $crate::arch::EncodeError>(())
+    };
+    (@stmt $asm:ident [$($insn:expr;)*]) => {
+        'x86: {
+            $(
+                if let Err(err) = $insn {
+                    break 'x86 Err(err);
+                }
+            )*
+            Ok::<(), $crate::arch::EncodeError>(())
+        }
+    };
+    (@stmt $asm:ident [$($insn:tt)*] ; $($rest:tt)*) => {
+        $crate::x86!(@stmt $asm [$($insn)*] $($rest)*)
+    };
+    (@stmt $asm:ident [$($insn:tt)*] $mnemonic:ident $($rest:tt)*) => {
+        $crate::x86!(@operand $asm [$($insn)*] $mnemonic [] $($rest)*)
+    };
+
+    // The operands of an instruction, up to the end of the statement.
+    (@operand $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*]) => {
+        $crate::x86!(@stmt $asm [$($insn)* $crate::x86!(@insn $asm $mnemonic $($op)*);])
+    };
+    (@operand $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*] ; $($rest:tt)*) => {
+        $crate::x86!(@stmt $asm [$($insn)* $crate::x86!(@insn $asm $mnemonic $($op)*);] $($rest)*)
+    };
+    (@operand $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*]
+        $size:ident ptr [$($mem:tt)*] $($rest:tt)*) => {
+        $crate::x86!(@next $asm [$($insn)*] $mnemonic
+            [$($op)* $crate::x86!(@mem $size $($mem)*),] $($rest)*)
+    };
+    (@operand $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*]
+        [$($mem:tt)*] $($rest:tt)*) => {
+        $crate::x86!(@next $asm [$($insn)*] $mnemonic
+            [$($op)* $crate::x86!(@mem implied $($mem)*),] $($rest)*)
+    };
+    (@operand $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*]
+        - $imm:tt $($rest:tt)*) => {
+        $crate::x86!(@next $asm [$($insn)*] $mnemonic
+            [$($op)* -$crate::x86!(@reg $imm),] $($rest)*)
+    };
+    (@operand $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*]
+        $operand:tt $($rest:tt)*) => {
+        $crate::x86!(@next $asm [$($insn)*] $mnemonic
+            [$($op)* $crate::x86!(@reg $operand),] $($rest)*)
+    };
+
+    (@next $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*] , $($rest:tt)*) => {
+        $crate::x86!(@operand $asm [$($insn)*] $mnemonic [$($op)*] $($rest)*)
+    };
+    (@next $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*] ; $($rest:tt)*) => {
+        $crate::x86!(@stmt $asm [$($insn)* $crate::x86!(@insn $asm $mnemonic $($op)*);] $($rest)*)
+    };
+    (@next $asm:ident [$($insn:tt)*] $mnemonic:ident [$($op:tt)*]) => {
+        $crate::x86!(@stmt $asm [$($insn)* $crate::x86!(@insn $asm $mnemonic $($op)*);])
+    };
+
+    // Memory operands.
+    (@mem byte $($addr:tt)*) => { $crate::x86!(@addr $crate::arch::x86::Mem::byte, $($addr)*) };
+    (@mem word $($addr:tt)*) => { $crate::x86!(@addr $crate::arch::x86::Mem::word, $($addr)*) };
+    (@mem dword $($addr:tt)*) => { $crate::x86!(@addr $crate::arch::x86::Mem::dword, $($addr)*) };
+    (@mem qword $($addr:tt)*) => { $crate::x86!(@addr $crate::arch::x86::Mem::qword, $($addr)*) };
+    (@mem xmmword $($addr:tt)*) => { $crate::x86!(@mem implied $($addr)*) };
+    (@mem ymmword $($addr:tt)*) => { $crate::x86!(@mem implied $($addr)*) };
+    (@mem implied $($addr:tt)*) => {
+        $crate::x86!(@addr $crate::arch::x86::Mem::implied, $($addr)*)
+    };
+
+    (@addr $new:path, $base:tt + $index:ident * $scale:tt + $disp:tt) => {
+        $crate::x86!(@addr $new, $base + $disp).with_index(
+            $crate::x86!(@reg $index),
+            $crate::x86!(@scale $scale),
+        )
+    };
+    (@addr $new:path, $base:tt + $index:ident * $scale:tt - $disp:tt) => {
+        $crate::x86!(@addr $new, $base - $disp).with_index(
+            $crate::x86!(@reg $index),
+            $crate::x86!(@scale $scale),
+        )
+    };
+    (@addr $new:path, $base:tt + $index:ident * $scale:tt) => {
+        $crate::x86!(@addr $new, $base + $index * $scale + 0)
+    };
+    (@addr $new:path, $base:tt + $scale:tt * $index:ident $($disp:tt)*) => {
+        $crate::x86!(@addr $new, $base + $index * $scale $($disp)*)
+    };
+    (@addr $new:path, $base:tt + $disp:tt) => {
+        $new($crate::x86!(@reg $base), $crate::x86!(@reg $disp))
+    };
+    (@addr $new:path, $base:tt - $disp:tt) => {
+        $new($crate::x86!(@reg $base), -$crate::x86!(@reg $disp))
+    };
+    (@addr $new:path, $base:tt) => {
+        $new($crate::x86!(@reg $base), 0)
+    };
+
+    (@scale 1) => { $crate::arch::x86::Scale::X1 };
+    (@scale 2) => { $crate::arch::x86::Scale::X2 };
+    (@scale 4) => { $crate::arch::x86::Scale::X4 };
+    (@scale 8) => { $crate::arch::x86::Scale::X8 };
+
+    // Conditional instructions and aliases, then everything else by name.
+    (@insn $asm:ident jo $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::O, $($op),*) };
+    (@insn $asm:ident jno $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::No, $($op),*) };
+    (@insn $asm:ident jb $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::B, $($op),*) };
+    (@insn $asm:ident jc $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::C, $($op),*) };
+    (@insn $asm:ident jnae $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nae, $($op),*) };
+    (@insn $asm:ident jae $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Ae, $($op),*) };
+    (@insn $asm:ident jnb $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nb, $($op),*) };
+    (@insn $asm:ident jnc $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nc, $($op),*) };
+    (@insn $asm:ident je $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::E, $($op),*) };
+    (@insn $asm:ident jz $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Z, $($op),*) };
+    (@insn $asm:ident jne $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Ne, $($op),*) };
+    (@insn $asm:ident jnz $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nz, $($op),*) };
+    (@insn $asm:ident jbe $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Be, $($op),*) };
+    (@insn $asm:ident jna $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Na, $($op),*) };
+    (@insn $asm:ident ja $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::A, $($op),*) };
+    (@insn $asm:ident jnbe $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nbe, $($op),*) };
+    (@insn $asm:ident js $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::S, $($op),*) };
+    (@insn $asm:ident jns $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Ns, $($op),*) };
+    (@insn $asm:ident jp $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::P, $($op),*) };
+    (@insn $asm:ident jpe $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Pe, $($op),*) };
+    (@insn $asm:ident jnp $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Np, $($op),*) };
+    (@insn $asm:ident jpo $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Po, $($op),*) };
+    (@insn $asm:ident jl $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::L, $($op),*) };
+    (@insn $asm:ident jnge $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nge, $($op),*) };
+    (@insn $asm:ident jge $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Ge, $($op),*) };
+    (@insn $asm:ident jnl $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nl, $($op),*) };
+    (@insn $asm:ident jle $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Le, $($op),*) };
+    (@insn $asm:ident jng $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Ng, $($op),*) };
+    (@insn $asm:ident jg $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::G, $($op),*) };
+    (@insn $asm:ident jnle $($op:expr,)*) => { $asm.jcc($crate::arch::x86::Cond::Nle, $($op),*) };
+    (@insn $asm:ident seto $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::O, $($op),*) };
+    (@insn $asm:ident setno $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::No, $($op),*) };
+    (@insn $asm:ident setb $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::B, $($op),*) };
+    (@insn $asm:ident setc $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::C, $($op),*) };
+    (@insn $asm:ident setnae $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nae, $($op),*) };
+    (@insn $asm:ident setae $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Ae, $($op),*) };
+    (@insn $asm:ident setnb $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nb, $($op),*) };
+    (@insn $asm:ident setnc $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nc, $($op),*) };
+    (@insn $asm:ident sete $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::E, $($op),*) };
+    (@insn $asm:ident setz $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Z, $($op),*) };
+    (@insn $asm:ident setne $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Ne, $($op),*) };
+    (@insn $asm:ident setnz $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nz, $($op),*) };
+    (@insn $asm:ident setbe $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Be, $($op),*) };
+    (@insn $asm:ident setna $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Na, $($op),*) };
+    (@insn $asm:ident seta $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::A, $($op),*) };
+    (@insn $asm:ident setnbe $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nbe, $($op),*) };
+    (@insn $asm:ident sets $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::S, $($op),*) };
+    (@insn $asm:ident setns $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Ns, $($op),*) };
+    (@insn $asm:ident setp $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::P, $($op),*) };
+    (@insn $asm:ident setpe $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Pe, $($op),*) };
+    (@insn $asm:ident setnp $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Np, $($op),*) };
+    (@insn $asm:ident setpo $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Po, $($op),*) };
+    (@insn $asm:ident setl $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::L, $($op),*) };
+    (@insn $asm:ident setnge $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nge, $($op),*) };
+    (@insn $asm:ident setge $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Ge, $($op),*) };
+    (@insn $asm:ident setnl $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nl, $($op),*) };
+    (@insn $asm:ident setle $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Le, $($op),*) };
+    (@insn $asm:ident setng $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Ng, $($op),*) };
+    (@insn $asm:ident setg $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::G, $($op),*) };
+    (@insn $asm:ident setnle $($op:expr,)*) => { $asm.setcc($crate::arch::x86::Cond::Nle, $($op),*) };
+    (@insn $asm:ident cmovo $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::O, $($op),*) };
+    (@insn $asm:ident cmovno $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::No, $($op),*) };
+    (@insn $asm:ident cmovb $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::B, $($op),*) };
+    (@insn $asm:ident cmovc $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::C, $($op),*) };
+    (@insn $asm:ident cmovnae $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nae, $($op),*) };
+    (@insn $asm:ident cmovae $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Ae, $($op),*) };
+    (@insn $asm:ident cmovnb $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nb, $($op),*) };
+    (@insn $asm:ident cmovnc $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nc, $($op),*) };
+    (@insn $asm:ident cmove $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::E, $($op),*) };
+    (@insn $asm:ident cmovz $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Z, $($op),*) };
+    (@insn $asm:ident cmovne $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Ne, $($op),*) };
+    (@insn $asm:ident cmovnz $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nz, $($op),*) };
+    (@insn $asm:ident cmovbe $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Be, $($op),*) };
+    (@insn $asm:ident cmovna $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Na, $($op),*) };
+    (@insn $asm:ident cmova $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::A, $($op),*) };
+    (@insn $asm:ident cmovnbe $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nbe, $($op),*) };
+    (@insn $asm:ident cmovs $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::S, $($op),*) };
+    (@insn $asm:ident cmovns $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Ns, $($op),*) };
+    (@insn $asm:ident cmovp $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::P, $($op),*) };
+    (@insn $asm:ident cmovpe $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Pe, $($op),*) };
+    (@insn $asm:ident cmovnp $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Np, $($op),*) };
+    (@insn $asm:ident cmovpo $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Po, $($op),*) };
+    (@insn $asm:ident cmovl $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::L, $($op),*) };
+    (@insn $asm:ident cmovnge $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nge, $($op),*) };
+    (@insn $asm:ident cmovge $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Ge, $($op),*) };
+    (@insn $asm:ident cmovnl $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nl, $($op),*) };
+    (@insn $asm:ident cmovle $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Le, $($op),*) };
+    (@insn $asm:ident cmovng $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Ng, $($op),*) };
+    (@insn $asm:ident cmovg $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::G, $($op),*) };
+    (@insn $asm:ident cmovnle $($op:expr,)*) => { $asm.cmovcc($crate::arch::x86::Cond::Nle, $($op),*) };
+    (@insn $asm:ident sal $($op:expr,)*) => { $asm.shl($($op),*) };
+    (@insn $asm:ident imul $dst:expr, $src:expr,) => { $asm.imul_2($dst, $src) };
+    (@insn $asm:ident imul $dst:expr, $src:expr, $imm:expr,) => {
+        $asm.imul_3($dst, $src, $imm)
+    };
+    (@insn $asm:ident $mnemonic:ident $($op:expr,)*) => { $asm.$mnemonic($($op),*) };
+
+    // Registers by name. Anything else is a Rust expression: an identifier, a literal
+    // or an expression in parentheses.
+    (@reg rax) => { $crate::arch::x86::Reg64::Rax };
+    (@reg rcx) => { $crate::arch::x86::Reg64::Rcx };
+    (@reg rdx) => { $crate::arch::x86::Reg64::Rdx };
+    (@reg rbx) => { $crate::arch::x86::Reg64::Rbx };
+    (@reg rsp) => { $crate::arch::x86::Reg64::Rsp };
+    (@reg rbp) => { $crate::arch::x86::Reg64::Rbp };
+    (@reg rsi) => { $crate::arch::x86::Reg64::Rsi };
+    (@reg rdi) => { $crate::arch::x86::Reg64::Rdi };
+    (@reg r8) => { $crate::arch::x86::Reg64::R8 };
+    (@reg r9) => { $crate::arch::x86::Reg64::R9 };
+    (@reg r10) => { $crate::arch::x86::Reg64::R10 };
+    (@reg r11) => { $crate::arch::x86::Reg64::R11 };
+    (@reg r12) => { $crate::arch::x86::Reg64::R12 };
+    (@reg r13) => { $crate::arch::x86::Reg64::R13 };
+    (@reg r14) => { $crate::arch::x86::Reg64::R14 };
+    (@reg r15) => { $crate::arch::x86::Reg64::R15 };
+    (@reg eax) => { $crate::arch::x86::Reg32::Eax };
+    (@reg ecx) => { $crate::arch::x86::Reg32::Ecx };
+    (@reg edx) => { $crate::arch::x86::Reg32::Edx };
+    (@reg ebx) => { $crate::arch::x86::Reg32::Ebx };
+    (@reg esp) => { $crate::arch::x86::Reg32::Esp };
+    (@reg ebp) => { $crate::arch::x86::Reg32::Ebp };
+    (@reg esi) => { $crate::arch::x86::Reg32::Esi };
+    (@reg edi) => { $crate::arch::x86::Reg32::Edi };
+    (@reg r8d) => { $crate::arch::x86::Reg32::R8d };
+    (@reg r9d) => { $crate::arch::x86::Reg32::R9d };
+    (@reg r10d) => { $crate::arch::x86::Reg32::R10d };
+    (@reg r11d) => { $crate::arch::x86::Reg32::R11d };
+    (@reg r12d) => { $crate::arch::x86::Reg32::R12d };
+    (@reg r13d) => { $crate::arch::x86::Reg32::R13d };
+    (@reg r14d) => { $crate::arch::x86::Reg32::R14d };
+    (@reg r15d) => { $crate::arch::x86::Reg32::R15d };
+    (@reg ax) => { $crate::arch::x86::Reg16::Ax };
+    (@reg cx) => { $crate::arch::x86::Reg16::Cx };
+    (@reg dx) => { $crate::arch::x86::Reg16::Dx };
+    (@reg bx) => { $crate::arch::x86::Reg16::Bx };
+    (@reg sp) => { $crate::arch::x86::Reg16::Sp };
+    (@reg bp) => { $crate::arch::x86::Reg16::Bp };
+    (@reg si) => { $crate::arch::x86::Reg16::Si };
+    (@reg di) => { $crate::arch::x86::Reg16::Di };
+    (@reg r8w) => { $crate::arch::x86::Reg16::R8w };
+    (@reg r9w) => { $crate::arch::x86::Reg16::R9w };
+    (@reg r10w) => { $crate::arch::x86::Reg16::R10w };
+    (@reg r11w) => { $crate::arch::x86::Reg16::R11w };
+    (@reg r12w) => { $crate::arch::x86::Reg16::R12w };
+    (@reg r13w) => { $crate::arch::x86::Reg16::R13w };
+    (@reg r14w) => { $crate::arch::x86::Reg16::R14w };
+    (@reg r15w) => { $crate::arch::x86::Reg16::R15w };
+    (@reg al) => { $crate::arch::x86::Reg8::Al };
+    (@reg cl) => { $crate::arch::x86::Reg8::Cl };
+    (@reg dl) => { $crate::arch::x86::Reg8::Dl };
+    (@reg bl) => { $crate::arch::x86::Reg8::Bl };
+    (@reg spl) => { $crate::arch::x86::Reg8::Spl };
+    (@reg bpl) => { $crate::arch::x86::Reg8::Bpl };
+    (@reg sil) => { $crate::arch::x86::Reg8::Sil };
+    (@reg dil) => { $crate::arch::x86::Reg8::Dil };
+    (@reg r8b) => { $crate::arch::x86::Reg8::R8b };
+    (@reg r9b) => { $crate::arch::x86::Reg8::R9b };
+    (@reg r10b) => { $crate::arch::x86::Reg8::R10b };
+    (@reg r11b) => { $crate::arch::x86::Reg8::R11b };
+    (@reg r12b) => { $crate::arch::x86::Reg8::R12b };
+    (@reg r13b) => { $crate::arch::x86::Reg8::R13b };
+    (@reg r14b) => { $crate::arch::x86::Reg8::R14b };
+    (@reg r15b) => { $crate::arch::x86::Reg8::R15b };
+    (@reg ah) => { $crate::arch::x86::Reg8::Ah };
+    (@reg ch) => { $crate::arch::x86::Reg8::Ch };
+    (@reg dh) => { $crate::arch::x86::Reg8::Dh };
+    (@reg bh) => { $crate::arch::x86::Reg8::Bh };
+    (@reg xmm0) => { $crate::arch::x86::Xmm::Xmm0 };
+    (@reg xmm1) => { $crate::arch::x86::Xmm::Xmm1 };
+    (@reg xmm2) => { $crate::arch::x86::Xmm::Xmm2 };
+    (@reg xmm3) => { $crate::arch::x86::Xmm::Xmm3 };
+    (@reg xmm4) => { $crate::arch::x86::Xmm::Xmm4 };
+    (@reg xmm5) => { $crate::arch::x86::Xmm::Xmm5 };
+    (@reg xmm6) => { $crate::arch::x86::Xmm::Xmm6 };
+    (@reg xmm7) => { $crate::arch::x86::Xmm::Xmm7 };
+    (@reg xmm8) => { $crate::arch::x86::Xmm::Xmm8 };
+    (@reg xmm9) => { $crate::arch::x86::Xmm::Xmm9 };
+    (@reg xmm10) => { $crate::arch::x86::Xmm::Xmm10 };
+    (@reg xmm11) => { $crate::arch::x86::Xmm::Xmm11 };
+    (@reg xmm12) => { $crate::arch::x86::Xmm::Xmm12 };
+    (@reg xmm13) => { $crate::arch::x86::Xmm::Xmm13 };
+    (@reg xmm14) => { $crate::arch::x86::Xmm::Xmm14 };
+    (@reg xmm15) => { $crate::arch::x86::Xmm::Xmm15 };
+    (@reg ymm0) => { $crate::arch::x86::Ymm::Ymm0 };
+    (@reg ymm1) => { $crate::arch::x86::Ymm::Ymm1 };
+    (@reg ymm2) => { $crate::arch::x86::Ymm::Ymm2 };
+    (@reg ymm3) => { $crate::arch::x86::Ymm::Ymm3 };
+    (@reg ymm4) => { $crate::arch::x86::Ymm::Ymm4 };
+    (@reg ymm5) => { $crate::arch::x86::Ymm::Ymm5 };
+    (@reg ymm6) => { $crate::arch::x86::Ymm::Ymm6 };
+    (@reg ymm7) => { $crate::arch::x86::Ymm::Ymm7 };
+    (@reg ymm8) => { $crate::arch::x86::Ymm::Ymm8 };
+    (@reg ymm9) => { $crate::arch::x86::Ymm::Ymm9 };
+    (@reg ymm10) => { $crate::arch::x86::Ymm::Ymm10 };
+    (@reg ymm11) => { $crate::arch::x86::Ymm::Ymm11 };
+    (@reg ymm12) => { $crate::arch::x86::Ymm::Ymm12 };
+    (@reg ymm13) => { $crate::arch::x86::Ymm::Ymm13 };
+    (@reg ymm14) => { $crate::arch::x86::Ymm::Ymm14 };
+    (@reg ymm15) => { $crate::arch::x86::Ymm::Ymm15 };
+    (@reg ($($expr:tt)*)) => { $($expr)* };
+    (@reg $expr:tt) => { $expr };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::x86::Reg64::*;
+    use crate::arch::x86::{Cond, Mem, Reg64, Scale, X86Asm};
+
+    #[test]
+    fn test_x86_64_macro() {
+        let mut codegen = X86Asm::new();
+        x86!(codegen;
+            mov r14, rdi;
+            add r14, 8;
+            mov rax, qword ptr [r14 + 8*rcx - 8];
+            cmp dword ptr [rsi + 16], -1;
+            mov qword ptr [rsp], 1;
+            shl rax, 3;
+            lea rdx, [rdi + rsi*4 + 0x20];
+            cmovl rax, rdx;
+            sete al;
+            vaddpd ymm0, ymm1, ymmword ptr [rdi + 32];
+            imul rdx, rcx, 100;
+            imul rax, rdx;
+            movzx ecx, byte ptr [rdi];
+            ret
+        )
+        .unwrap();
+
+        assert_eq!(
+            codegen.code(),
+            &[
+                0x49, 0x89, 0xfe, // mov r14, rdi
+                0x49, 0x83, 0xc6, 0x08, // add r14, 8
+                0x49, 0x8b, 0x44, 0xce, 0xf8, // mov rax, qword ptr [r14 + 8*rcx - 8]
+                0x83, 0x7e, 0x10, 0xff, // cmp dword ptr [rsi + 16], -1
+                0x48, 0xc7, 0x04, 0x24, 0x01, 0x00, 0x00, 0x00, // mov qword ptr [rsp], 1
+                0x48, 0xc1, 0xe0, 0x03, // shl rax, 3
+                0x48, 0x8d, 0x54, 0xb7, 0x20, // lea rdx, [rdi + 4*rsi + 32]
+                0x48, 0x0f, 0x4c, 0xc2, // cmovl rax, rdx
+                0x0f, 0x94, 0xc0, // sete al
+                0xc5, 0xf5, 0x58, 0x47, 0x20, // vaddpd ymm0, ymm1, ymmword ptr [rdi + 32]
+                0x48, 0x6b, 0xd1, 0x64, // imul rdx, rcx, 100
+                0x48, 0x0f, 0xaf, 0xc2, // imul rax, rdx
+                0x0f, 0xb6, 0x0f, // movzx ecx, byte ptr [rdi]
+                0xc3, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_macro_expressions() {
+        const STACK: Reg64 = R14;
+        let slot = |i: i32| i * 8;
+
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        x86!(codegen;
+            push r12;
+            mov STACK, (Rdi);
+            mov rax, 0x1122334455667788i64;
+            mov qword ptr [STACK - (slot(1))], rax;
+            and byte ptr [rsp], -(0x10 + 2);;
+            jnz start;
+            pop r12;
+        )
+        .unwrap();
+
+        let mut expected = X86Asm::new();
+        let start = expected.new_label();
+        expected.bind_label(start).unwrap();
+        expected.push(R12).unwrap();
+        expected.mov(R14, Rdi).unwrap();
+        expected.mov(Rax, 0x1122334455667788i64).unwrap();
+        expected.mov(Mem::qword(R14, -8), Rax).unwrap();
+        expected.and(Mem::byte(Rsp, 0), -18i8).unwrap();
+        expected.jcc(Cond::Ne, start).unwrap();
+        expected.pop(R12).unwrap();
+        assert_eq!(codegen.code(), expected.code());
+    }
+
+    #[test]
+    fn test_x86_64_macro_errors() {
+        let mut codegen = X86Asm::new();
+        assert_eq!(x86!(codegen;), Ok(()));
+
+        // The sequence stops at the first instruction that fails to encode.
+        let result = x86!(codegen;
+            ret;
+            mov ah, byte ptr [r8];
+            ret
+        );
+        assert!(result.is_err());
+        assert_eq!(codegen.code(), &[0xc3]);
+
+        let mem = Mem::implied(Rdi, 0).with_index(Rsp, Scale::X1);
+        assert!(x86!(codegen; vmovupd ymm2, (mem)).is_err());
+    }
+}
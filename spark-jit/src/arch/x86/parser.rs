@@ -0,0 +1,825 @@
+//! Intel-syntax assembly text front-end for [`X86Asm`].
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::iter::Peekable;
+
+use super::{Base, Cond, Mem, Operand, Reg16, Reg32, Reg64, Reg8, Scale, Size, X86Asm, Xmm, Ymm};
+use crate::arch::EncodeError;
+use crate::label::Label;
+
+/// Error returned by the text assembler, with the line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number.
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The statement or one of its operands is malformed.
+    Syntax(String),
+    /// The assembler doesn't know the mnemonic or directive.
+    UnknownMnemonic(String),
+    /// The label is defined more than once.
+    DuplicateLabel(String),
+    /// The label is referenced but never defined.
+    UndefinedLabel(String),
+    /// A branch targets an address that isn't the start of a statement.
+    InvalidTarget(u64),
+    /// The instruction can't be encoded.
+    Encode(EncodeError),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Line {}: ", self.line)?;
+        match &self.kind {
+            ParseErrorKind::Syntax(msg) => write!(f, "{}", msg),
+            ParseErrorKind::UnknownMnemonic(mnemonic) => {
+                write!(f, "Unknown mnemonic: {}", mnemonic)
+            }
+            ParseErrorKind::DuplicateLabel(name) => write!(f, "Label {} is already defined", name),
+            ParseErrorKind::UndefinedLabel(name) => write!(f, "Label {} is not defined", name),
+            ParseErrorKind::InvalidTarget(target) => {
+                write!(
+                    f,
+                    "Branch target {:#x} is not the start of an instruction",
+                    target
+                )
+            }
+            ParseErrorKind::Encode(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Assemble Intel-syntax text into machine code, see [`X86Asm::assemble`].
+pub fn assemble(source: &str) -> Result<Vec<u8>, ParseError> {
+    let mut codegen = X86Asm::new();
+    codegen.assemble(source)?;
+    Ok(codegen.code().to_vec())
+}
+
+impl X86Asm {
+    /// Assemble Intel-syntax text and append it to the code.
+    ///
+    /// Statements are separated by newlines or `;`, and `#` or `//` start a comment. A
+    /// statement is a label definition (`loop:`), an instruction (`mov rax, qword ptr [rbx + 8]`),
+    /// a data directive (`.byte`, `.word`, `.long` or `.quad`, followed by numbers) or an
+    /// alignment directive (`.align 16`, padded with NOPs).
+    ///
+    /// Branches and `lea reg, [rip + label]` reference labels by name. Branches to numbers,
+    /// as printed by [`X86Asm::dump_generated_code`] with a base address of 0, target that
+    /// offset in the code. Labels are local to one call, and must all be defined by its end.
+    ///
+    /// The whole text is parsed before any code is emitted, so a syntax error leaves the
+    /// code unchanged. Other errors, e.g. an operand combination that can't be encoded, a
+    /// label defined twice or an undefined label, are only found while emitting: the code
+    /// is then left partly assembled, with the statements before the failing one, and
+    /// shouldn't be used.
+    pub fn assemble(&mut self, source: &str) -> Result<(), ParseError> {
+        let mut parser = Parser {
+            asm: self,
+            line: 0,
+            labels: HashMap::new(),
+            targets: Vec::new(),
+        };
+
+        // Parse everything first, to know the numeric branch targets before emitting code.
+        let mut stmts = Vec::new();
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            parser.line = line;
+            let text = text.split("//").next().unwrap().split('#').next().unwrap();
+            for stmt in text.split(';') {
+                let stmt = parser
+                    .parse_stmt(stmt)
+                    .map_err(|kind| ParseError { line, kind })?;
+                stmts.extend(stmt.map(|stmt| (line, stmt)));
+            }
+        }
+
+        let mut targets = std::mem::take(&mut parser.targets);
+        targets.sort_by_key(|(target, _)| *target);
+        let mut targets = targets.into_iter().peekable();
+        for (line, stmt) in stmts {
+            parser
+                .bind_targets(&mut targets)
+                .and_then(|_| parser.emit_stmt(stmt))
+                .map_err(|kind| ParseError { line, kind })?;
+        }
+
+        let last_line = source.lines().count().max(1);
+        parser
+            .bind_targets(&mut targets)
+            .map_err(|kind| ParseError {
+                line: last_line,
+                kind,
+            })?;
+        if let Some((target, _)) = targets.next() {
+            return Err(ParseError {
+                line: last_line,
+                kind: ParseErrorKind::InvalidTarget(target),
+            });
+        }
+
+        let mut undefined: Vec<_> = parser
+            .labels
+            .iter()
+            .filter(|(_, (label, _))| parser.asm.label_offset(*label).is_none())
+            .map(|(name, (_, line))| (*line, name.clone()))
+            .collect();
+        undefined.sort();
+        match undefined.into_iter().next() {
+            Some((line, name)) => Err(ParseError {
+                line,
+                kind: ParseErrorKind::UndefinedLabel(name),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A parsed statement.
+enum Stmt {
+    Label(String),
+    Insn {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Data {
+        size: Size,
+        values: Vec<i64>,
+    },
+    Align(usize),
+}
+
+struct Parser<'a> {
+    asm: &'a mut X86Asm,
+    /// Line being parsed.
+    line: usize,
+    /// Named labels and the line they were first seen on.
+    labels: HashMap<String, (Label, usize)>,
+    /// Labels for branches to numeric offsets, bound when the code reaches them.
+    targets: Vec<(u64, Label)>,
+}
+
+type ParseResult<T> = Result<T, ParseErrorKind>;
+
+fn syntax<T>(msg: String) -> ParseResult<T> {
+    Err(ParseErrorKind::Syntax(msg))
+}
+
+impl Parser<'_> {
+    fn parse_stmt(&mut self, text: &str) -> ParseResult<Option<Stmt>> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(name) = text.strip_suffix(':') {
+            if !is_ident(name) {
+                return syntax(format!("Invalid label name: {}", name));
+            }
+            return Ok(Some(Stmt::Label(name.to_string())));
+        }
+
+        let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+            None => (text, ""),
+        };
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let operands: Vec<&str> = match operands {
+            "" => Vec::new(),
+            _ => split_operands(operands)?,
+        };
+
+        if mnemonic == ".align" {
+            let align = match operands[..] {
+                [align] => number(&align.to_ascii_lowercase())
+                    .and_then(|align| usize::try_from(align).ok()),
+                _ => None,
+            };
+            return match align {
+                Some(align) => Ok(Some(Stmt::Align(align))),
+                None => syntax(format!("Invalid alignment: {}", operands.join(", "))),
+            };
+        }
+
+        if let Some(directive) = mnemonic.strip_prefix('.') {
+            let size = match directive {
+                "byte" => Size::Byte,
+                "word" => Size::Word,
+                "long" => Size::Dword,
+                "quad" => Size::Qword,
+                _ => return Err(ParseErrorKind::UnknownMnemonic(mnemonic)),
+            };
+            let values = operands
+                .iter()
+                .map(|value| data_value(size, value))
+                .collect::<ParseResult<_>>()?;
+            return Ok(Some(Stmt::Data { size, values }));
+        }
+
+        let is_branch = mnemonic == "call" || mnemonic.starts_with('j');
+        let operands = operands
+            .iter()
+            .map(|operand| self.parse_operand(operand, is_branch))
+            .collect::<ParseResult<_>>()?;
+        Ok(Some(Stmt::Insn { mnemonic, operands }))
+    }
+
+    fn parse_operand(&mut self, text: &str, is_branch: bool) -> ParseResult<Operand> {
+        let lower = text.to_ascii_lowercase();
+
+        if let Some(reg) = register(&lower) {
+            return Ok(reg);
+        }
+        if lower.ends_with(']') {
+            return self.parse_mem(text).map(Operand::Mem);
+        }
+        if let Some(value) = number(&lower) {
+            if is_branch {
+                let Ok(target) = u64::try_from(value) else {
+                    return syntax(format!("Invalid branch target: {}", text));
+                };
+                return Ok(Operand::Label(self.target(target)));
+            }
+            return match imm(value) {
+                Some(imm) => Ok(Operand::Imm64(imm)),
+                None => syntax(format!("Immediate out of range: {}", text)),
+            };
+        }
+        if is_branch && is_ident(text) {
+            return Ok(Operand::Label(self.label(text)));
+        }
+
+        syntax(format!("Invalid operand: {}", text))
+    }
+
+    /// Parse `[size ptr] [base + index * scale + disp]`, where the base can also be `rip`,
+    /// optionally plus a label.
+    fn parse_mem(&mut self, text: &str) -> ParseResult<Mem> {
+        let invalid = || syntax(format!("Invalid memory operand: {}", text));
+
+        let Some((prefix, expr)) = text.strip_suffix(']').and_then(|text| text.split_once('['))
+        else {
+            return invalid();
+        };
+        let size = match prefix
+            .to_ascii_lowercase()
+            .split_whitespace()
+            .collect::<Vec<_>>()[..]
+        {
+            [] => None,
+            ["byte", "ptr"] => Some(Size::Byte),
+            ["word", "ptr"] => Some(Size::Word),
+            ["dword", "ptr"] => Some(Size::Dword),
+            ["qword", "ptr"] => Some(Size::Qword),
+            // The size of vector operands is implied by the register operands.
+            ["xmmword" | "ymmword", "ptr"] => None,
+            _ => return invalid(),
+        };
+
+        let mut base = Base::None;
+        let mut index = None;
+        let mut disp: i128 = 0;
+        let mut label = None;
+
+        // Split the expression into signed terms.
+        let mut terms = Vec::new();
+        let mut negative = false;
+        let mut start = 0;
+        for (i, c) in expr.char_indices() {
+            if c == '+' || c == '-' {
+                terms.push((negative, &expr[start..i]));
+                negative = c == '-';
+                start = i + 1;
+            }
+        }
+        terms.push((negative, &expr[start..]));
+
+        for (i, (negative, term)) in terms.into_iter().enumerate() {
+            let term = term.trim();
+            let lower = term.to_ascii_lowercase();
+            if term.is_empty() && i == 0 {
+                // A leading sign, e.g. `[-8]`.
+                continue;
+            }
+
+            if let Some(value) = number(&lower) {
+                disp += if negative { -value } else { value };
+                continue;
+            }
+            if negative {
+                return invalid();
+            }
+
+            if let Some((lhs, rhs)) = lower.split_once('*') {
+                let (lhs, rhs) = (lhs.trim(), rhs.trim());
+                let (reg, scale) = match (reg64(lhs), reg64(rhs)) {
+                    (Some(reg), None) => (reg, rhs),
+                    (None, Some(reg)) => (reg, lhs),
+                    _ => return invalid(),
+                };
+                let scale = match scale {
+                    "1" => Scale::X1,
+                    "2" => Scale::X2,
+                    "4" => Scale::X4,
+                    "8" => Scale::X8,
+                    _ => return invalid(),
+                };
+                if index.replace((reg, scale)).is_some() {
+                    return invalid();
+                }
+            } else if let Some(reg) = reg64(&lower) {
+                match (base, index) {
+                    (Base::None, _) => base = Base::Reg(reg),
+                    (_, None) => index = Some((reg, Scale::X1)),
+                    _ => return invalid(),
+                }
+            } else if lower == "rip" && matches!(base, Base::None) {
+                base = Base::Rip;
+            } else if is_ident(term) && label.is_none() {
+                label = Some(self.label(term));
+            } else {
+                return invalid();
+            }
+        }
+
+        let disp = i32::try_from(disp)
+            .or_else(|_| syntax(format!("Displacement out of range: {}", text)))?;
+        let base = match (base, label) {
+            (base, None) => base,
+            (Base::Rip, Some(label)) if index.is_none() && disp == 0 => Base::Label(label),
+            _ => return invalid(),
+        };
+
+        Ok(Mem {
+            size,
+            base,
+            index,
+            disp,
+        })
+    }
+
+    /// Get the label with the given name, creating it on first use.
+    fn label(&mut self, name: &str) -> Label {
+        if let Some((label, _)) = self.labels.get(name) {
+            return *label;
+        }
+
+        let label = self.asm.new_label();
+        self.labels.insert(name.to_string(), (label, self.line));
+        label
+    }
+
+    /// Get the label of a numeric branch target.
+    fn target(&mut self, target: u64) -> Label {
+        if let Some((_, label)) = self.targets.iter().find(|(other, _)| *other == target) {
+            return *label;
+        }
+
+        let label = self.asm.new_label();
+        self.targets.push((target, label));
+        label
+    }
+
+    /// Bind the labels of the numeric targets the code has reached. Targets that were
+    /// skipped over are in the middle of a statement.
+    fn bind_targets(
+        &mut self,
+        targets: &mut Peekable<impl Iterator<Item = (u64, Label)>>,
+    ) -> ParseResult<()> {
+        let offset = self.asm.offset() as u64;
+        while let Some((target, label)) = targets.next_if(|(target, _)| *target <= offset) {
+            if target < offset {
+                return Err(ParseErrorKind::InvalidTarget(target));
+            }
+            self.asm.bind_label(label).map_err(ParseErrorKind::Encode)?;
+        }
+        Ok(())
+    }
+
+    fn emit_stmt(&mut self, stmt: Stmt) -> ParseResult<()> {
+        match stmt {
+            Stmt::Label(name) => {
+                let label = self.label(&name);
+                if self.asm.label_offset(label).is_some() {
+                    return Err(ParseErrorKind::DuplicateLabel(name));
+                }
+                self.asm.bind_label(label).map_err(ParseErrorKind::Encode)
+            }
+            Stmt::Data { size, values } => {
+                for value in values {
+                    match size {
+                        Size::Byte => self.asm.writer.emit8(value as u8),
+                        Size::Word => self.asm.writer.emit16(value as u16),
+                        Size::Dword => self.asm.writer.emit32(value as u32),
+                        Size::Qword => self.asm.writer.emit64(value as u64),
+                    }
+                }
+                Ok(())
+            }
+            Stmt::Align(align) => self.asm.align(align).map_err(ParseErrorKind::Encode),
+            Stmt::Insn { mnemonic, operands } => {
+                emit_insn(self.asm, &mnemonic, &operands).map_err(|err| match err {
+                    Some(err) => ParseErrorKind::Encode(err),
+                    None => ParseErrorKind::UnknownMnemonic(mnemonic),
+                })
+            }
+        }
+    }
+}
+
+/// Emit an instruction given its mnemonic. Returns `Err(None)` for unknown mnemonics and
+/// wrong operand counts.
+fn emit_insn(asm: &mut X86Asm, mnemonic: &str, ops: &[Operand]) -> Result<(), Option<EncodeError>> {
+    let conditional =
+        [("j", 1), ("set", 1), ("cmov", 2)]
+            .into_iter()
+            .find_map(|(prefix, count)| {
+                let cond = cond(mnemonic.strip_prefix(prefix)?)?;
+                Some((prefix, cond, count))
+            });
+    if let Some((prefix, cond, count)) = conditional {
+        if ops.len() != count {
+            return Err(None);
+        }
+        return match prefix {
+            "j" => asm.jcc(cond, ops[0]),
+            "set" => asm.setcc(cond, ops[0]),
+            _ => asm.cmovcc(cond, ops[0], ops[1]),
+        }
+        .map_err(Some);
+    }
+
+    let result = match (mnemonic, ops) {
+        ("ret", []) => asm.ret(),
+        ("cqo", []) => asm.cqo(),
+        ("nop", []) => asm.nop(1),
+        ("neg", [dst]) => asm.neg(*dst),
+        ("not", [dst]) => asm.not(*dst),
+        ("inc", [dst]) => asm.inc(*dst),
+        ("dec", [dst]) => asm.dec(*dst),
+        ("bswap", [dst]) => asm.bswap(*dst),
+        ("mul", [src]) => asm.mul(*src),
+        ("imul", [src]) => asm.imul(*src),
+        ("imul", [dst, src]) => asm.imul_2(*dst, *src),
+        ("imul", [dst, src, imm]) => asm.imul_3(*dst, *src, *imm),
+        ("div", [src]) => asm.div(*src),
+        ("idiv", [src]) => asm.idiv(*src),
+        ("push", [src]) => asm.push(*src),
+        ("pop", [dst]) => asm.pop(*dst),
+        ("call", [target]) => asm.call(*target),
+        ("jmp", [target]) => asm.jmp(*target),
+        ("mov" | "movabs", [dst, src]) => asm.mov(*dst, *src),
+        ("movzx", [dst, src]) => asm.movzx(*dst, *src),
+        ("movsx", [dst, src]) => asm.movsx(*dst, *src),
+        ("movsxd", [dst, src]) => asm.movsxd(*dst, *src),
+        ("xchg", [dst, src]) => asm.xchg(*dst, *src),
+        ("add", [dst, src]) => asm.add(*dst, *src),
+        ("adc", [dst, src]) => asm.adc(*dst, *src),
+        ("sub", [dst, src]) => asm.sub(*dst, *src),
+        ("sbb", [dst, src]) => asm.sbb(*dst, *src),
+        ("cmp", [dst, src]) => asm.cmp(*dst, *src),
+        ("and", [dst, src]) => asm.and(*dst, *src),
+        ("or", [dst, src]) => asm.or(*dst, *src),
+        ("xor", [dst, src]) => asm.xor(*dst, *src),
+        ("test", [dst, src]) => asm.test(*dst, *src),
+        ("rol", [dst, count]) => asm.rol(*dst, *count),
+        ("ror", [dst, count]) => asm.ror(*dst, *count),
+        ("shl" | "sal", [dst, count]) => asm.shl(*dst, *count),
+        ("shr", [dst, count]) => asm.shr(*dst, *count),
+        ("sar", [dst, count]) => asm.sar(*dst, *count),
+        ("lea", [dst, src]) => asm.lea(*dst, *src),
+        ("popcnt", [dst, src]) => asm.popcnt(*dst, *src),
+        ("lzcnt", [dst, src]) => asm.lzcnt(*dst, *src),
+        ("tzcnt", [dst, src]) => asm.tzcnt(*dst, *src),
+        ("movsd", [dst, src]) => asm.movsd(*dst, *src),
+        ("movq", [dst, src]) => asm.movq(*dst, *src),
+        ("addsd", [dst, src]) => asm.addsd(*dst, *src),
+        ("subsd", [dst, src]) => asm.subsd(*dst, *src),
+        ("mulsd", [dst, src]) => asm.mulsd(*dst, *src),
+        ("divsd", [dst, src]) => asm.divsd(*dst, *src),
+        ("sqrtsd", [dst, src]) => asm.sqrtsd(*dst, *src),
+        ("ucomisd", [lhs, rhs]) => asm.ucomisd(*lhs, *rhs),
+        ("xorpd", [dst, src]) => asm.xorpd(*dst, *src),
+        ("cvtsi2sd", [dst, src]) => asm.cvtsi2sd(*dst, *src),
+        ("cvttsd2si", [dst, src]) => asm.cvttsd2si(*dst, *src),
+        ("vmovupd", [dst, src]) => asm.vmovupd(*dst, *src),
+        ("vbroadcastsd", [dst, src]) => asm.vbroadcastsd(*dst, *src),
+        ("vaddpd", [dst, src1, src2]) => asm.vaddpd(*dst, *src1, *src2),
+        ("vmulpd", [dst, src1, src2]) => asm.vmulpd(*dst, *src1, *src2),
+        ("vfmadd231pd", [dst, src1, src2]) => asm.vfmadd231pd(*dst, *src1, *src2),
+        ("vpaddq", [dst, src1, src2]) => asm.vpaddq(*dst, *src1, *src2),
+        _ => return Err(None),
+    };
+    result.map_err(Some)
+}
+
+/// Split the operands of an instruction on the commas outside of memory operands.
+fn split_operands(text: &str) -> ParseResult<Vec<&str>> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(text[start..].trim());
+
+    if depth != 0 || operands.iter().any(|operand| operand.is_empty()) {
+        return syntax(format!("Invalid operands: {}", text));
+    }
+    Ok(operands)
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+/// Parse a decimal or `0x` hexadecimal number, optionally negative.
+fn number(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits.trim_start()),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    } as i128;
+
+    Some(if negative { -value } else { value })
+}
+
+/// Convert a number to an immediate. Numbers above `i64::MAX` are the unsigned form of
+/// negative 64-bit immediates, as printed by disassemblers.
+fn imm(value: i128) -> Option<i64> {
+    i64::try_from(value)
+        .ok()
+        .or_else(|| u64::try_from(value).ok().map(|value| value as i64))
+}
+
+/// Parse a value of a data directive, which must fit into its size either as a signed
+/// or as an unsigned number.
+fn data_value(size: Size, text: &str) -> ParseResult<i64> {
+    let bits = match size {
+        Size::Byte => 8,
+        Size::Word => 16,
+        Size::Dword => 32,
+        Size::Qword => 64,
+    };
+    match number(&text.to_ascii_lowercase()) {
+        Some(value) if (-(1i128 << (bits - 1))..(1i128 << bits)).contains(&value) => {
+            Ok(value as i64)
+        }
+        _ => syntax(format!("Invalid value: {}", text)),
+    }
+}
+
+fn cond(text: &str) -> Option<Cond> {
+    use Cond::*;
+
+    Some(match text {
+        "o" => O,
+        "no" => No,
+        "b" | "c" | "nae" => B,
+        "ae" | "nb" | "nc" => Ae,
+        "e" | "z" => E,
+        "ne" | "nz" => Ne,
+        "be" | "na" => Be,
+        "a" | "nbe" => A,
+        "s" => S,
+        "ns" => Ns,
+        "p" | "pe" => P,
+        "np" | "po" => Np,
+        "l" | "nge" => L,
+        "ge" | "nl" => Ge,
+        "le" | "ng" => Le,
+        "g" | "nle" => G,
+        _ => return None,
+    })
+}
+
+/// Find a register by name, given all the registers of its kind.
+fn find_reg<T: Copy + Debug>(regs: &[T], name: &str) -> Option<T> {
+    regs.iter()
+        .copied()
+        .find(|reg| format!("{:?}", reg).eq_ignore_ascii_case(name))
+}
+
+fn reg64(name: &str) -> Option<Reg64> {
+    use Reg64::*;
+
+    let regs = [
+        Rax, Rcx, Rdx, Rbx, Rsp, Rbp, Rsi, Rdi, R8, R9, R10, R11, R12, R13, R14, R15,
+    ];
+    find_reg(&regs, name)
+}
+
+/// Look up a register of any kind by name.
+fn register(name: &str) -> Option<Operand> {
+    use Reg16::*;
+    use Reg32::*;
+    use Reg8::*;
+    use Xmm::*;
+    use Ymm::*;
+
+    let reg32 = [
+        Eax, Ecx, Edx, Ebx, Esp, Ebp, Esi, Edi, R8d, R9d, R10d, R11d, R12d, R13d, R14d, R15d,
+    ];
+    let reg16 = [
+        Ax, Cx, Dx, Bx, Sp, Bp, Si, Di, R8w, R9w, R10w, R11w, R12w, R13w, R14w, R15w,
+    ];
+    let reg8 = [
+        Al, Cl, Dl, Bl, Spl, Bpl, Sil, Dil, R8b, R9b, R10b, R11b, R12b, R13b, R14b, R15b, Ah, Ch,
+        Dh, Bh,
+    ];
+    let xmm = [
+        Xmm0, Xmm1, Xmm2, Xmm3, Xmm4, Xmm5, Xmm6, Xmm7, Xmm8, Xmm9, Xmm10, Xmm11, Xmm12, Xmm13,
+        Xmm14, Xmm15,
+    ];
+    let ymm = [
+        Ymm0, Ymm1, Ymm2, Ymm3, Ymm4, Ymm5, Ymm6, Ymm7, Ymm8, Ymm9, Ymm10, Ymm11, Ymm12, Ymm13,
+        Ymm14, Ymm15,
+    ];
+
+    reg64(name)
+        .map(Operand::Reg)
+        .or_else(|| find_reg(&reg32, name).map(Operand::Reg32))
+        .or_else(|| find_reg(&reg16, name).map(Operand::Reg16))
+        .or_else(|| find_reg(&reg8, name).map(Operand::Reg8))
+        .or_else(|| find_reg(&xmm, name).map(Operand::Xmm))
+        .or_else(|| find_reg(&ymm, name).map(Operand::Ymm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Operand::{Imm32, Imm64, Imm8, MemDisp, Reg};
+    use Reg64::*;
+
+    #[test]
+    fn test_x86_64_assemble() {
+        let code = assemble(
+            "start:
+                mov r14, rdi
+                mov rax, qword ptr [rbx + 8]
+                mov dword ptr [rsp + 4*rcx - 0x10], 0x1234
+                lea rsi, [rip + data]
+                add r14, 8; cmp r14, rax   # two statements
+                jne start
+                shl eax, cl
+                sete al
+                ret
+            data:
+                .byte 1, 0xff
+                .word 0x1234
+                .long -1
+                .quad 0x1122334455667788",
+        )
+        .unwrap();
+
+        assert_eq!(
+            code,
+            &[
+                0x49, 0x89, 0xfe, // mov r14, rdi
+                0x48, 0x8b, 0x43, 0x08, // mov rax, qword ptr [rbx + 8]
+                0xc7, 0x44, 0x8c, 0xf0, 0x34, 0x12, 0x00,
+                0x00, // mov dword ptr [rsp + 4*rcx - 16], 0x1234
+                0x48, 0x8d, 0x35, 0x0f, 0x00, 0x00, 0x00, // lea rsi, [rip + data]
+                0x49, 0x83, 0xc6, 0x08, // add r14, 8
+                0x49, 0x39, 0xc6, // cmp r14, rax
+                0x75, 0xe1, // jne start
+                0xd3, 0xe0, // shl eax, cl
+                0x0f, 0x94, 0xc0, // sete al
+                0xc3, // ret
+                0x01, 0xff, // .byte 1, 0xff
+                0x34, 0x12, // .word 0x1234
+                0xff, 0xff, 0xff, 0xff, // .long -1
+                0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // .quad 0x1122334455667788
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_assemble_matches_builder() {
+        let mut codegen = X86Asm::new();
+        codegen.push(Reg(R12)).unwrap();
+        codegen.mov(Reg(R14), Reg(Rdi)).unwrap();
+        codegen.add(Reg(R14), Imm32(8)).unwrap();
+        codegen.align(16).unwrap();
+        codegen.pop(Reg(R12)).unwrap();
+        codegen.nop(1).unwrap();
+        codegen.ret().unwrap();
+
+        let code =
+            assemble("push r12; mov r14, rdi; add r14, 8; .align 16; pop r12; nop; ret").unwrap();
+        assert_eq!(code, codegen.code());
+    }
+
+    #[test]
+    fn test_x86_64_assemble_round_trip() {
+        let mut codegen = X86Asm::new();
+        let start = codegen.new_label();
+        let end = codegen.new_label();
+        codegen.bind_label(start).unwrap();
+        codegen.push(Reg(Rbp)).unwrap();
+        codegen.mov(Reg(Rbp), Reg(Rsp)).unwrap();
+        codegen.mov(Reg(Rax), Imm64(-2)).unwrap();
+        codegen.mov(Reg(Rcx), Imm64(0x1122_3344_5566_7788)).unwrap();
+        codegen.and(Operand::Reg32(Reg32::Eax), Imm32(-1)).unwrap();
+        let mem = Mem::qword(Rdi, -8).with_index(R9, Scale::X8);
+        codegen.mov(Reg(R10), Operand::Mem(mem)).unwrap();
+        codegen
+            .cmp(Operand::Mem(Mem::byte(Rsi, 0x100)), Imm8(-1))
+            .unwrap();
+        codegen.jcc(Cond::A, end).unwrap();
+        codegen.sar(Reg(Rdx), Imm8(3)).unwrap();
+        codegen.cmovcc(Cond::L, Reg(Rax), Reg(Rcx)).unwrap();
+        codegen.cvtsi2sd(Operand::Xmm(Xmm::Xmm1), Reg(Rax)).unwrap();
+        let (ymm0, ymm9) = (Operand::Ymm(Ymm::Ymm0), Operand::Ymm(Ymm::Ymm9));
+        codegen.vaddpd(ymm0, ymm9, MemDisp(Rdi, 32)).unwrap();
+        codegen.imul_3(Reg(R9), Reg(Rax), Imm32(1000)).unwrap();
+        codegen
+            .movzx(Reg(Rcx), Operand::Mem(Mem::word(Rsi, 2)))
+            .unwrap();
+        codegen.xchg(Reg(Rcx), Reg(Rax)).unwrap();
+        codegen.popcnt(Reg(Rdx), Reg(R9)).unwrap();
+        codegen.inc(Operand::Reg32(Reg32::R11d)).unwrap();
+        codegen.sub(MemDisp(Rsp, 16), Reg(Rdx)).unwrap();
+        codegen.call(MemDisp(Rax, 8)).unwrap();
+        codegen.jcc(Cond::Ne, start).unwrap();
+        codegen.call(Operand::Label(start)).unwrap();
+        codegen.bind_label(end).unwrap();
+        codegen.pop(Reg(Rbp)).unwrap();
+        codegen.ret().unwrap();
+
+        let text = codegen
+            .disassemble(0)
+            .iter()
+            .map(|insn| insn.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble(&text).unwrap(), codegen.code(), "{}", text);
+    }
+
+    #[test]
+    fn test_x86_64_assemble_errors() {
+        let error = |source| assemble(source).unwrap_err();
+
+        assert_eq!(
+            error("ret\nfoo rax"),
+            ParseError {
+                line: 2,
+                kind: ParseErrorKind::UnknownMnemonic("foo".to_string()),
+            }
+        );
+        assert_eq!(
+            error("jmp done\nret").kind,
+            ParseErrorKind::UndefinedLabel("done".to_string())
+        );
+        assert_eq!(
+            error("a:\nret\na:").kind,
+            ParseErrorKind::DuplicateLabel("a".to_string())
+        );
+        assert_eq!(
+            error("mov rax, qword ptr [rax + rbx + rcx]").kind,
+            ParseErrorKind::Syntax(
+                "Invalid memory operand: qword ptr [rax + rbx + rcx]".to_string()
+            )
+        );
+        assert!(matches!(
+            error("mov al, 0x100").kind,
+            ParseErrorKind::Encode(EncodeError::ImmediateOutOfRange(0x100))
+        ));
+        assert!(matches!(
+            error("mov rax, qword ptr [rbx], 1").kind,
+            ParseErrorKind::UnknownMnemonic(_)
+        ));
+        assert!(matches!(
+            error(".align 12").kind,
+            ParseErrorKind::Encode(EncodeError::InvalidAlignment(12))
+        ));
+        assert!(matches!(error(".align").kind, ParseErrorKind::Syntax(_)));
+        // The jump targets the middle of the `mov`.
+        assert_eq!(
+            error("jmp 0x6; mov eax, 1; ret").kind,
+            ParseErrorKind::InvalidTarget(6)
+        );
+    }
+}
@@ -1,5 +1,5 @@
 #[allow(unused)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Reg64 {
     Rax = 0,
     Rcx = 1,
@@ -18,3 +18,123 @@ pub enum Reg64 {
     R14 = 14,
     R15 = 15,
 }
+
+/// 32-bit general-purpose registers. Writing to them zero-extends the result
+/// into the full 64-bit register.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg32 {
+    Eax = 0,
+    Ecx = 1,
+    Edx = 2,
+    Ebx = 3,
+    Esp = 4,
+    Ebp = 5,
+    Esi = 6,
+    Edi = 7,
+    R8d = 8,
+    R9d = 9,
+    R10d = 10,
+    R11d = 11,
+    R12d = 12,
+    R13d = 13,
+    R14d = 14,
+    R15d = 15,
+}
+
+/// 16-bit general-purpose registers.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg16 {
+    Ax = 0,
+    Cx = 1,
+    Dx = 2,
+    Bx = 3,
+    Sp = 4,
+    Bp = 5,
+    Si = 6,
+    Di = 7,
+    R8w = 8,
+    R9w = 9,
+    R10w = 10,
+    R11w = 11,
+    R12w = 12,
+    R13w = 13,
+    R14w = 14,
+    R15w = 15,
+}
+
+/// 8-bit general-purpose registers.
+///
+/// SPL, BPL, SIL and DIL share their encodings with AH, CH, DH and BH: the former
+/// are selected by the presence of a REX prefix, which makes the latter unencodable
+/// in any instruction that needs one.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg8 {
+    Al = 0,
+    Cl = 1,
+    Dl = 2,
+    Bl = 3,
+    Spl = 4,
+    Bpl = 5,
+    Sil = 6,
+    Dil = 7,
+    R8b = 8,
+    R9b = 9,
+    R10b = 10,
+    R11b = 11,
+    R12b = 12,
+    R13b = 13,
+    R14b = 14,
+    R15b = 15,
+    // The low nibble holds the encoding, bit 4 marks the legacy high-byte registers.
+    Ah = 0x14,
+    Ch = 0x15,
+    Dh = 0x16,
+    Bh = 0x17,
+}
+
+/// 128-bit SSE registers.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xmm {
+    Xmm0 = 0,
+    Xmm1 = 1,
+    Xmm2 = 2,
+    Xmm3 = 3,
+    Xmm4 = 4,
+    Xmm5 = 5,
+    Xmm6 = 6,
+    Xmm7 = 7,
+    Xmm8 = 8,
+    Xmm9 = 9,
+    Xmm10 = 10,
+    Xmm11 = 11,
+    Xmm12 = 12,
+    Xmm13 = 13,
+    Xmm14 = 14,
+    Xmm15 = 15,
+}
+
+/// 256-bit AVX registers. The low 128 bits alias the XMM register with the same number.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ymm {
+    Ymm0 = 0,
+    Ymm1 = 1,
+    Ymm2 = 2,
+    Ymm3 = 3,
+    Ymm4 = 4,
+    Ymm5 = 5,
+    Ymm6 = 6,
+    Ymm7 = 7,
+    Ymm8 = 8,
+    Ymm9 = 9,
+    Ymm10 = 10,
+    Ymm11 = 11,
+    Ymm12 = 12,
+    Ymm13 = 13,
+    Ymm14 = 14,
+    Ymm15 = 15,
+}
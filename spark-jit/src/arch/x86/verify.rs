@@ -0,0 +1,756 @@
+//! Round-trip verification of the encoder against zydis.
+//!
+//! Every emitter is run on all the combinations of a representative set of operands: all
+//! the registers, memory operands with every base and index register and displacements
+//! of each width, and immediates at the edges of each size. The code must decode to a
+//! single instruction with the expected mnemonic and operands.
+
+use zydis::ffi::{DecodedOperand, DecodedOperandKind};
+use zydis::{Decoder, Register, VisibleOperands};
+
+use super::Reg64::*;
+use super::{Base, Cond, Disassembly, Mem, Operand, Reg16, Reg32, Reg64, Reg8, Scale, Size};
+use super::{X86Asm, Xmm, Ymm};
+use crate::arch::EncodeError;
+
+const REG64: [Reg64; 16] = [
+    Rax, Rcx, Rdx, Rbx, Rsp, Rbp, Rsi, Rdi, R8, R9, R10, R11, R12, R13, R14, R15,
+];
+
+const CONDS: [Cond; 16] = [
+    Cond::O,
+    Cond::No,
+    Cond::B,
+    Cond::Ae,
+    Cond::E,
+    Cond::Ne,
+    Cond::Be,
+    Cond::A,
+    Cond::S,
+    Cond::Ns,
+    Cond::P,
+    Cond::Np,
+    Cond::L,
+    Cond::Ge,
+    Cond::Le,
+    Cond::G,
+];
+
+/// Immediates at the edges of the 8, 16 and 32-bit ranges.
+const IMMS: [i64; 11] = [
+    0,
+    1,
+    -1,
+    0x7f,
+    -0x80,
+    0x80,
+    -0x81,
+    0x7fff,
+    -0x8000,
+    0x7fff_ffff,
+    -0x8000_0000,
+];
+
+type Emit<const N: usize> = fn(&mut X86Asm, [Operand; N]) -> Result<(), EncodeError>;
+
+/// Emit an instruction and check that it decodes to `mnemonic` with the `expected`
+/// operands, as given to the emitter (see [`canonical`]).
+fn verify(
+    mnemonic: &str,
+    expected: &[Operand],
+    emit: impl FnOnce(&mut X86Asm) -> Result<(), EncodeError>,
+) {
+    let mut asm = X86Asm::new();
+    if let Err(err) = emit(&mut asm) {
+        panic!("{} {:?}: {}", mnemonic, expected, err);
+    }
+
+    let expected = &canonical(mnemonic, expected);
+    let code = asm.code();
+    let context = || {
+        format!(
+            "{} {:?} encoded as {}",
+            mnemonic,
+            expected,
+            Disassembly::new(code, 0)
+        )
+    };
+    let insn = match Decoder::new64().decode_first::<VisibleOperands>(code) {
+        Ok(Some(insn)) => insn,
+        _ => panic!("{}: can't decode", context()),
+    };
+
+    assert_eq!(
+        insn.length as usize,
+        code.len(),
+        "{}: wrong length",
+        context()
+    );
+    assert_eq!(
+        insn.mnemonic.static_string(),
+        Some(mnemonic),
+        "{}",
+        context()
+    );
+    assert_eq!(insn.operands().len(), expected.len(), "{}", context());
+    for (decoded, expected) in insn.operands().iter().zip(expected) {
+        assert!(matches(expected, decoded), "{}: {:?}", context(), decoded);
+    }
+}
+
+/// The operands as listed by the decoder, for operands given to the emitter. `test`
+/// and `xchg` with a memory operand encode it as the first operand, the short form of
+/// `xchg` with the accumulator lists it last, and `mov` of a non-negative immediate that
+/// fits in 32 bits into a 64-bit register uses the shorter zero-extending 32-bit move.
+fn canonical(mnemonic: &str, ops: &[Operand]) -> Vec<Operand> {
+    let accumulator =
+        |op: Operand| matches!(op.gpr(), Some(gpr) if gpr.num == 0 && gpr.size != Size::Byte);
+
+    match (mnemonic, ops) {
+        ("test" | "xchg", &[reg, mem @ Operand::Mem(_)]) => vec![mem, reg],
+        ("xchg", &[acc, reg]) if accumulator(acc) && !accumulator(reg) && reg.gpr().is_some() => {
+            vec![reg, acc]
+        }
+        ("mov", &[Operand::Reg(reg), Operand::Imm32(imm)]) if imm >= 0 => {
+            vec![Operand::Reg32(reg32(reg as u8)), Operand::Imm32(imm)]
+        }
+        ("mov", &[Operand::Reg(reg), Operand::Imm64(imm)]) if u32::try_from(imm).is_ok() => {
+            vec![Operand::Reg32(reg32(reg as u8)), Operand::Imm32(imm as i32)]
+        }
+        _ => ops.to_vec(),
+    }
+}
+
+/// Check whether a decoded operand is the expected one.
+fn matches(expected: &Operand, decoded: &DecodedOperand) -> bool {
+    fn reg(decoded: &DecodedOperand, reg: impl std::fmt::Debug) -> bool {
+        matches!(decoded.kind, DecodedOperandKind::Reg(found) if is_reg(found, reg))
+    }
+
+    match *expected {
+        Operand::Reg(r) => reg(decoded, r),
+        Operand::Reg32(r) => reg(decoded, r),
+        Operand::Reg16(r) => reg(decoded, r),
+        Operand::Reg8(r) => reg(decoded, r),
+        Operand::Xmm(r) => reg(decoded, r),
+        Operand::Ymm(r) => reg(decoded, r),
+        Operand::Imm64(imm) => matches_imm(imm, decoded),
+        Operand::Imm32(imm) => matches_imm(imm as i64, decoded),
+        Operand::Imm16(imm) => matches_imm(imm as i64, decoded),
+        Operand::Imm8(imm) => matches_imm(imm as i64, decoded),
+        Operand::MemDisp(base, disp) => matches_mem(&Mem::implied(base, disp), decoded),
+        Operand::MemAbs(base) => matches_mem(&Mem::implied(base, 0), decoded),
+        Operand::Mem(mem) => matches_mem(&mem, decoded),
+        Operand::Label(_) => false,
+    }
+}
+
+fn is_reg(found: Register, reg: impl std::fmt::Debug) -> bool {
+    found.static_string() == Some(format!("{:?}", reg).to_lowercase().as_str())
+}
+
+/// Immediates are compared in the size of the operand, as the decoder sign-extends some
+/// and zero-extends others.
+fn matches_imm(imm: i64, decoded: &DecodedOperand) -> bool {
+    let DecodedOperandKind::Imm(info) = &decoded.kind else {
+        return false;
+    };
+    let mask = u64::MAX >> (64 - decoded.size.min(64));
+    (info.value ^ imm as u64) & mask == 0
+}
+
+fn matches_mem(mem: &Mem, decoded: &DecodedOperand) -> bool {
+    let DecodedOperandKind::Mem(info) = &decoded.kind else {
+        return false;
+    };
+    let base = match mem.base {
+        Base::Reg(base) => is_reg(info.base, base),
+        Base::Rip => info.base == Register::RIP,
+        Base::None => info.base == Register::NONE,
+        Base::Label(_) | Base::Constant(_) => false,
+    };
+    let index = match mem.index {
+        Some((index, scale)) => is_reg(info.index, index) && info.scale == 1 << scale as u8,
+        None => info.index == Register::NONE,
+    };
+    let size = match mem.size {
+        Some(size) => decoded.size == bits(size),
+        None => true,
+    };
+    base && index && size && info.disp.displacement == mem.disp as i64
+}
+
+fn bits(size: Size) -> u16 {
+    match size {
+        Size::Byte => 8,
+        Size::Word => 16,
+        Size::Dword => 32,
+        Size::Qword => 64,
+    }
+}
+
+/// Zydis mnemonic suffix of a condition.
+fn cc(cond: Cond) -> &'static str {
+    match cond {
+        Cond::O => "o",
+        Cond::No => "no",
+        Cond::B => "b",
+        Cond::Ae => "nb",
+        Cond::E => "z",
+        Cond::Ne => "nz",
+        Cond::Be => "be",
+        Cond::A => "nbe",
+        Cond::S => "s",
+        Cond::Ns => "ns",
+        Cond::P => "p",
+        Cond::Np => "np",
+        Cond::L => "l",
+        Cond::Ge => "nl",
+        Cond::Le => "le",
+        Cond::G => "nle",
+    }
+}
+
+/// The general-purpose registers of one size, with immediates of the matching size.
+struct Gpr {
+    size: Size,
+    regs: Vec<Operand>,
+    imms: Vec<Operand>,
+}
+
+/// The general-purpose registers of each size. The legacy high-byte registers are left
+/// out, as they can't be combined with the registers that need a REX prefix.
+fn gprs() -> [Gpr; 4] {
+    let regs = |reg: fn(u8) -> Operand| (0..16).map(reg).collect::<Vec<_>>();
+    let imms = |imm: fn(i64) -> Option<Operand>| IMMS.into_iter().filter_map(imm).collect();
+
+    [
+        Gpr {
+            size: Size::Qword,
+            regs: regs(|n| Operand::Reg(REG64[n as usize])),
+            imms: imms(|imm| Some(Operand::Imm32(imm.try_into().ok()?))),
+        },
+        Gpr {
+            size: Size::Dword,
+            regs: regs(|n| Operand::Reg32(reg32(n))),
+            imms: imms(|imm| Some(Operand::Imm32(imm.try_into().ok()?))),
+        },
+        Gpr {
+            size: Size::Word,
+            regs: regs(|n| Operand::Reg16(reg16(n))),
+            imms: imms(|imm| Some(Operand::Imm16(imm.try_into().ok()?))),
+        },
+        Gpr {
+            size: Size::Byte,
+            regs: regs(|n| Operand::Reg8(reg8(n))),
+            imms: imms(|imm| Some(Operand::Imm8(imm.try_into().ok()?))),
+        },
+    ]
+}
+
+fn reg32(n: u8) -> Reg32 {
+    use Reg32::*;
+
+    [
+        Eax, Ecx, Edx, Ebx, Esp, Ebp, Esi, Edi, R8d, R9d, R10d, R11d, R12d, R13d, R14d, R15d,
+    ][n as usize]
+}
+
+fn reg16(n: u8) -> Reg16 {
+    use Reg16::*;
+
+    [
+        Ax, Cx, Dx, Bx, Sp, Bp, Si, Di, R8w, R9w, R10w, R11w, R12w, R13w, R14w, R15w,
+    ][n as usize]
+}
+
+fn reg8(n: u8) -> Reg8 {
+    use Reg8::*;
+
+    [
+        Al, Cl, Dl, Bl, Spl, Bpl, Sil, Dil, R8b, R9b, R10b, R11b, R12b, R13b, R14b, R15b,
+    ][n as usize]
+}
+
+fn xmms() -> Vec<Operand> {
+    use Xmm::*;
+
+    [
+        Xmm0, Xmm1, Xmm2, Xmm3, Xmm4, Xmm5, Xmm6, Xmm7, Xmm8, Xmm9, Xmm10, Xmm11, Xmm12, Xmm13,
+        Xmm14, Xmm15,
+    ]
+    .map(Operand::Xmm)
+    .to_vec()
+}
+
+fn ymms() -> Vec<Operand> {
+    use Ymm::*;
+
+    [
+        Ymm0, Ymm1, Ymm2, Ymm3, Ymm4, Ymm5, Ymm6, Ymm7, Ymm8, Ymm9, Ymm10, Ymm11, Ymm12, Ymm13,
+        Ymm14, Ymm15,
+    ]
+    .map(Operand::Ymm)
+    .to_vec()
+}
+
+/// Memory operands of the given size: every base register with displacements of each
+/// width, every index register and scale with the bases that have special encodings,
+/// absolute and RIP-relative addresses.
+fn mems(size: Option<Size>) -> Vec<Operand> {
+    let mem = |base, index, disp| {
+        Operand::Mem(Mem {
+            size,
+            base,
+            index,
+            disp,
+        })
+    };
+    let mut mems = Vec::new();
+
+    for base in REG64 {
+        for disp in [0, 0x7f, -0x80, 0x80, -0x1234_5678] {
+            mems.push(mem(Base::Reg(base), None, disp));
+        }
+    }
+    for base in [Rax, Rsp, Rbp, R12, R13] {
+        for index in REG64.into_iter().filter(|&index| index != Rsp) {
+            for scale in [Scale::X1, Scale::X2, Scale::X4, Scale::X8] {
+                for disp in [0, -8] {
+                    mems.push(mem(Base::Reg(base), Some((index, scale)), disp));
+                }
+            }
+        }
+    }
+    mems.push(mem(Base::None, None, 0x1000));
+    mems.push(mem(Base::None, Some((R9, Scale::X8)), 0x1000));
+    mems.push(mem(Base::Rip, None, -0x10));
+    mems
+}
+
+/// Pair every operand of `lhs` with every operand of `rhs`.
+fn all_pairs(lhs: &[Operand], rhs: &[Operand]) -> Vec<[Operand; 2]> {
+    lhs.iter()
+        .flat_map(|&lhs| rhs.iter().map(move |&rhs| [lhs, rhs]))
+        .collect()
+}
+
+/// Pair every operand of `lhs` with one operand of `rhs`, cycling through `rhs`. Used
+/// with memory operands, where all the pairs would take too long.
+fn cycle_pairs(lhs: &[Operand], rhs: &[Operand]) -> Vec<[Operand; 2]> {
+    lhs.iter()
+        .zip(rhs.iter().cycle())
+        .map(|(&lhs, &rhs)| [lhs, rhs])
+        .collect()
+}
+
+/// Swap the operands of pairs, e.g. to turn memory destinations into memory sources.
+fn swapped(pairs: Vec<[Operand; 2]>) -> Vec<[Operand; 2]> {
+    pairs.into_iter().map(|[lhs, rhs]| [rhs, lhs]).collect()
+}
+
+fn verify_all<const N: usize>(mnemonic: &str, forms: &[[Operand; N]], emit: Emit<N>) {
+    for &ops in forms {
+        verify(mnemonic, &ops, |asm| emit(asm, ops));
+    }
+}
+
+/// Verify a binary instruction with a general-purpose register or memory destination.
+fn verify_binary(mnemonic: &str, emit: Emit<2>) {
+    for gpr in gprs() {
+        let mems = mems(Some(gpr.size));
+        verify_all(mnemonic, &all_pairs(&gpr.regs, &gpr.regs), emit);
+        verify_all(mnemonic, &all_pairs(&gpr.regs, &gpr.imms), emit);
+        verify_all(mnemonic, &swapped(cycle_pairs(&mems, &gpr.regs)), emit);
+        verify_all(mnemonic, &cycle_pairs(&mems, &gpr.regs), emit);
+        verify_all(mnemonic, &cycle_pairs(&mems, &gpr.imms), emit);
+    }
+}
+
+fn verify_unary(mnemonic: &str, emit: Emit<1>) {
+    for gpr in gprs() {
+        let regs: Vec<_> = gpr.regs.iter().map(|&reg| [reg]).collect();
+        let mems: Vec<_> = mems(Some(gpr.size)).into_iter().map(|mem| [mem]).collect();
+        verify_all(mnemonic, &regs, emit);
+        verify_all(mnemonic, &mems, emit);
+    }
+}
+
+fn verify_shift(mnemonic: &str, emit: Emit<2>) {
+    let counts = [1, 2, 7, 0x3f].map(Operand::Imm8);
+    let cl = [Operand::Reg8(Reg8::Cl)];
+
+    for gpr in gprs() {
+        let mems = mems(Some(gpr.size));
+        verify_all(mnemonic, &all_pairs(&gpr.regs, &counts), emit);
+        verify_all(mnemonic, &all_pairs(&gpr.regs, &cl), emit);
+        verify_all(mnemonic, &cycle_pairs(&mems, &counts), emit);
+        verify_all(mnemonic, &all_pairs(&mems, &cl), emit);
+    }
+}
+
+/// Verify an SSE instruction with an XMM destination and an XMM or memory source.
+fn verify_sse(mnemonic: &str, emit: Emit<2>) {
+    let xmms = xmms();
+    verify_all(mnemonic, &all_pairs(&xmms, &xmms), emit);
+    verify_all(mnemonic, &swapped(cycle_pairs(&mems(None), &xmms)), emit);
+}
+
+/// Verify a VEX-encoded instruction with three vector operands, the last of which can
+/// be in memory.
+fn verify_avx(mnemonic: &str, emit: Emit<3>) {
+    let mems = mems(None);
+    for regs in [xmms(), ymms()] {
+        for &dst in &regs {
+            for [src1, src2] in all_pairs(&regs, &regs) {
+                verify(mnemonic, &[dst, src1, src2], |asm| {
+                    emit(asm, [dst, src1, src2])
+                });
+            }
+        }
+        for (i, &mem) in mems.iter().enumerate() {
+            let ops = [regs[i % 16], regs[(i + 5) % 16], mem];
+            verify(mnemonic, &ops, |asm| emit(asm, ops));
+        }
+    }
+}
+
+#[test]
+fn test_x86_64_verify_mov() {
+    verify_binary("mov", |asm, [dst, src]| asm.mov(dst, src));
+
+    for reg in REG64 {
+        let imms = [
+            -1,
+            0x7fff_ffff,
+            0xffff_ffff,
+            0x1_0000_0000,
+            i64::MIN,
+            i64::MAX,
+        ];
+        for imm in imms {
+            let ops = [Operand::Reg(reg), Operand::Imm64(imm)];
+            verify("mov", &ops, |asm| asm.mov(ops[0], ops[1]));
+        }
+    }
+
+    for base in REG64 {
+        let ops = [Operand::Reg(base), Operand::MemDisp(base, -8)];
+        verify("mov", &ops, |asm| asm.mov(ops[0], ops[1]));
+        let ops = [Operand::MemAbs(base), Operand::Reg(base)];
+        verify("mov", &ops, |asm| asm.mov(ops[0], ops[1]));
+    }
+
+    // Without a REX prefix, the high-byte registers replace SPL, BPL, SIL and DIL.
+    let legacy = [0, 1, 2, 3].map(|n| Operand::Reg8(reg8(n)));
+    let high = [Reg8::Ah, Reg8::Ch, Reg8::Dh, Reg8::Bh].map(Operand::Reg8);
+    let regs = [legacy, high].concat();
+    verify_all("mov", &all_pairs(&regs, &regs), |asm, [dst, src]| {
+        asm.mov(dst, src)
+    });
+}
+
+#[test]
+fn test_x86_64_verify_alu() {
+    verify_binary("add", |asm, [dst, src]| asm.add(dst, src));
+    verify_binary("sub", |asm, [dst, src]| asm.sub(dst, src));
+    verify_binary("sbb", |asm, [dst, src]| asm.sbb(dst, src));
+    verify_binary("adc", |asm, [dst, src]| asm.adc(dst, src));
+    verify_binary("cmp", |asm, [dst, src]| asm.cmp(dst, src));
+    verify_binary("and", |asm, [dst, src]| asm.and(dst, src));
+    verify_binary("or", |asm, [dst, src]| asm.or(dst, src));
+    verify_binary("xor", |asm, [dst, src]| asm.xor(dst, src));
+    verify_binary("test", |asm, [dst, src]| asm.test(dst, src));
+}
+
+#[test]
+fn test_x86_64_verify_unary() {
+    verify_unary("not", |asm, [dst]| asm.not(dst));
+    verify_unary("neg", |asm, [dst]| asm.neg(dst));
+    verify_unary("inc", |asm, [dst]| asm.inc(dst));
+    verify_unary("dec", |asm, [dst]| asm.dec(dst));
+    verify_unary("mul", |asm, [src]| asm.mul(src));
+    verify_unary("imul", |asm, [src]| asm.imul(src));
+    verify_unary("div", |asm, [src]| asm.div(src));
+    verify_unary("idiv", |asm, [src]| asm.idiv(src));
+}
+
+#[test]
+fn test_x86_64_verify_shifts() {
+    verify_shift("rol", |asm, [dst, count]| asm.rol(dst, count));
+    verify_shift("ror", |asm, [dst, count]| asm.ror(dst, count));
+    verify_shift("shl", |asm, [dst, count]| asm.shl(dst, count));
+    verify_shift("shr", |asm, [dst, count]| asm.shr(dst, count));
+    verify_shift("sar", |asm, [dst, count]| asm.sar(dst, count));
+}
+
+#[test]
+fn test_x86_64_verify_stack() {
+    for reg in REG64.map(Operand::Reg) {
+        verify("push", &[reg], |asm| asm.push(reg));
+        verify("pop", &[reg], |asm| asm.pop(reg));
+        verify("call", &[reg], |asm| asm.call(reg));
+        verify("jmp", &[reg], |asm| asm.jmp(reg));
+    }
+    for mem in mems(Some(Size::Qword)) {
+        verify("push", &[mem], |asm| asm.push(mem));
+        verify("pop", &[mem], |asm| asm.pop(mem));
+        verify("call", &[mem], |asm| asm.call(mem));
+        verify("jmp", &[mem], |asm| asm.jmp(mem));
+    }
+    for imm in IMMS.map(Operand::Imm64) {
+        verify("push", &[imm], |asm| asm.push(imm));
+    }
+    verify("ret", &[], |asm| asm.ret());
+    verify("cqo", &[], |asm| asm.cqo());
+}
+
+#[test]
+fn test_x86_64_verify_conditional() {
+    let [reg64, reg32, reg16, reg8] = gprs().map(|gpr| gpr.regs);
+
+    for cond in CONDS {
+        let cmov = format!("cmov{}", cc(cond));
+        for (regs, size) in [
+            (&reg64, Size::Qword),
+            (&reg32, Size::Dword),
+            (&reg16, Size::Word),
+        ] {
+            for ops in all_pairs(regs, regs).into_iter().chain(
+                cycle_pairs(&mems(Some(size)), regs)
+                    .into_iter()
+                    .map(|[mem, reg]| [reg, mem]),
+            ) {
+                verify(&cmov, &ops, |asm| asm.cmovcc(cond, ops[0], ops[1]));
+            }
+        }
+
+        let set = format!("set{}", cc(cond));
+        for dst in reg8.iter().copied().chain(mems(Some(Size::Byte))) {
+            verify(&set, &[dst], |asm| asm.setcc(cond, dst));
+        }
+    }
+}
+
+#[test]
+fn test_x86_64_verify_lea() {
+    let gprs = gprs();
+    for gpr in &gprs[..3] {
+        let forms = swapped(cycle_pairs(&mems(None), &gpr.regs));
+        verify_all("lea", &forms, |asm, [dst, src]| asm.lea(dst, src));
+    }
+}
+
+#[test]
+fn test_x86_64_verify_extend() {
+    let [reg64, reg32, reg16, reg8] = gprs().map(|gpr| gpr.regs);
+    let [byte, word, dword] = [Size::Byte, Size::Word, Size::Dword].map(|size| mems(Some(size)));
+
+    for (dsts, srcs) in [
+        (&reg64, &[(&reg8, &byte), (&reg16, &word)][..]),
+        (&reg32, &[(&reg8, &byte), (&reg16, &word)]),
+        (&reg16, &[(&reg8, &byte)]),
+    ] {
+        for &(regs, mems) in srcs {
+            let forms = [all_pairs(dsts, regs), swapped(cycle_pairs(mems, dsts))].concat();
+            verify_all("movzx", &forms, |asm, [dst, src]| asm.movzx(dst, src));
+            verify_all("movsx", &forms, |asm, [dst, src]| asm.movsx(dst, src));
+        }
+    }
+
+    let forms = [
+        all_pairs(&reg64, &reg32),
+        swapped(cycle_pairs(&dword, &reg64)),
+    ]
+    .concat();
+    verify_all("movsxd", &forms, |asm, [dst, src]| asm.movsxd(dst, src));
+}
+
+#[test]
+fn test_x86_64_verify_imul() {
+    for gpr in &gprs()[..3] {
+        let mems = mems(Some(gpr.size));
+        let forms = [
+            all_pairs(&gpr.regs, &gpr.regs),
+            swapped(cycle_pairs(&mems, &gpr.regs)),
+        ]
+        .concat();
+        verify_all("imul", &forms, |asm, [dst, src]| asm.imul_2(dst, src));
+
+        for (i, [dst, src]) in forms.into_iter().enumerate() {
+            let ops = [dst, src, gpr.imms[i % gpr.imms.len()]];
+            verify("imul", &ops, |asm| asm.imul_3(ops[0], ops[1], ops[2]));
+        }
+    }
+}
+
+#[test]
+fn test_x86_64_verify_xchg() {
+    for gpr in gprs() {
+        let mems = mems(Some(gpr.size));
+        let forms = [
+            all_pairs(&gpr.regs, &gpr.regs),
+            cycle_pairs(&mems, &gpr.regs),
+            swapped(cycle_pairs(&mems, &gpr.regs)),
+        ]
+        .concat();
+        verify_all("xchg", &forms, |asm, [dst, src]| asm.xchg(dst, src));
+    }
+}
+
+#[test]
+fn test_x86_64_verify_bit_ops() {
+    let gprs = gprs();
+    for gpr in &gprs[..2] {
+        let regs: Vec<_> = gpr.regs.iter().map(|&reg| [reg]).collect();
+        verify_all("bswap", &regs, |asm, [dst]| asm.bswap(dst));
+    }
+
+    for gpr in &gprs[..3] {
+        let forms = [
+            all_pairs(&gpr.regs, &gpr.regs),
+            swapped(cycle_pairs(&mems(Some(gpr.size)), &gpr.regs)),
+        ]
+        .concat();
+        verify_all("popcnt", &forms, |asm, [dst, src]| asm.popcnt(dst, src));
+        verify_all("lzcnt", &forms, |asm, [dst, src]| asm.lzcnt(dst, src));
+        verify_all("tzcnt", &forms, |asm, [dst, src]| asm.tzcnt(dst, src));
+    }
+}
+
+/// Check that a branch emitted at `start` decodes to `mnemonic` and targets `target`.
+fn verify_branch(mnemonic: &str, asm: &X86Asm, start: usize, target: usize) {
+    let code = &asm.code()[start..];
+    let context = || {
+        format!(
+            "{} to {:#x}: {}",
+            mnemonic,
+            target,
+            Disassembly::new(code, 0)
+        )
+    };
+    let insn = Decoder::new64()
+        .decode_first::<VisibleOperands>(code)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        insn.mnemonic.static_string(),
+        Some(mnemonic),
+        "{}",
+        context()
+    );
+
+    let DecodedOperandKind::Imm(imm) = &insn.operands()[0].kind else {
+        panic!("{}: not a relative branch", context());
+    };
+    let end = start + insn.length as usize;
+    assert!(imm.is_relative, "{}", context());
+    assert_eq!(
+        end as i64 + imm.value as i64,
+        target as i64,
+        "{}",
+        context()
+    );
+}
+
+#[test]
+fn test_x86_64_verify_branches() {
+    let branches = [("jmp".to_string(), None), ("call".to_string(), None)]
+        .into_iter()
+        .chain(CONDS.map(|cond| (format!("j{}", cc(cond)), Some(cond))));
+    let emit = |asm: &mut X86Asm, mnemonic: &str, cond, label| match (mnemonic, cond) {
+        ("call", _) => asm.call(label),
+        (_, Some(cond)) => asm.jcc(cond, label),
+        _ => asm.jmp(label),
+    };
+
+    // Backward branches to bound labels, in and out of the rel8 range, and forward
+    // branches to labels bound later.
+    for (mnemonic, cond) in branches {
+        for distance in [0, 0x10, 0x7d, 0x80, 0x1000] {
+            let mut asm = X86Asm::new();
+            let label = asm.new_label();
+            asm.bind_label(label).unwrap();
+            for _ in 0..distance {
+                asm.ret().unwrap();
+            }
+            emit(&mut asm, &mnemonic, cond, label).unwrap();
+            verify_branch(&mnemonic, &asm, distance, 0);
+
+            let mut asm = X86Asm::new();
+            let label = asm.new_label();
+            emit(&mut asm, &mnemonic, cond, label).unwrap();
+            for _ in 0..distance {
+                asm.ret().unwrap();
+            }
+            let target = asm.offset();
+            asm.bind_label(label).unwrap();
+            verify_branch(&mnemonic, &asm, 0, target);
+        }
+    }
+}
+
+#[test]
+fn test_x86_64_verify_sse() {
+    verify_sse("movsd", |asm, [dst, src]| asm.movsd(dst, src));
+    verify_sse("movq", |asm, [dst, src]| asm.movq(dst, src));
+    verify_sse("addsd", |asm, [dst, src]| asm.addsd(dst, src));
+    verify_sse("subsd", |asm, [dst, src]| asm.subsd(dst, src));
+    verify_sse("mulsd", |asm, [dst, src]| asm.mulsd(dst, src));
+    verify_sse("divsd", |asm, [dst, src]| asm.divsd(dst, src));
+    verify_sse("sqrtsd", |asm, [dst, src]| asm.sqrtsd(dst, src));
+    verify_sse("ucomisd", |asm, [dst, src]| asm.ucomisd(dst, src));
+    verify_sse("xorpd", |asm, [dst, src]| asm.xorpd(dst, src));
+
+    let xmms = xmms();
+    let mem_ops = mems(None);
+    let [reg64, reg32, ..] = gprs().map(|gpr| gpr.regs);
+    verify_all("movsd", &cycle_pairs(&mem_ops, &xmms), |asm, [dst, src]| {
+        asm.movsd(dst, src)
+    });
+    verify_all("movq", &cycle_pairs(&mem_ops, &xmms), |asm, [dst, src]| {
+        asm.movq(dst, src)
+    });
+    verify_all("movq", &all_pairs(&xmms, &reg64), |asm, [dst, src]| {
+        asm.movq(dst, src)
+    });
+    verify_all("movq", &all_pairs(&reg64, &xmms), |asm, [dst, src]| {
+        asm.movq(dst, src)
+    });
+    for regs in [&reg64, &reg32] {
+        let emit: Emit<2> = |asm, [dst, src]| asm.cvtsi2sd(dst, src);
+        verify_all("cvtsi2sd", &all_pairs(&xmms, regs), emit);
+        let emit: Emit<2> = |asm, [dst, src]| asm.cvttsd2si(dst, src);
+        verify_all("cvttsd2si", &all_pairs(regs, &xmms), emit);
+        verify_all("cvttsd2si", &swapped(cycle_pairs(&mem_ops, regs)), emit);
+    }
+    let forms = swapped(cycle_pairs(&mems(Some(Size::Qword)), &xmms));
+    verify_all("cvtsi2sd", &forms, |asm, [dst, src]| asm.cvtsi2sd(dst, src));
+}
+
+#[test]
+fn test_x86_64_verify_avx() {
+    verify_avx("vaddpd", |asm, [dst, src1, src2]| {
+        asm.vaddpd(dst, src1, src2)
+    });
+    verify_avx("vmulpd", |asm, [dst, src1, src2]| {
+        asm.vmulpd(dst, src1, src2)
+    });
+    verify_avx("vfmadd231pd", |asm, [dst, src1, src2]| {
+        asm.vfmadd231pd(dst, src1, src2)
+    });
+    verify_avx("vpaddq", |asm, [dst, src1, src2]| {
+        asm.vpaddq(dst, src1, src2)
+    });
+
+    let mems = mems(None);
+    for regs in [xmms(), ymms()] {
+        let emit: Emit<2> = |asm, [dst, src]| asm.vmovupd(dst, src);
+        verify_all("vmovupd", &all_pairs(&regs, &regs), emit);
+        verify_all("vmovupd", &cycle_pairs(&mems, &regs), emit);
+        verify_all("vmovupd", &swapped(cycle_pairs(&mems, &regs)), emit);
+    }
+    let emit: Emit<2> = |asm, [dst, src]| asm.vbroadcastsd(dst, src);
+    verify_all("vbroadcastsd", &all_pairs(&ymms(), &xmms()), emit);
+    verify_all("vbroadcastsd", &swapped(cycle_pairs(&mems, &ymms())), emit);
+}
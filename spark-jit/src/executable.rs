@@ -1,12 +1,83 @@
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
 use crate::mmap::MmapBuf;
+use crate::module::{LinkError, Module};
 
 use super::mmap::GuardedMmap;
 
 /// The size of the evaluation stack in bytes.
 const EVAL_STACK_SIZE: usize = 8 * 1024;
 
+/// The `(offset, len)` of every instruction of an executable, sorted by offset.
+#[derive(Clone)]
+struct InsnTable {
+    code_addr: usize,
+    insns: Arc<[(usize, usize)]>,
+}
+
+/// The instruction tables of the live executables, sorted by code address. See
+/// [`instruction_at`].
+///
+/// Fault handlers read the tables from signal handlers, where taking a lock isn't
+/// async-signal-safe. So the tables are never modified in place: every update publishes a
+/// new copy, and only frees the previous one once no [`instruction_at`] call can still be
+/// reading it.
+static INSTRUCTION_TABLES: AtomicPtr<Vec<InsnTable>> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Number of [`instruction_at`] calls in progress.
+static INSTRUCTION_READERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes the updates of [`INSTRUCTION_TABLES`]. Never taken by readers.
+static INSTRUCTION_UPDATES: Mutex<()> = Mutex::new(());
+
+/// Find the instruction of a live executable that contains the address, and return the
+/// addresses it spans. Only executables whose instructions were recorded with
+/// [`Executable::set_instructions`] are searched.
+///
+/// Meant for fault handlers, e.g. to resume execution after a faulting instruction. It
+/// doesn't lock or allocate, so it's async-signal-safe.
+pub fn instruction_at(addr: usize) -> Option<Range<usize>> {
+    // Announce the read before loading the tables, so that an update that replaced them
+    // in the meantime waits for it before freeing them.
+    INSTRUCTION_READERS.fetch_add(1, Ordering::SeqCst);
+    let tables = unsafe { INSTRUCTION_TABLES.load(Ordering::SeqCst).as_ref() };
+    let found = tables.and_then(|tables| {
+        let i = tables.partition_point(|table| table.code_addr <= addr);
+        let table = tables[..i].last()?;
+        let offset = addr - table.code_addr;
+        let i = table.insns.partition_point(|&(start, _)| start <= offset);
+        let &(start, len) = table.insns[..i].last()?;
+        let code_addr = table.code_addr;
+        (offset < start + len).then(|| code_addr + start..code_addr + start + len)
+    });
+    INSTRUCTION_READERS.fetch_sub(1, Ordering::SeqCst);
+    found
+}
+
+/// Publish a copy of the instruction tables changed by `update`, and free the previous
+/// one once the [`instruction_at`] calls that may still be reading it are done.
+fn update_instruction_tables(update: impl FnOnce(&mut Vec<InsnTable>)) {
+    let _guard = INSTRUCTION_UPDATES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let old = INSTRUCTION_TABLES.load(Ordering::SeqCst);
+    let mut tables = unsafe { old.as_ref() }.cloned().unwrap_or_default();
+    update(&mut tables);
+    INSTRUCTION_TABLES.store(Box::into_raw(Box::new(tables)), Ordering::SeqCst);
+
+    // Readers that start from now on load the new tables. The handlers still running
+    // are short and never wait on this thread, so this doesn't spin for long.
+    while INSTRUCTION_READERS.load(Ordering::SeqCst) != 0 {
+        std::hint::spin_loop();
+    }
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
 /// An executable generated by the JIT compiler.
 ///
 /// The executable contains the machine code generated by the JIT compiler and an evaluation stack.
@@ -43,12 +114,60 @@ impl Executable {
     ///
     /// A new executable.
     pub fn new(code_bytes: &[u8], integrity: &[u8], variables_map: HashMap<String, usize>) -> Self {
-        let mut stack_and_code = MmapBuf::new(EVAL_STACK_SIZE + code_bytes.len() + 4096).unwrap();
+        let exec = Self::with_code_size(code_bytes.len(), integrity, variables_map);
+        exec.copy_code(code_bytes);
+        exec
+    }
+
+    /// Create a new executable from a module of functions. The first function of the
+    /// module is the entry point, and its instructions are recorded as with
+    /// [`Executable::set_instructions`].
+    ///
+    /// # Arguments
+    ///
+    /// * `module` - The functions to link and execute.
+    /// * `integrity` - The SHA256 hash of the code (calculated by the compiler).
+    /// * `variables_map` - Mapping of variable names to their offsets in the variables area.
+    ///
+    /// # Returns
+    ///
+    /// A new executable, or an error if the module can't be linked.
+    pub fn from_module(
+        module: &Module,
+        integrity: &[u8],
+        variables_map: HashMap<String, usize>,
+    ) -> Result<Self, LinkError> {
+        let mut exec = Self::with_code_size(module.size(), integrity, variables_map);
+        let code_addr = exec.code.as_ref().unwrap().ptr() as usize;
+        exec.copy_code(&module.link(code_addr)?);
+        exec.set_instructions(module.instructions());
+        Ok(exec)
+    }
+
+    /// Map the evaluation stack and room for the code.
+    fn with_code_size(
+        code_size: usize,
+        integrity: &[u8],
+        variables_map: HashMap<String, usize>,
+    ) -> Self {
+        let mut stack_and_code = MmapBuf::new(EVAL_STACK_SIZE + code_size + 4096).unwrap();
         stack_and_code.protect_rwx().unwrap();
 
         let code = stack_and_code
-            .split_end(MmapBuf::page_aligned_size(code_bytes.len()))
+            .split_end(MmapBuf::page_aligned_size(code_size))
             .unwrap();
+
+        Self {
+            code: Some(code),
+            eval_stack: Some(stack_and_code),
+            variables_map,
+            integrity: integrity.to_vec(),
+        }
+    }
+
+    /// Copy the machine code to the start of the code mapping.
+    fn copy_code(&self, code_bytes: &[u8]) {
+        let code = self.code.as_ref().unwrap();
         unsafe {
             std::ptr::copy_nonoverlapping(
                 code_bytes.as_ptr(),
@@ -56,12 +175,22 @@ impl Executable {
                 code_bytes.len(),
             );
         }
+    }
 
-        Self {
-            code: Some(code),
-            eval_stack: Some(stack_and_code),
-            variables_map,
-            integrity: integrity.to_vec(),
+    /// Record the instructions of the code as `(offset, len)` pairs sorted by offset,
+    /// e.g. from [`X86Asm::instructions`](crate::X86Asm::instructions), so that
+    /// [`instruction_at`] can find them while the executable is alive.
+    pub fn set_instructions(&mut self, insns: impl IntoIterator<Item = (usize, usize)>) {
+        if let Some(code) = &self.code {
+            let table = InsnTable {
+                code_addr: code.ptr() as usize,
+                insns: insns.into_iter().collect(),
+            };
+            update_instruction_tables(|tables| {
+                tables.retain(|other| other.code_addr != table.code_addr);
+                let i = tables.partition_point(|other| other.code_addr < table.code_addr);
+                tables.insert(i, table);
+            });
         }
     }
 
@@ -122,3 +251,46 @@ impl Executable {
         Ok(func(eval_stack_ptr as *mut i64, variables_area.as_ptr()))
     }
 }
+
+impl Drop for Executable {
+    fn drop(&mut self) {
+        if let Some(code) = &self.code {
+            let code_addr = code.ptr() as usize;
+            update_instruction_tables(|tables| {
+                tables.retain(|table| table.code_addr != code_addr);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_at() {
+        let mut first = Executable::new(&[0xc3; 8], &[], HashMap::new());
+        let mut second = Executable::new(&[0xc3; 8], &[], HashMap::new());
+        first.set_instructions([(0, 3), (3, 5)]);
+        second.set_instructions([(0, 8)]);
+
+        let first_addr = first.code.as_ref().unwrap().ptr() as usize;
+        let second_addr = second.code.as_ref().unwrap().ptr() as usize;
+        assert_eq!(
+            instruction_at(first_addr + 4),
+            Some(first_addr + 3..first_addr + 8)
+        );
+        assert_eq!(
+            instruction_at(second_addr),
+            Some(second_addr..second_addr + 8)
+        );
+        assert_eq!(instruction_at(first_addr + 8), None);
+
+        drop(first);
+        assert_eq!(instruction_at(first_addr + 4), None);
+        assert_eq!(
+            instruction_at(second_addr + 7),
+            Some(second_addr..second_addr + 8)
+        );
+    }
+}
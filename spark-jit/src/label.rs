@@ -0,0 +1,84 @@
+use crate::arch::EncodeError;
+
+/// A position in the generated code.
+///
+/// A label is created unbound, can be referenced by branch instructions before its position
+/// is known, and is bound exactly once to an offset in the code buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(usize);
+
+impl Label {
+    /// Index of the label within the assembler that created it.
+    pub fn id(&self) -> usize {
+        self.0
+    }
+}
+
+/// A reference to a label that has to be patched once the label is bound.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fixup<K> {
+    /// The referenced label.
+    pub label: Label,
+    /// Offset of the field to patch.
+    pub offset: usize,
+    /// Architecture-specific kind of the patched field.
+    pub kind: K,
+}
+
+/// Bookkeeping of label offsets and fixups that still wait for their label to be bound.
+#[derive(Debug)]
+pub(crate) struct LabelTable<K> {
+    offsets: Vec<Option<usize>>,
+    pending: Vec<Fixup<K>>,
+}
+
+impl<K> Default for LabelTable<K> {
+    fn default() -> Self {
+        Self {
+            offsets: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<K> LabelTable<K> {
+    /// Create a new unbound label.
+    pub fn create(&mut self) -> Label {
+        self.offsets.push(None);
+        Label(self.offsets.len() - 1)
+    }
+
+    /// Get the offset of a bound label.
+    pub fn offset(&self, label: Label) -> Option<usize> {
+        self.offsets[label.0]
+    }
+
+    /// Bind the label to the given offset.
+    ///
+    /// # Returns
+    ///
+    /// The fixups that were waiting for this label and must now be patched, or an error
+    /// if the label is already bound.
+    pub fn bind(&mut self, label: Label, offset: usize) -> Result<Vec<Fixup<K>>, EncodeError> {
+        if self.offsets[label.0].is_some() {
+            return Err(EncodeError::LabelAlreadyBound(label));
+        }
+        self.offsets[label.0] = Some(offset);
+
+        let (resolved, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|fixup| fixup.label == label);
+        self.pending = pending;
+        Ok(resolved)
+    }
+
+    /// Record a fixup for a label that is not bound yet.
+    pub fn add_pending(&mut self, fixup: Fixup<K>) {
+        self.pending.push(fixup);
+    }
+
+    /// Check whether some fixups still reference unbound labels.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
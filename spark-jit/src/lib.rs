@@ -1,6 +1,12 @@
 pub mod arch;
 pub mod executable;
+pub mod label;
 pub mod mmap;
+pub mod module;
 pub mod writer;
 
+pub use arch::aarch64::AArch64Asm;
+pub use arch::arm::ArmAsm;
 pub use arch::x86::X86Asm;
+pub use arch::Assembler;
+pub use module::Module;
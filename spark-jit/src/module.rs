@@ -0,0 +1,438 @@
+//! Linking of several functions into one executable mapping.
+//!
+//! Every function is generated by its own [`X86Asm`] and calls the other functions of the
+//! module, or native functions registered with [`Module::add_extern`], by name with
+//! [`X86Asm::call_symbol`]. The functions are laid out one after another, followed by a
+//! stub for every native function:
+//!
+//! ```text
+//! jmp qword ptr [rip]
+//! .quad <address of the native function>
+//! ```
+//!
+//! so that every call is a `call rel32` that stays within the mapping, however far the
+//! native functions are.
+
+use std::collections::HashMap;
+
+use crate::arch::x86::{RelocKind, SymbolRef};
+use crate::arch::EncodeError;
+use crate::mmap::{MmapBuf, MmapError};
+use crate::X86Asm;
+
+/// Functions and stubs start at multiples of 16 bytes, or of the alignment of the constant
+/// pool of the function if larger. The gaps are filled with `int3`.
+const FUNCTION_ALIGN: usize = 16;
+const PADDING: u8 = 0xcc;
+
+/// `jmp qword ptr [rip]`: jump to the address right after the instruction.
+const STUB_JMP: [u8; 6] = [0xff, 0x25, 0x00, 0x00, 0x00, 0x00];
+const STUB_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// A function or a native function with this name was already added.
+    DuplicateSymbol(String),
+    /// A function references a symbol that the module doesn't define.
+    UndefinedSymbol(String),
+    /// The function references labels that were never bound.
+    UnboundLabels(String),
+    /// A 32-bit reference can't reach its symbol.
+    SymbolOutOfRange(String),
+    Encode(EncodeError),
+    Mmap(MmapError),
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol(name) => write!(f, "Symbol {} is already defined", name),
+            LinkError::UndefinedSymbol(name) => write!(f, "Undefined symbol: {}", name),
+            LinkError::UnboundLabels(name) => {
+                write!(f, "Function {} references unbound labels", name)
+            }
+            LinkError::SymbolOutOfRange(name) => write!(f, "Symbol {} is out of range", name),
+            LinkError::Encode(err) => write!(f, "Failed to encode function: {}", err),
+            LinkError::Mmap(err) => write!(f, "Failed to map the code: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl From<EncodeError> for LinkError {
+    fn from(err: EncodeError) -> Self {
+        LinkError::Encode(err)
+    }
+}
+
+impl From<MmapError> for LinkError {
+    fn from(err: MmapError) -> Self {
+        LinkError::Mmap(err)
+    }
+}
+
+/// A function of a module and where it's placed.
+struct Function {
+    name: String,
+    offset: usize,
+    code: Vec<u8>,
+    symbol_refs: Vec<SymbolRef>,
+    /// `(offset, len)` of every instruction, relative to the function.
+    insns: Vec<(usize, usize)>,
+}
+
+/// Functions that call each other and native functions by name, linked together into
+/// a single piece of code.
+#[derive(Default)]
+pub struct Module {
+    functions: Vec<Function>,
+    /// Native functions and their addresses, in the order of their stubs.
+    externs: Vec<(String, usize)>,
+    /// End of the last function.
+    code_len: usize,
+}
+
+impl Module {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a function, and place its constant pool after its code. The first function
+    /// is placed at offset 0, so it's the entry point of the code.
+    ///
+    /// # Returns
+    ///
+    /// The offset of the function in the linked code.
+    pub fn add_function(&mut self, name: &str, mut asm: X86Asm) -> Result<usize, LinkError> {
+        self.check_duplicate(name)?;
+        if asm.has_unbound_references() {
+            return Err(LinkError::UnboundLabels(name.to_string()));
+        }
+        asm.finalize()?;
+
+        let align = FUNCTION_ALIGN.max(asm.alignment());
+        let offset = self.code_len.next_multiple_of(align);
+        self.code_len = offset + asm.code().len();
+        self.functions.push(Function {
+            name: name.to_string(),
+            offset,
+            code: asm.code().to_vec(),
+            symbol_refs: asm.symbol_refs().to_vec(),
+            insns: asm
+                .instructions()
+                .iter()
+                .map(|insn| (insn.offset, insn.len))
+                .collect(),
+        });
+        Ok(offset)
+    }
+
+    /// Add a native function that follows the System V calling convention, e.g.
+    /// `builtins::pow as *const () as usize`.
+    pub fn add_extern(&mut self, name: &str, addr: usize) -> Result<(), LinkError> {
+        self.check_duplicate(name)?;
+        self.externs.push((name.to_string(), addr));
+        Ok(())
+    }
+
+    /// Reject a name that is already defined by a function or a native function.
+    fn check_duplicate(&self, name: &str) -> Result<(), LinkError> {
+        match self.symbol_offset(name) {
+            Some(_) => Err(LinkError::DuplicateSymbol(name.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn stubs_offset(&self) -> usize {
+        self.code_len.next_multiple_of(FUNCTION_ALIGN)
+    }
+
+    /// Size of the linked code in bytes.
+    pub fn size(&self) -> usize {
+        self.stubs_offset() + self.externs.len() * STUB_SIZE
+    }
+
+    /// Get the offset of a function, or of the stub of a native function, in the linked code.
+    pub fn symbol_offset(&self, name: &str) -> Option<usize> {
+        if let Some(function) = self.functions.iter().find(|f| f.name == name) {
+            return Some(function.offset);
+        }
+        let index = self
+            .externs
+            .iter()
+            .position(|(extern_name, _)| extern_name == name)?;
+        Some(self.stubs_offset() + index * STUB_SIZE)
+    }
+
+    /// The `(offset, len)` of every instruction of the linked code, sorted by offset,
+    /// e.g. for [`Executable::set_instructions`](crate::executable::Executable::set_instructions).
+    pub fn instructions(&self) -> Vec<(usize, usize)> {
+        let functions = self.functions.iter().flat_map(|function| {
+            function
+                .insns
+                .iter()
+                .map(|&(offset, len)| (function.offset + offset, len))
+        });
+        let stubs = (0..self.externs.len())
+            .map(|index| (self.stubs_offset() + index * STUB_SIZE, STUB_JMP.len()));
+        functions.chain(stubs).collect()
+    }
+
+    /// Get the address a symbol reference resolves to, for code loaded at `base`. Absolute
+    /// references to native functions get their address, others go through their stub.
+    fn resolve_target(&self, base: usize, symbol_ref: &SymbolRef) -> Result<usize, LinkError> {
+        let symbol = &symbol_ref.symbol;
+        if symbol_ref.kind == RelocKind::Abs64 {
+            if let Some((_, addr)) = self.externs.iter().find(|(name, _)| name == symbol) {
+                return Ok(*addr);
+            }
+        }
+        match self.symbol_offset(symbol) {
+            Some(offset) => Ok(base + offset),
+            None => Err(LinkError::UndefinedSymbol(symbol.clone())),
+        }
+    }
+
+    /// Lay out the functions and the stubs as loaded at `base`, and resolve every symbol
+    /// reference. Only absolute references depend on `base`.
+    pub fn link(&self, base: usize) -> Result<Vec<u8>, LinkError> {
+        let mut code = vec![PADDING; self.size()];
+
+        for function in &self.functions {
+            let start = function.offset;
+            code[start..start + function.code.len()].copy_from_slice(&function.code);
+
+            for symbol_ref in &function.symbol_refs {
+                let target = self.resolve_target(base, symbol_ref)?;
+                let bytes = symbol_ref
+                    .resolve(base + start, target)
+                    .ok_or_else(|| LinkError::SymbolOutOfRange(symbol_ref.symbol.clone()))?;
+                let field = start + symbol_ref.offset;
+                code[field..field + bytes.len()].copy_from_slice(&bytes);
+            }
+        }
+
+        for (index, (_, addr)) in self.externs.iter().enumerate() {
+            let start = self.stubs_offset() + index * STUB_SIZE;
+            code[start..start + STUB_JMP.len()].copy_from_slice(&STUB_JMP);
+            code[start + STUB_JMP.len()..start + STUB_JMP.len() + 8]
+                .copy_from_slice(&(*addr as u64).to_le_bytes());
+        }
+        Ok(code)
+    }
+
+    /// Link the module into a new executable mapping.
+    pub fn load(&self) -> Result<LoadedModule, LinkError> {
+        let mut mmap = MmapBuf::new(self.size())?;
+        let code = self.link(mmap.ptr() as usize)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(code.as_ptr(), mmap.ptr() as *mut u8, code.len());
+        }
+        mmap.protect_rx()?;
+
+        let symbols = self
+            .functions
+            .iter()
+            .map(|function| (function.name.clone(), function.offset))
+            .collect();
+        Ok(LoadedModule {
+            code: mmap,
+            symbols,
+        })
+    }
+}
+
+/// A module loaded into executable memory by [`Module::load`].
+pub struct LoadedModule {
+    code: MmapBuf,
+    /// Offsets of the functions.
+    symbols: HashMap<String, usize>,
+}
+
+impl LoadedModule {
+    /// Address of the linked code.
+    pub fn ptr(&self) -> *const u8 {
+        self.code.ptr()
+    }
+
+    /// Get the address of a function of the module, to be transmuted into a function
+    /// pointer of the matching signature.
+    pub fn symbol(&self, name: &str) -> Option<*const u8> {
+        let offset = *self.symbols.get(name)?;
+        Some(unsafe { self.code.ptr().add(offset) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x86::Reg64::*;
+    use crate::arch::x86::{Mem, Scale, Size};
+
+    extern "C" fn add_one(x: i64) -> i64 {
+        x + 1
+    }
+
+    /// `twice(x) = 2 * x` and `main(x) = add_one(twice(x))`, as a tail call.
+    fn example() -> Module {
+        let mut twice = X86Asm::new();
+        twice
+            .lea(Rax, Mem::implied(Rdi, 0).with_index(Rdi, Scale::X1))
+            .unwrap();
+        twice.ret().unwrap();
+
+        let mut main = X86Asm::new();
+        main.call_symbol("twice").unwrap();
+        main.mov(Rdi, Rax).unwrap();
+        main.jmp_symbol("add_one").unwrap();
+
+        let mut module = Module::new();
+        assert_eq!(module.add_function("main", main).unwrap(), 0);
+        assert_eq!(module.add_function("twice", twice).unwrap(), 16);
+        module
+            .add_extern("add_one", add_one as *const () as usize)
+            .unwrap();
+        module
+    }
+
+    #[test]
+    fn test_module_link() {
+        let module = example();
+        assert_eq!(module.symbol_offset("twice"), Some(16));
+        assert_eq!(module.symbol_offset("add_one"), Some(32));
+        assert_eq!(module.symbol_offset("pow"), None);
+        assert_eq!(module.size(), 48);
+        assert_eq!(
+            module.instructions(),
+            [(0, 5), (5, 3), (8, 5), (16, 4), (20, 1), (32, 6)]
+        );
+
+        let mut expected = vec![
+            0xe8, 0x0b, 0x00, 0x00, 0x00, // call twice
+            0x48, 0x89, 0xc7, // mov rdi, rax
+            0xe9, 0x13, 0x00, 0x00, 0x00, // jmp add_one
+            0xcc, 0xcc, 0xcc, // padding
+            0x48, 0x8d, 0x04, 0x3f, // twice: lea rax, [rdi + rdi]
+            0xc3, // ret
+            0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, // padding
+            0xff, 0x25, 0x00, 0x00, 0x00, 0x00, // add_one: jmp qword ptr [rip]
+        ];
+        expected.extend_from_slice(&(add_one as *const () as usize as u64).to_le_bytes());
+        expected.extend_from_slice(&[0xcc, 0xcc]);
+        assert_eq!(module.link(0x1000).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_module_load() {
+        let loaded = example().load().unwrap();
+        assert_eq!(loaded.symbol("main"), Some(loaded.ptr()));
+        assert!(loaded.symbol("add_one").is_none());
+
+        let main: extern "C" fn(i64) -> i64 =
+            unsafe { std::mem::transmute(loaded.symbol("main").unwrap()) };
+        let twice: extern "C" fn(i64) -> i64 =
+            unsafe { std::mem::transmute(loaded.symbol("twice").unwrap()) };
+        assert_eq!(twice(21), 42);
+        assert_eq!(main(20), 41);
+        assert_eq!(main(-3), -5);
+    }
+
+    #[test]
+    fn test_module_symbol_kinds() {
+        let mut module = example();
+
+        // The address of `twice`, absolute or relative to the code.
+        let mut abs = X86Asm::new();
+        abs.mov_symbol(Rax, "twice").unwrap();
+        abs.ret().unwrap();
+        let mut rel = X86Asm::new();
+        rel.lea_symbol(Rax, "twice").unwrap();
+        rel.ret().unwrap();
+        // Absolute references to native functions bypass their stub.
+        let mut native = X86Asm::new();
+        native.mov_symbol(Rax, "add_one").unwrap();
+        native.jmp(Rax).unwrap();
+
+        assert_eq!(module.add_function("abs", abs).unwrap(), 32);
+        assert_eq!(module.add_function("rel", rel).unwrap(), 48);
+        assert_eq!(module.add_function("native", native).unwrap(), 64);
+        assert_eq!(module.symbol_offset("add_one"), Some(80));
+
+        let base = 0x7fff_0000_0000;
+        let code = module.link(base).unwrap();
+        assert_eq!(code[34..42], (base as u64 + 16).to_le_bytes());
+        assert_eq!(code[51..55], (16i32 - 55).to_le_bytes());
+        assert_eq!(
+            code[66..74],
+            (add_one as *const () as usize as u64).to_le_bytes()
+        );
+
+        let loaded = module.load().unwrap();
+        let function = |name| loaded.symbol(name).unwrap();
+        let abs: extern "C" fn() -> *const u8 = unsafe { std::mem::transmute(function("abs")) };
+        let rel: extern "C" fn() -> *const u8 = unsafe { std::mem::transmute(function("rel")) };
+        let native: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(function("native")) };
+        assert_eq!(abs(), function("twice"));
+        assert_eq!(rel(), function("twice"));
+        assert_eq!(native(41), 42);
+    }
+
+    #[test]
+    fn test_module_constant_alignment() {
+        let mut first = X86Asm::new();
+        first.ret().unwrap();
+
+        // The 32-byte constant is placed at offset 32 of the function, which must then
+        // start at a multiple of 32 as well.
+        let bytes: Vec<u8> = (1..=32).collect();
+        let mut second = X86Asm::new();
+        let constant = second.add_constant(&bytes).unwrap();
+        second
+            .mov(Rax, Mem::constant(Size::Qword, constant))
+            .unwrap();
+        second.ret().unwrap();
+        assert_eq!(second.alignment(), 32);
+
+        let mut module = Module::new();
+        assert_eq!(module.add_function("first", first).unwrap(), 0);
+        assert_eq!(module.add_function("second", second).unwrap(), 32);
+
+        let code = module.link(0).unwrap();
+        assert_eq!(code[64..96], bytes);
+
+        let loaded = module.load().unwrap();
+        let second: extern "C" fn() -> u64 =
+            unsafe { std::mem::transmute(loaded.symbol("second").unwrap()) };
+        assert_eq!(second(), u64::from_le_bytes(bytes[..8].try_into().unwrap()));
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let mut module = example();
+        assert!(matches!(
+            module.add_function("twice", X86Asm::new()),
+            Err(LinkError::DuplicateSymbol(name)) if name == "twice"
+        ));
+        assert!(matches!(
+            module.add_extern("main", 0),
+            Err(LinkError::DuplicateSymbol(name)) if name == "main"
+        ));
+
+        let mut unbound = X86Asm::new();
+        let label = unbound.new_label();
+        unbound.jmp(label).unwrap();
+        assert!(matches!(
+            module.add_function("unbound", unbound),
+            Err(LinkError::UnboundLabels(name)) if name == "unbound"
+        ));
+
+        let mut caller = X86Asm::new();
+        caller.call_symbol("pow").unwrap();
+        module.add_function("caller", caller).unwrap();
+        assert!(matches!(
+            module.link(0),
+            Err(LinkError::UndefinedSymbol(name)) if name == "pow"
+        ));
+    }
+}
@@ -1,8 +1,32 @@
+use crate::arch::EncodeError;
+
+/// Handle of a literal in the constant pool of a [`Writer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Constant(usize);
+
+/// A literal of the constant pool and its offset, once it's placed after the code.
+#[derive(Debug, Clone)]
+struct PoolEntry {
+    bytes: Vec<u8>,
+    offset: Option<usize>,
+}
+
+/// A 32-bit field that must hold the distance from `origin` to a pooled constant.
+#[derive(Debug, Clone, Copy)]
+struct ConstantRef {
+    constant: Constant,
+    offset: usize,
+    origin: usize,
+}
 
 /// Universal code writer.
 #[derive(Default)]
 pub struct Writer {
     buffer: Vec<u8>,
+    /// Literals that are placed after the code by [`Writer::finalize`].
+    constants: Vec<PoolEntry>,
+    /// References to constants that are not placed yet.
+    constant_refs: Vec<ConstantRef>,
 }
 
 macro_rules! define_emits {
@@ -25,6 +49,8 @@ impl Writer {
     pub fn new() -> Self {
         Self {
             buffer: Vec::with_capacity(4096),
+            constants: Vec::new(),
+            constant_refs: Vec::new(),
         }
     }
 
@@ -54,6 +80,127 @@ impl Writer {
             .splice(offset..offset + bytes.len(), bytes.iter().cloned());
     }
 
+    /// Remove the bytes emitted from offset `len` on, e.g. the partial encoding of an
+    /// instruction that failed.
+    pub fn truncate(&mut self, len: usize) {
+        self.buffer.truncate(len);
+    }
+
+    /// Get the number of bytes needed to pad the buffer to a multiple of `align`, which
+    /// must be a power of two.
+    pub fn padding(&self, align: usize) -> Result<usize, EncodeError> {
+        if !align.is_power_of_two() {
+            return Err(EncodeError::InvalidAlignment(align));
+        }
+        Ok(self.buffer.len().next_multiple_of(align) - self.buffer.len())
+    }
+
+    /// Pad the buffer to a multiple of `align`, which must be a power of two, with the
+    /// longest fillers that fit. `fillers[n - 1]` is the filler of `n` bytes, e.g. a NOP
+    /// instruction of that length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if padding is needed and `fillers` is empty.
+    pub fn align(&mut self, align: usize, fillers: &[&[u8]]) -> Result<(), EncodeError> {
+        let mut padding = self.padding(align)?;
+        while padding > 0 {
+            let filler = fillers[padding.min(fillers.len()) - 1];
+            self.emit(filler);
+            padding -= filler.len();
+        }
+        Ok(())
+    }
+
+    /// Add an 8, 16 or 32-byte literal to the constant pool. Identical literals are only
+    /// stored once.
+    pub fn add_constant(&mut self, bytes: &[u8]) -> Result<Constant, EncodeError> {
+        if !matches!(bytes.len(), 8 | 16 | 32) {
+            return Err(EncodeError::InvalidConstant(bytes.len()));
+        }
+
+        match self.constants.iter().position(|entry| entry.bytes == bytes) {
+            Some(index) => Ok(Constant(index)),
+            None => {
+                self.constants.push(PoolEntry {
+                    bytes: bytes.to_vec(),
+                    offset: None,
+                });
+                Ok(Constant(self.constants.len() - 1))
+            }
+        }
+    }
+
+    /// Record a 32-bit field at `offset` that must hold the distance from `origin`
+    /// to the constant. The field is patched by [`Writer::finalize`], or right away
+    /// if the constant is already placed.
+    pub fn reference_constant(
+        &mut self,
+        constant: Constant,
+        offset: usize,
+        origin: usize,
+    ) -> Result<(), EncodeError> {
+        let constant_ref = ConstantRef {
+            constant,
+            offset,
+            origin,
+        };
+
+        match self.constants[constant.0].offset {
+            Some(target) => self.patch_constant_ref(constant_ref, target)?,
+            None => self.constant_refs.push(constant_ref),
+        }
+        Ok(())
+    }
+
+    /// Alignment the buffer must be loaded at for its constants to be aligned to their
+    /// size: the size of the largest constant, or 1 without constants.
+    pub fn alignment(&self) -> usize {
+        self.constants
+            .iter()
+            .map(|entry| entry.bytes.len())
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// Place the constants added since the last call after the code, each one aligned to
+    /// its size relative to the start of the buffer, see [`Writer::alignment`], and patch
+    /// every reference to them.
+    pub fn finalize(&mut self) -> Result<(), EncodeError> {
+        // Larger constants go first, so that no padding is needed between them.
+        let mut pending: Vec<usize> = (0..self.constants.len())
+            .filter(|&index| self.constants[index].offset.is_none())
+            .collect();
+        pending.sort_by_key(|&index| std::cmp::Reverse(self.constants[index].bytes.len()));
+
+        for index in pending {
+            let bytes = std::mem::take(&mut self.constants[index].bytes);
+            let padding = self.buffer.len().next_multiple_of(bytes.len()) - self.buffer.len();
+            self.buffer.resize(self.buffer.len() + padding, 0);
+
+            self.constants[index].offset = Some(self.buffer.len());
+            self.emit(&bytes);
+            self.constants[index].bytes = bytes;
+        }
+
+        for constant_ref in std::mem::take(&mut self.constant_refs) {
+            let target = self.constants[constant_ref.constant.0].offset.unwrap();
+            self.patch_constant_ref(constant_ref, target)?;
+        }
+        Ok(())
+    }
+
+    fn patch_constant_ref(
+        &mut self,
+        constant_ref: ConstantRef,
+        target: usize,
+    ) -> Result<(), EncodeError> {
+        let rel = target as i64 - constant_ref.origin as i64;
+        let rel = i32::try_from(rel).map_err(|_| EncodeError::ConstantOutOfRange)?;
+        self.emit32_at(constant_ref.offset, rel as u32);
+        Ok(())
+    }
+
     define_emits!(
         /// Emit a byte at the end of the buffer.
         emit8, 